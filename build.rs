@@ -1,4 +1,5 @@
-//! Build script to generate format parser and documentation.
+//! Build script to generate format parser, documentation, and completion
+//! script.
 
 use std::fs::File;
 use std::io::BufWriter;
@@ -10,10 +11,14 @@ const PARSER_CODE: &str = "format-parser.rs";
 
 const LABELS_CODE: &str = "labels-parser.rs";
 
+const CHECKER_CODE: &str = "format-checker.rs";
+
 const DOC_PLAIN_TEXT: &str = "doc.txt";
 
 const DOC_MARKDOWN: &str = "FORMAT.md";
 
+const COMPLETION_SCRIPT: &str = "completion/timehistory.bash";
+
 fn main() {
     let out_dir = PathBuf::from(std::env::var("OUT_DIR").unwrap());
 
@@ -35,6 +40,11 @@ fn main() {
     generator::parser::generate_parser(BufWriter::new(parser), &specs, false)
         .expect("Failed to generate parser code for labels.");
 
+    // Checker for unknown specifiers.
+    let checker = File::create(out_dir.join(CHECKER_CODE)).unwrap();
+    generator::parser::generate_checker(BufWriter::new(checker), &specs)
+        .expect("Failed to generate checker code.");
+
     // Plain text documentation.
     let doc_txt = File::create(out_dir.join(DOC_PLAIN_TEXT)).unwrap();
     generator::docs::generate_plain_text(BufWriter::new(doc_txt), &doc_items)
@@ -44,4 +54,9 @@ fn main() {
     let doc_md = File::create(DOC_MARKDOWN).unwrap();
     generator::docs::generate_markdown(BufWriter::new(doc_md), &doc_items)
         .expect("Failed to generate markdown documentation.");
+
+    // Bash completion.
+    let completion = File::create(COMPLETION_SCRIPT).unwrap();
+    generator::completion::generate_completion(BufWriter::new(completion), &specs)
+        .expect("Failed to generate completion script.");
 }
@@ -0,0 +1,197 @@
+# Bash completion for the timehistory builtin.
+#
+# This file is generated by the build script, from the specifiers in the
+# src/format/format.spec file. Do not edit it manually.
+
+_timehistory_specifiers=(
+  '%%'
+  '%(argc)'
+  '%(args)'
+  '%(blocks_rate)'
+  '%(core_dumped)'
+  '%(cores)'
+  '%(cpu)'
+  '%(cpu_time)'
+  '%(cpu_time_us)'
+  '%(csw_rate)'
+  '%(depth)'
+  '%(faults_rate)'
+  '%(filename)'
+  '%(histnum)'
+  '%(inblock)'
+  '%(job)'
+  '%(majflt)'
+  '%(maxrss)'
+  '%(members)'
+  '%(minflt)'
+  '%(nivcsw)'
+  '%(nvcsw)'
+  '%(oublock)'
+  '%(pgid)'
+  '%(pid)'
+  '%(shell_cmd)'
+  '%(shell_status)'
+  '%(signal_name)'
+  '%(state)'
+  '%(status)'
+  '%(stopped_time)'
+  '%(stops)'
+  '%(sys_time)'
+  '%(sys_time_us)'
+  '%(tags)'
+  '%(time:'
+  '%(user_time)'
+  '%(user_time_us)'
+  '%C'
+  '%E'
+  '%F'
+  '%I'
+  '%M'
+  '%N'
+  '%O'
+  '%P'
+  '%R'
+  '%S'
+  '%Tn'
+  '%Tt'
+  '%Tx'
+  '%U'
+  '%c'
+  '%e'
+  '%n'
+  '%u'
+  '%w'
+  '%x'
+)
+
+# Complete entry numbers. If there are multiple candidates, they are shown
+# with the command of every entry.
+#
+# Numbers prefixed with a plus symbol are offsets from the end of the list.
+# If the second argument is "ranges", a number followed by a hyphen (like
+# '3-') is completed with the last number of a range.
+_timehistory_numbers() {
+  local cur="$1"
+  local ranges="${2:-}"
+  local prefix=""
+  local first=0
+  local idx line
+  local -a lines=() entries=()
+
+  if [[ $ranges == ranges && $cur =~ ^([0-9]+)-(.*)$ ]]
+  then
+    first="${BASH_REMATCH[1]}"
+    prefix="$first-"
+    cur="${BASH_REMATCH[2]}"
+  fi
+
+  mapfile -t lines < <(timehistory -f '%n  %C' 2> /dev/null)
+
+  for (( idx = 0; idx < ${#lines[@]}; idx++ ))
+  do
+    if [[ $cur == +* ]]
+    then
+      # Offsets start with the last entry ('+1').
+      line="${lines[${#lines[@]} - idx - 1]}"
+      line="+$(( idx + 1 ))  ${line#*  }"
+    else
+      line="${lines[idx]}"
+    fi
+
+    if [[ ${line%% *} == "$cur"* ]] && (( ${line%% *} >= first ))
+    then
+      entries+=("$prefix$line")
+    fi
+  done
+
+  if [ ${#entries[@]} -eq 1 ]
+  then
+    COMPREPLY=("${entries[0]%% *}")
+  else
+    COMPREPLY=("${entries[@]}")
+  fi
+}
+
+# Complete the names of the formats in the configuration file.
+_timehistory_named_formats() {
+  local cur="$1"
+  local name source
+
+  while read -r name source
+  do
+    if [[ $name == @* && $name == "$cur"* ]]
+    then
+      COMPREPLY+=("$name")
+    fi
+  done < <(timehistory -s 2> /dev/null)
+}
+
+# Complete the specifier at the end of a format string.
+_timehistory_format() {
+  local cur="$1"
+  local prefix partial spec
+
+  if [[ $cur == @* ]]
+  then
+    _timehistory_named_formats "$cur"
+    return
+  fi
+
+  if [[ $cur != *%* ]]
+  then
+    mapfile -t COMPREPLY < <(compgen -W 'help' -- "$cur")
+    return
+  fi
+
+  prefix="${cur%\%*}"
+  partial="%${cur##*%}"
+
+  for spec in "${_timehistory_specifiers[@]}"
+  do
+    if [[ $spec == "$partial"* ]]
+    then
+      COMPREPLY+=("$prefix$spec")
+    fi
+  done
+
+  compopt -o nospace 2> /dev/null || :
+}
+
+_timehistory() {
+  local cur="$2"
+  local prev="$3"
+
+  COMPREPLY=()
+
+  case "$prev" in
+    -f)
+      _timehistory_format "$cur"
+      return
+      ;;
+
+    -d)
+      _timehistory_numbers "$cur" ranges
+      return
+      ;;
+
+    -A)
+      mapfile -t COMPREPLY < <(compgen -A arrayvar -- "$cur")
+      return
+      ;;
+
+    -t | -T)
+      # Tags are free-form text.
+      return
+      ;;
+  esac
+
+  if [[ $cur == -* ]]
+  then
+    mapfile -t COMPREPLY < <(compgen -W '-f -v -j -A -g -T -p -s -R -d -t' -- "$cur")
+    return
+  fi
+
+  _timehistory_numbers "$cur"
+}
+
+complete -F _timehistory timehistory
@@ -0,0 +1,37 @@
+//! Generate the bash completion script.
+
+use std::io::{self, Write};
+
+/// Template for the completion script.
+const TEMPLATE: &str = include_str!("completion.bash");
+
+/// Generate a bash completion script for the builtin.
+///
+/// The mark `%SPECS%` in the template is replaced with the names of the
+/// format specifiers, one per line.
+pub fn generate_completion(mut output: impl Write, specs: &[super::FormatSpec]) -> io::Result<()> {
+    let mut names: Vec<&str> = specs
+        .iter()
+        .flat_map(|spec| &spec.sequences)
+        .map(String::as_str)
+        .filter(|seq| seq.starts_with('%'))
+        .collect();
+
+    names.sort_unstable();
+    names.dedup();
+
+    let mut parts = TEMPLATE.split("%SPECS%\n");
+
+    // Before %SPECS%.
+    output.write_all(parts.next().unwrap().as_bytes())?;
+
+    for name in names {
+        assert!(!name.contains('\''), "Invalid specifier: {:?}", name);
+        writeln!(&mut output, "  '{}'", name)?;
+    }
+
+    // After %SPECS%.
+    output.write_all(parts.next().unwrap().as_bytes())?;
+
+    Ok(())
+}
@@ -16,7 +16,7 @@ pub struct DocumentationItem<'a> {
 }
 
 /// Convert a `FormatSpec` items to `DocumentationItem`.
-pub fn collect_items(specs: &[super::FormatSpec]) -> Vec<DocumentationItem> {
+pub fn collect_items(specs: &[super::FormatSpec]) -> Vec<DocumentationItem<'_>> {
     let mut items: Vec<_> = specs
         .iter()
         .map(|format_spec| {
@@ -1,5 +1,7 @@
-//! Parser `format.spec` file and generate a parser and the documentation.
+//! Parser `format.spec` file and generate a parser, the documentation, and
+//! the completion script.
 
+pub mod completion;
 pub mod docs;
 pub mod parser;
 pub mod source;
@@ -207,8 +207,107 @@ pub fn generate_parser(
     write!(output, "{}", code)
 }
 
+/// Generate the code to find unknown specifiers.
+///
+/// The generated code is the body of a function that returns an
+/// `Option<usize>`, with the position in `format` of the first `%` specifier
+/// that is not in `specs`.
+/// Unknown escape sequences (like `\q`) are copied as literal text by the
+/// parser, so they are accepted.
+pub fn generate_checker(mut output: impl Write, specs: &[FormatSpec]) -> io::Result<()> {
+    let states = state_machine(specs);
+
+    let match_branches = states.iter().map(|state| {
+        let state_number = state.number;
+
+        let chr_states = state.transitions.iter().map(|(chr, node)| {
+            let expr = match node {
+                // If the specifier takes an argument (like `%(time:…)`), it
+                // must be terminated.
+                Transition::Code(Code {
+                    header_label_until: Some(until),
+                    ..
+                }) => quote! {
+                    if !input.any(|(_, c)| *c == #until) {
+                        return Some(spec_start);
+                    }
+
+                    state = 0;
+                },
+
+                Transition::Code(_) => quote! { state = 0; },
+
+                Transition::State(state) => quote! { state = #state; },
+            };
+
+            quote! {
+                #chr => { #expr }
+            }
+        });
+
+        let unknown_char = if state.number == 0 {
+            quote! {}
+        } else {
+            quote! { unknown_spec!(); }
+        };
+
+        quote! {
+            #state_number => {
+                match chr {
+                    #(#chr_states)*
+
+                    _ => { #unknown_char }
+                }
+            }
+        }
+    });
+
+    let code = quote! {{
+        let format = format.as_bytes();
+        let mut input = format.iter().enumerate();
+
+        let mut state = 0;
+        let mut spec_start = 0;
+
+        while let Some((chr_index, chr)) = input.next() {
+            'current_chr: loop {
+                if state == 0 {
+                    spec_start = chr_index;
+                }
+
+                macro_rules! unknown_spec {
+                    () => {{
+                        if format[spec_start] == b'%' {
+                            return Some(spec_start);
+                        }
+
+                        state = 0;
+                        continue 'current_chr;
+                    }}
+                }
+
+                match state {
+                    #(#match_branches)*
+
+                    _ => { unknown_spec!(); }
+                }
+
+                break 'current_chr;
+            }
+        }
+
+        if state != 0 && format[spec_start] == b'%' {
+            return Some(spec_start);
+        }
+
+        None
+    }};
+
+    write!(output, "{}", code)
+}
+
 /// Generates a parser from a list of specifiers.
-fn state_machine(specs: &[FormatSpec]) -> Vec<State> {
+fn state_machine(specs: &[FormatSpec]) -> Vec<State<'_>> {
     let mut states_map: BTreeMap<u8, TreeNode> = BTreeMap::new();
     let mut state_counter = 0;
 
@@ -31,6 +31,7 @@ DEST=$(mktemp -d)
 
 DEST_BIN="$DEST/debian/$PACKAGE_NAME/usr/lib/bash"
 DEST_DOC="$DEST/debian/$PACKAGE_NAME/usr/share/doc/$PACKAGE_NAME"
+DEST_COMPLETION="$DEST/debian/$PACKAGE_NAME/usr/share/bash-completion/completions"
 
 DEST_DEB="$SOURCE/target/packages"
 
@@ -40,6 +41,9 @@ find target/release -maxdepth 1 -name '*.so' -exec cp -a {} "$DEST_BIN" \;
 mkdir -p "$DEST_DOC"
 cp -a ./*.md "$DEST_DOC"
 
+mkdir -p "$DEST_COMPLETION"
+cp -a completion/timehistory.bash "$DEST_COMPLETION/timehistory"
+
 mkdir -p "$DEST_DEB"
 
 
@@ -13,6 +13,8 @@ cargo build --release
 
 %install
 install -t %{buildroot}/usr/lib/bash -s -D -o root -g root target/release/libtimehistory_bash.so
+install -m 644 -D -o root -g root completion/timehistory.bash %{buildroot}/usr/share/bash-completion/completions/timehistory
 
 %files
 /usr/lib/bash/libtimehistory_bash.so
+/usr/share/bash-completion/completions/timehistory
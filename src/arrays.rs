@@ -0,0 +1,64 @@
+//! Write the fields of a history entry in a shell associative array.
+
+use bash_builtins::variables::{self, VariableError};
+
+use crate::history::Entry;
+
+/// Fields in the array, and the format string to render their values.
+const FIELDS: &[(&str, &str)] = &[
+    ("number", "%n"),
+    ("pid", "%(pid)"),
+    ("pgid", "%(pgid)"),
+    ("job", "%(job)"),
+    ("histnum", "%(histnum)"),
+    ("depth", "%(depth)"),
+    ("filename", "%N"),
+    ("args", "%C"),
+    ("argc", "%(argc)"),
+    ("shell_cmd", "%(shell_cmd)"),
+    ("start_time", "%(time:%s)"),
+    ("state", "%(state)"),
+    ("elapsed", "%e"),
+    ("elapsed_us", "%u"),
+    ("status", "%(shell_status)"),
+    ("signal", "%Tn"),
+    ("signal_name", "%(signal_name)"),
+    ("user_time", "%U"),
+    ("sys_time", "%S"),
+    ("cpu_time", "%(cpu_time)"),
+    ("cores", "%(cores)"),
+    ("maxrss", "%M"),
+    ("majflt", "%F"),
+    ("minflt", "%R"),
+    ("inblock", "%I"),
+    ("oublock", "%O"),
+    ("nvcsw", "%w"),
+    ("nivcsw", "%c"),
+    ("stops", "%(stops)"),
+    ("stopped_time", "%(stopped_time)"),
+    ("tags", "%(tags)"),
+];
+
+/// Write the fields of `entry` in the associative array `name`.
+///
+/// If the variable does not exist, it is created in the global scope. Use
+/// `local -A name` in a function to get a local array.
+///
+/// Every field is assigned, even if it is empty, so no value from a
+/// previous entry is kept. Other keys in the array are not modified.
+pub fn set_entry_fields(name: &str, entry: &Entry) -> Result<(), VariableError> {
+    let mut value = Vec::new();
+    for (key, format) in FIELDS {
+        value.clear();
+        if crate::format::render(entry, format, &mut value).is_err() {
+            value.clear();
+        }
+
+        // Values can't contain nul bytes.
+        value.retain(|c| *c != 0);
+
+        variables::assoc_set(name, key, &value)?;
+    }
+
+    Ok(())
+}
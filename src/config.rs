@@ -0,0 +1,399 @@
+//! Configuration file.
+//!
+//! The file is read when the builtin is loaded, from
+//! `$XDG_CONFIG_HOME/timehistory/config.toml`, or
+//! `~/.config/timehistory/config.toml` if `XDG_CONFIG_HOME` is not set.
+//!
+//! It is a TOML document with the settings as top-level keys, and a
+//! `[formats]` table to define named formats, used as `-f @name`.
+//!
+//! The values in the file are applied before the shell variables, so any
+//! variable overrides them. The source of every value is tracked to be
+//! shown in `timehistory -s`.
+
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::{CStr, CString};
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use bash_builtins::variables::{self, DynamicVariable};
+use bash_builtins::warning;
+use once_cell::sync::{Lazy, OnceCell};
+use serde::de::{self, Deserialize, Deserializer};
+
+/// Global configuration, loaded in `TimeHistory::new`.
+static CONFIG: OnceCell<Config> = OnceCell::new();
+
+/// Sources of the values assigned to the dynamic variables.
+static SOURCES: Lazy<Mutex<HashMap<&'static str, Source>>> = Lazy::new(Default::default);
+
+/// Origin of a setting.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Source {
+    Default,
+    Config,
+    Shell,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str(match self {
+            Source::Default => "default",
+            Source::Config => "config",
+            Source::Shell => "shell",
+        })
+    }
+}
+
+/// Contents of the configuration file.
+#[derive(serde::Deserialize)]
+struct File {
+    format: Option<String>,
+    prompt_format: Option<String>,
+    limit: Option<u64>,
+    cmdline_limit: Option<u64>,
+    ignore: Option<Patterns>,
+    only: Option<Patterns>,
+    #[serde(default, deserialize_with = "seconds")]
+    min_duration: Option<f64>,
+    redact: Option<Patterns>,
+    tag: Option<String>,
+    nested: Option<bool>,
+
+    /// Named formats, in the `[formats]` table.
+    #[serde(default)]
+    formats: BTreeMap<String, String>,
+
+    /// Unknown keys, reported as warnings.
+    #[serde(flatten)]
+    unknown: BTreeMap<String, toml::Value>,
+}
+
+/// A single pattern, or a list of them.
+#[derive(serde::Deserialize)]
+#[serde(untagged, expecting = "expected a pattern or an array of patterns")]
+enum Patterns {
+    One(String),
+    List(Vec<String>),
+}
+
+impl Patterns {
+    /// Patterns joined with `:`, like in the shell variables.
+    fn join(self) -> String {
+        match self {
+            Patterns::One(pattern) => pattern,
+            Patterns::List(patterns) => patterns.join(":"),
+        }
+    }
+}
+
+/// Deserialize a non-negative number of seconds.
+fn seconds<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<f64>, D::Error> {
+    let secs = f64::deserialize(deserializer)?;
+    if crate::filters::valid_seconds(secs) {
+        Ok(Some(secs))
+    } else {
+        Err(de::Error::invalid_value(
+            de::Unexpected::Float(secs),
+            &"a non-negative number of seconds, less than 2^64",
+        ))
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Config {
+    /// Path of the file, if it was found.
+    pub path: Option<PathBuf>,
+
+    /// Values for the shell variables.
+    settings: Vec<(&'static str, String)>,
+
+    /// Named formats.
+    pub formats: BTreeMap<String, String>,
+}
+
+impl Config {
+    /// Parse the contents of a configuration file.
+    ///
+    /// If the file is not valid, no setting is applied. Unknown keys are
+    /// ignored, and returned as warnings.
+    fn parse(source: &str) -> (Config, Vec<String>) {
+        let file: File = match toml::from_str(source) {
+            Ok(file) => file,
+            Err(e) => return (Config::default(), vec![e.to_string()]),
+        };
+
+        let warnings = file
+            .unknown
+            .keys()
+            .map(|key| format!("unknown key '{}'", key))
+            .collect();
+
+        let settings = vec![
+            ("TIMEHISTORY_FORMAT", file.format),
+            ("TIMEHISTORY_PROMPT_FORMAT", file.prompt_format),
+            ("TIMEHISTORY_LIMIT", file.limit.map(|n| n.to_string())),
+            (
+                "TIMEHISTORY_CMDLINE_LIMIT",
+                file.cmdline_limit.map(|n| n.to_string()),
+            ),
+            ("TIMEHISTORY_IGNORE", file.ignore.map(Patterns::join)),
+            ("TIMEHISTORY_ONLY", file.only.map(Patterns::join)),
+            (
+                "TIMEHISTORY_MIN_DURATION",
+                file.min_duration.map(|n| n.to_string()),
+            ),
+            ("TIMEHISTORY_REDACT", file.redact.map(Patterns::join)),
+            ("TIMEHISTORY_TAG", file.tag),
+            (
+                "TIMEHISTORY_NESTED",
+                file.nested.map(|b| (if b { "1" } else { "0" }).to_owned()),
+            ),
+        ];
+
+        let config = Config {
+            path: None,
+            settings: settings
+                .into_iter()
+                .filter_map(|(var, value)| Some((var, value?)))
+                .collect(),
+            formats: file.formats,
+        };
+
+        (config, warnings)
+    }
+
+    /// Value for a shell variable, if it is defined in the file.
+    pub fn value(&self, var: &str) -> Option<&str> {
+        self.settings
+            .iter()
+            .find(|(v, _)| *v == var)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Load the configuration file, and apply its values to the variables
+/// bound with [`Tracked`].
+///
+/// bash resets the value of a variable when a dynamic variable is bound, so
+/// `shell_values` contains the values defined before loading the builtin.
+/// They are applied after the file, since shell variables have priority.
+pub fn load(shell_values: Vec<(&'static str, CString)>) {
+    let config = CONFIG.get_or_init(read);
+
+    for (var, value) in &config.settings {
+        if is_tracked(var) {
+            // Rejected values are not marked by `Tracked`.
+            let _ = variables::set(var, value);
+            if source(var) == Source::Shell {
+                set_source(var, Source::Config);
+            }
+        }
+    }
+
+    for (var, value) in shell_values {
+        let _ = variables::set(var, value.as_bytes());
+    }
+}
+
+/// Read the configuration file, if it exists.
+fn read() -> Config {
+    let path = match config_path() {
+        Some(path) if path.is_file() => path,
+        _ => return Config::default(),
+    };
+
+    let source = match std::fs::read_to_string(&path) {
+        Ok(s) => s,
+        Err(e) => {
+            warning!("{}: {}", path.display(), e);
+            return Config::default();
+        }
+    };
+
+    let (mut config, errors) = Config::parse(&source);
+    for error in errors {
+        warning!("{}: {}", path.display(), error);
+    }
+
+    config.path = Some(path);
+    config
+}
+
+/// Path of the configuration file.
+fn config_path() -> Option<PathBuf> {
+    let var = |name| {
+        variables::find_as_string(name)
+            .and_then(|v| v.into_string().ok())
+            .filter(|v| v.starts_with('/'))
+            .map(PathBuf::from)
+    };
+
+    let mut path = match var("XDG_CONFIG_HOME") {
+        Some(path) => path,
+        None => var("HOME")?.join(".config"),
+    };
+
+    path.push("timehistory");
+    path.push("config.toml");
+    Some(path)
+}
+
+/// Global configuration, if it was loaded.
+pub fn get() -> Option<&'static Config> {
+    CONFIG.get()
+}
+
+/// Value for a shell variable from the configuration file.
+pub fn value(var: &str) -> Option<&'static str> {
+    get().and_then(|c| c.value(var))
+}
+
+/// Format string for a `@name` reference.
+pub fn named_format(name: &str) -> Option<&'static str> {
+    get().and_then(|c| c.formats.get(name)).map(String::as_str)
+}
+
+/// Source of the value of a shell variable.
+pub fn source(var: &str) -> Source {
+    if let Some(source) = SOURCES.lock().ok().and_then(|s| s.get(var).copied()) {
+        return source;
+    }
+
+    if is_tracked(var) {
+        Source::Default
+    } else if variables::find_as_string(var).is_some() {
+        Source::Shell
+    } else if value(var).is_some() {
+        Source::Config
+    } else {
+        Source::Default
+    }
+}
+
+/// Update the source of a dynamic variable.
+pub fn set_source(var: &'static str, source: Source) {
+    if let Ok(mut sources) = SOURCES.lock() {
+        sources.insert(var, source);
+    }
+}
+
+/// Variables bound with [`Tracked`].
+static TRACKED: Lazy<Mutex<Vec<&'static str>>> = Lazy::new(Default::default);
+
+fn is_tracked(var: &str) -> bool {
+    TRACKED.lock().map(|t| t.contains(&var)).unwrap_or(false)
+}
+
+/// Value of a dynamic variable that can be defined in the configuration
+/// file.
+pub trait Setting {
+    /// Current value of the variable.
+    fn get(&mut self) -> Option<CString>;
+
+    /// Assign a new value. Returns `false` if the value is rejected.
+    fn set(&mut self, value: &CStr) -> bool;
+}
+
+/// Wrapper for a dynamic variable to record when its value is assigned from
+/// the shell.
+pub struct Tracked<V> {
+    name: &'static str,
+    inner: V,
+}
+
+impl<V> Tracked<V> {
+    pub fn new(name: &'static str, inner: V) -> Self {
+        if let Ok(mut tracked) = TRACKED.lock() {
+            if !tracked.contains(&name) {
+                tracked.push(name);
+            }
+        }
+
+        Tracked { name, inner }
+    }
+}
+
+impl<V: Setting> DynamicVariable for Tracked<V> {
+    fn get(&mut self) -> Option<CString> {
+        self.inner.get()
+    }
+
+    fn set(&mut self, value: &CStr) {
+        if self.inner.set(value) {
+            set_source(self.name, Source::Shell);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_settings() {
+        let (config, errors) = Config::parse(
+            r#"
+            # Shared settings.
+            limit = 5_000
+            format = '[header]%n\t%C'   # literal string
+            prompt_format = "@short"
+            ignore = ["ls", "*/git"]
+            min_duration = 0.5
+            nested = true
+            tag = "teamA"
+
+            [formats]
+            short = "%n %e"
+            "#,
+        );
+
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(config.value("TIMEHISTORY_LIMIT"), Some("5000"));
+        assert_eq!(config.value("TIMEHISTORY_FORMAT"), Some("[header]%n\\t%C"));
+        assert_eq!(config.value("TIMEHISTORY_PROMPT_FORMAT"), Some("@short"));
+        assert_eq!(config.value("TIMEHISTORY_IGNORE"), Some("ls:*/git"));
+        assert_eq!(config.value("TIMEHISTORY_MIN_DURATION"), Some("0.5"));
+        assert_eq!(config.value("TIMEHISTORY_NESTED"), Some("1"));
+        assert_eq!(config.value("TIMEHISTORY_TAG"), Some("teamA"));
+        assert_eq!(config.value("TIMEHISTORY_ONLY"), None);
+        assert_eq!(
+            config.formats.get("short").map(String::as_str),
+            Some("%n %e")
+        );
+    }
+
+    #[test]
+    fn report_unknown_keys() {
+        let (config, errors) = Config::parse(
+            r#"
+            size = 10
+            cmdline_limit = 100
+
+            [output]
+            limit = 10
+            "#,
+        );
+
+        assert_eq!(errors, ["unknown key 'output'", "unknown key 'size'"]);
+        assert_eq!(config.value("TIMEHISTORY_CMDLINE_LIMIT"), Some("100"));
+        assert_eq!(config.value("TIMEHISTORY_LIMIT"), None);
+    }
+
+    #[test]
+    fn reject_invalid_files() {
+        for source in [
+            "limit = -1\ntag = 'a'",
+            "min_duration = -0.5\ntag = 'a'",
+            "min_duration = 1e20\ntag = 'a'",
+            "format = \"%n\ntag = 'a'",
+            "ignore = [1, 2]\ntag = 'a'",
+            "tag = 'a'\n[formats]\nshort = 1",
+        ] {
+            let (config, errors) = Config::parse(source);
+            assert_eq!(errors.len(), 1, "{:?}", source);
+            assert_eq!(config.value("TIMEHISTORY_TAG"), None, "{:?}", source);
+        }
+    }
+}
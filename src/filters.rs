@@ -0,0 +1,226 @@
+//! Filters to select the commands added to the history.
+//!
+//! `TIMEHISTORY_IGNORE` and `TIMEHISTORY_ONLY` are lists of glob patterns,
+//! separated by colons (like `HISTIGNORE` in bash). The patterns are
+//! matched against the filename of the program and its first argument.
+//!
+//! `TIMEHISTORY_MIN_DURATION` is the minimum elapsed time, in seconds, to
+//! keep an entry when its process is finished.
+
+use std::ffi::{CStr, CString, OsStr, OsString};
+use std::io::{self, Write};
+use std::os::raw::{c_char, c_int};
+use std::os::unix::ffi::OsStrExt;
+use std::time::Duration;
+
+use crate::config::Setting;
+
+/// Flag for `fnmatch` to ignore case (GNU extension).
+const FNM_CASEFOLD: c_int = 1 << 4;
+
+extern "C" {
+    fn fnmatch(pattern: *const c_char, string: *const c_char, flags: c_int) -> c_int;
+}
+
+/// Filters applied by the history.
+#[derive(Default)]
+pub struct Filters {
+    /// Programs that are not added to the history.
+    pub ignore: Patterns,
+
+    /// If not empty, only programs matching these patterns are added to
+    /// the history.
+    pub only: Patterns,
+
+    /// Entries finished in less than this time are removed.
+    pub min_duration: Option<Duration>,
+}
+
+impl Filters {
+    /// Returns `true` if a program has to be added to the history.
+    pub fn accept(&self, filename: &OsStr, args: &[OsString]) -> bool {
+        let matches = |patterns: &Patterns| {
+            patterns.matches(filename) || args.first().map_or(false, |arg| patterns.matches(arg))
+        };
+
+        if !self.only.is_empty() && !matches(&self.only) {
+            return false;
+        }
+
+        !matches(&self.ignore)
+    }
+
+    /// Returns `true` if a finished entry has to be removed because of the
+    /// minimum duration.
+    pub fn too_short(&self, running_time: Option<Duration>) -> bool {
+        matches!((self.min_duration, running_time), (Some(min), Some(time)) if time < min)
+    }
+}
+
+/// List of glob patterns.
+#[derive(Default)]
+pub struct Patterns {
+    /// Value used to build the list.
+    source: CString,
+
+    patterns: Vec<CString>,
+}
+
+impl Patterns {
+    /// Parse a list of patterns separated by colons. Empty patterns are
+    /// discarded.
+    pub fn parse(source: &CStr) -> Patterns {
+        let patterns = source
+            .to_bytes()
+            .split(|c| *c == b':')
+            .filter(|p| !p.is_empty())
+            .filter_map(|p| CString::new(p).ok())
+            .collect();
+
+        Patterns {
+            source: source.to_owned(),
+            patterns,
+        }
+    }
+
+    /// Value used to build the list.
+    pub fn source(&self) -> &CStr {
+        &self.source
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    /// Returns `true` if `value` matches any of the patterns.
+    pub fn matches(&self, value: &OsStr) -> bool {
+        self.matches_with_flags(value.as_bytes(), 0)
+    }
+
+    /// Like `matches`, but ignoring case.
+    pub fn matches_ignore_case(&self, value: &[u8]) -> bool {
+        self.matches_with_flags(value, FNM_CASEFOLD)
+    }
+
+    fn matches_with_flags(&self, value: &[u8], flags: c_int) -> bool {
+        let value = match CString::new(value) {
+            Ok(v) => v,
+            Err(_) => return false,
+        };
+
+        self.patterns
+            .iter()
+            .any(|p| unsafe { fnmatch(p.as_ptr(), value.as_ptr(), flags) == 0 })
+    }
+}
+
+/// Dynamic variables to control the filters.
+pub enum FilterVariable {
+    Ignore,
+    Only,
+    MinDuration,
+}
+
+impl Setting for FilterVariable {
+    fn get(&mut self) -> std::option::Option<CString> {
+        let history = crate::history::HISTORY.try_lock().ok()?;
+        let filters = &history.filters;
+
+        match self {
+            FilterVariable::Ignore => Some(filters.ignore.source().to_owned()),
+            FilterVariable::Only => Some(filters.only.source().to_owned()),
+            FilterVariable::MinDuration => {
+                let secs = filters.min_duration.map_or(0.0, |d| d.as_secs_f64());
+                CString::new(secs.to_string()).ok()
+            }
+        }
+    }
+
+    fn set(&mut self, value: &CStr) -> bool {
+        // The value is validated before touching the history, so an invalid
+        // value does not affect the previous commands.
+        let min_duration = match self {
+            FilterVariable::MinDuration => match parse_duration(value) {
+                Some(d) => Some(d),
+
+                None => {
+                    let _ = writeln!(io::stderr(), "timehistory: invalid duration");
+                    return false;
+                }
+            },
+
+            _ => None,
+        };
+
+        // Entries for the previous commands use the previous filters.
+        crate::ipc::events::flush_pending_events();
+
+        let mut history = match crate::history::HISTORY.try_lock() {
+            Ok(h) => h,
+            Err(e) => {
+                let _ = writeln!(io::stderr(), "timehistory: history unavailable: {}", e);
+                return false;
+            }
+        };
+
+        let filters = &mut history.filters;
+
+        match self {
+            FilterVariable::Ignore => filters.ignore = Patterns::parse(value),
+            FilterVariable::Only => filters.only = Patterns::parse(value),
+            FilterVariable::MinDuration => {
+                filters.min_duration = min_duration.filter(|d| !d.is_zero());
+            }
+        }
+
+        true
+    }
+}
+
+/// Parse a number of seconds for `TIMEHISTORY_MIN_DURATION`.
+///
+/// Returns `None` if the value is negative, or too big for a `Duration`.
+fn parse_duration(value: &CStr) -> Option<Duration> {
+    let secs: f64 = value.to_str().ok()?.parse().ok()?;
+    if valid_seconds(secs) {
+        Some(Duration::from_secs_f64(secs))
+    } else {
+        None
+    }
+}
+
+/// Check if a number of seconds can be used in a `Duration`.
+pub fn valid_seconds(secs: f64) -> bool {
+    // `Duration::from_secs_f64` panics if the number does not fit in an
+    // `u64`. `u64::MAX as f64` is rounded to 2^64.
+    secs >= 0.0 && secs < u64::MAX as f64
+}
+
+#[test]
+fn match_patterns() {
+    let filters = Filters {
+        ignore: Patterns::parse(CStr::from_bytes_with_nul(b"ls:*/git::\0").unwrap()),
+        ..Filters::default()
+    };
+
+    let accept = |filename: &str, arg: &str| filters.accept(filename.as_ref(), &[arg.into()]);
+
+    assert!(!accept("/bin/ls", "ls"));
+    assert!(!accept("/usr/bin/git", "git"));
+    assert!(accept("/bin/lsblk", "lsblk"));
+    assert!(accept("/usr/bin/gitk", "gitk"));
+
+    let filters = Filters {
+        only: Patterns::parse(CStr::from_bytes_with_nul(b"make:cargo\0").unwrap()),
+        ignore: Patterns::parse(CStr::from_bytes_with_nul(b"cargo\0").unwrap()),
+        min_duration: Some(Duration::from_millis(500)),
+    };
+
+    assert!(filters.accept("/usr/bin/make".as_ref(), &["make".into()]));
+    assert!(!filters.accept("/usr/bin/cargo".as_ref(), &["cargo".into()]));
+    assert!(!filters.accept("/bin/ls".as_ref(), &["ls".into()]));
+
+    assert!(filters.too_short(Some(Duration::from_millis(100))));
+    assert!(!filters.too_short(Some(Duration::from_secs(1))));
+    assert!(!filters.too_short(None));
+}
@@ -68,25 +68,97 @@
 : %C %(args)
     //! [label] COMMAND
     //! Command name and arguments.
+    //! If the arguments were truncated, it is followed by '…(+N bytes)'.
+    //! Subshells in a pipeline are shown as '( … )'.
+    //! See TIMEHISTORY_CMDLINE_LIMIT.
+    let members = match entry.pipeline.as_slice() {
+        [] => std::slice::from_ref(entry),
+        pipeline => pipeline,
+    };
+
     let mut need_space = false;
-    for arg in entry.args.iter() {
-        if mem::replace(&mut need_space, true) {
-            w!(" ");
+    for (idx, member) in members.iter().enumerate() {
+        // A subshell is always the first member.
+        if member.subshell {
+            w!("( … )");
+            need_space = true;
+            continue;
+        } else if idx > 0 {
+            w!(" |");
+        }
+
+        for arg in member.args.iter() {
+            if mem::replace(&mut need_space, true) {
+                w!(" ");
+            }
+
+            w!(EscapeArgument(arg.as_bytes()));
         }
 
-        w!(EscapeArgument(arg.as_bytes()));
+        if let Some(omitted_bytes) = member.omitted_bytes {
+            w!("…");
+
+            if omitted_bytes > 0 {
+                w!("(+{} bytes)", omitted_bytes);
+            }
+        }
+    }
+
+: %(argc)
+    //! [label] ARGC
+    //! Number of arguments, including the truncated ones.
+    w!(entry.argc);
+
+: %(shell_cmd)
+    //! [label] SHELLCMD
+    //! Command line as seen by the shell ($BASH_COMMAND).
+    w!(String::from_utf8_lossy(entry.shell_command.as_bytes()));
+
+: %(histnum)
+    //! [label] HISTNUM
+    //! Number of the command in the shell history ($HISTCMD).
+    if let Some(histnum) = entry.histnum {
+        w!(histnum);
+    }
+
+: %(depth)
+    //! [label] DEPTH
+    //! Nesting depth of the shell that executed the program.
+    //! 0 is the main shell. See TIMEHISTORY_NESTED.
+    w!(entry.depth);
+
+: %(tags)
+    //! [label] TAGS
+    //! Tags attached to the entry, separated by commas.
+    //! See TIMEHISTORY_TAG and the -t option.
+    let mut need_comma = false;
+    for tag in &entry.tags {
+        if mem::replace(&mut need_comma, true) {
+            w!(",");
+        }
+
+        w!(tag);
     }
 
 : %E
     //! [label] ELAPSED
     //! Elapsed real (wall clock) time in [hour:]min:sec.
-    if let State::Finished { running_time: Some(time), .. } = &entry.state {
-        let (secs, ms) = (time.as_secs(), time.subsec_millis());
-        if secs >= 3660 {
-            w!("{}:{:02}:{:02}", secs / 3600, (secs % 3600) / 60, secs % 60);
-        } else {
-            w!("{}:{:02}.{:03}", secs / 60, secs % 60, ms);
+    //! 'lost' if the process disappeared without reporting its results.
+    match &entry.state {
+        State::Finished { running_time: Some(time), .. } => {
+            let (secs, ms) = (time.as_secs(), time.subsec_millis());
+            if secs >= 3660 {
+                w!("{}:{:02}:{:02}", secs / 3600, (secs % 3600) / 60, secs % 60);
+            } else {
+                w!("{}:{:02}.{:03}", secs / 60, secs % 60, ms);
+            }
+        }
+
+        State::Lost { .. } => {
+            w!("lost");
         }
+
+        _ => (),
     }
 
 : %F %(majflt)
@@ -117,15 +189,19 @@
         // very-short commands, like `/bin/true`.
         let elapsed = time.as_millis();
 
-        if elapsed > 0 {
-            let usage_time =
-                rusage.ru_utime.tv_sec * 1_000 + rusage.ru_utime.tv_usec / 1000 +
-                rusage.ru_stime.tv_sec * 1_000 + rusage.ru_stime.tv_usec / 1000;
+        let usage_time =
+            rusage.ru_utime.tv_sec * 1_000 + rusage.ru_utime.tv_usec / 1000 +
+            rusage.ru_stime.tv_sec * 1_000 + rusage.ru_stime.tv_usec / 1000;
 
-            let pcent = (1000000 * usage_time as u128 / elapsed) as f64 / 10000.0;
-            w!("{:.1$}%", pcent, if pcent < 10.0 { 2 } else { 0 });
-        } else {
-            w!("0%");
+        match (1000000 * usage_time as u128).checked_div(elapsed) {
+            Some(pcent) => {
+                let pcent = pcent as f64 / 10000.0;
+                w!("{:.1$}%", pcent, if pcent < 10.0 { 2 } else { 0 });
+            }
+
+            None => {
+                w!("0%");
+            }
         }
     }
 
@@ -152,13 +228,17 @@
 
 : %Tt
     //! [label] EXTYPE
-    //! Termination type: normal, signalled, stopped.
-    if let State::Finished { status, .. } = &entry.state {
-        w!(
-            if libc::WIFSTOPPED(*status) { "stopped" }
-            else if libc::WIFSIGNALED(*status) { "signalled" }
-            else { "normal" }
-        );
+    //! Termination type: normal, signalled, stopped, continued, lost.
+    match &entry.state {
+        State::Finished { status, .. } => {
+            w!(WaitStatus::new(*status).kind());
+        }
+
+        State::Lost { .. } => {
+            w!("lost");
+        }
+
+        _ => (),
     }
 
 : %Tn
@@ -203,8 +283,17 @@
 : %e
     //! [label] ELAPSED
     //! Elapsed real time in seconds.
-    if let State::Finished { running_time: Some(time), .. } = &entry.state {
-        w!("{}.{:03}", time.as_secs(), time.subsec_millis());
+    //! 'lost' if the process disappeared without reporting its results.
+    match &entry.state {
+        State::Finished { running_time: Some(time), .. } => {
+            w!("{}.{:03}", time.as_secs(), time.subsec_millis());
+        }
+
+        State::Lost { .. } => {
+            w!("lost");
+        }
+
+        _ => (),
     }
 
 : %n
@@ -231,11 +320,134 @@
         w!(*status);
     }
 
+: %(cpu_time)
+    //! [label] CPUTIME
+    //! Total CPU time, user and system (seconds).
+    if let Some(metrics) = Metrics::from_state(&entry.state) {
+        let time = metrics.cpu_time();
+        w!("{}.{:03}", time.as_secs(), time.subsec_millis());
+    }
+
+: %(cpu_time_us)
+    //! [label] CPUTIME
+    //! Total CPU time, user and system (microseconds).
+    if let Some(metrics) = Metrics::from_state(&entry.state) {
+        w!(metrics.cpu_time().as_micros());
+    }
+
+: %(cores)
+    //! [label] CORES
+    //! Average number of CPU cores used.
+    if let Some(cores) = Metrics::from_state(&entry.state).and_then(|m| m.cores()) {
+        w!("{:.2}", cores);
+    }
+
+: %(faults_rate)
+    //! [label] FLT/S
+    //! Page faults (major and minor) per second.
+    if let Some(rate) = Metrics::from_state(&entry.state).and_then(|m| m.faults_rate()) {
+        w!("{:.1}", rate);
+    }
+
+: %(csw_rate)
+    //! [label] CSW/S
+    //! Context switches (voluntary and involuntary) per second.
+    if let Some(rate) = Metrics::from_state(&entry.state).and_then(|m| m.csw_rate()) {
+        w!("{:.1}", rate);
+    }
+
+: %(blocks_rate)
+    //! [label] BLK/S
+    //! File system blocks (inputs and outputs) per second.
+    if let Some(rate) = Metrics::from_state(&entry.state).and_then(|m| m.blocks_rate()) {
+        w!("{:.1}", rate);
+    }
+
+: %(signal_name)
+    //! [label] SIGNAME
+    //! Name of the signal that terminated or stopped the process.
+    match &entry.state {
+        State::Finished { status, .. } => {
+            if let Some(signal) = WaitStatus::new(*status).signal() {
+                w!(SignalName(signal));
+            }
+        }
+
+        State::Stopped { signal, .. } => {
+            w!(SignalName(*signal));
+        }
+
+        _ => (),
+    }
+
+: %(core_dumped)
+    //! [label] CORE
+    //! 'yes' if the process produced a core dump, 'no' otherwise.
+    if let State::Finished { status, .. } = &entry.state {
+        w!(if WaitStatus::new(*status).core_dumped() { "yes" } else { "no" });
+    }
+
+: %(shell_status)
+    //! [label] $?
+    //! Exit status as reported by bash (128+N if killed by signal N).
+    if let State::Finished { status, .. } = &entry.state {
+        if let Some(code) = WaitStatus::new(*status).shell_status() {
+            w!(code);
+        }
+    }
+
+: %(state)
+    //! [label] STATE
+    //! Process state: running, stopped, continued, finished, lost.
+    w!(match &entry.state {
+        State::Running { .. } => "running",
+        State::Stopped { .. } => "stopped",
+        State::Continued { .. } => "continued",
+        State::Finished { .. } => "finished",
+        State::Lost { .. } => "lost",
+    });
+
+: %(stops)
+    //! [label] STOPS
+    //! Number of times the process was stopped by job control.
+    w!(entry.job_control.stops);
+
+: %(stopped_time)
+    //! [label] STOPPED
+    //! Time spent in the stopped state (seconds).
+    let time = &entry.job_control.stopped_time;
+    w!("{}.{:03}", time.as_secs(), time.subsec_millis());
+
 : %(pid)
     //! [label] PID
     //! Process identifier.
     w!(entry.pid);
 
+: %(members)
+    //! [label] MEMBERS
+    //! Entry numbers of the members of a pipeline, when -g is used.
+    let mut need_comma = false;
+    for member in &entry.pipeline {
+        if mem::replace(&mut need_comma, true) {
+            w!(",");
+        }
+
+        w!(member.number);
+    }
+
+: %(pgid)
+    //! [label] PGID
+    //! Process group identifier. See %(job) for the job number of bash.
+    w!(entry.pgid);
+
+: %(job)
+    //! [label] JOB
+    //! Job number of bash (like `%1`). It is empty if the
+    //! process was not a job of the main shell.
+    if let Some(job) = entry.job {
+        w!(job);
+    }
+
 : %(time:
     //! [label] STARTED
     //! [label-until] )
@@ -1,9 +1,14 @@
 //! Format data from a history entry.
 
-use crate::history::{Entry, State};
+use crate::config::Setting;
+use crate::history::{Entry, History, State};
+use crate::metrics::Metrics;
+use crate::waitstatus::{SignalName, WaitStatus};
+use std::ffi::{CStr, CString};
 use std::io::{self, Write};
 use std::mem;
 use std::os::unix::ffi::OsStrExt;
+use std::sync::Mutex;
 
 mod escapes;
 mod options;
@@ -29,3 +34,112 @@ pub fn labels(format: &str, mut output: impl Write) -> io::Result<()> {
     include!(concat!(env!("OUT_DIR"), "/labels-parser.rs"));
     Ok(())
 }
+
+/// Check if a format string can be used as the default format.
+///
+/// `@name` references must exist in the configuration file, and all `%`
+/// specifiers must be known.
+pub fn validate(format: &str) -> Result<(), String> {
+    let format = match format.strip_prefix('@') {
+        None => format,
+        Some(name) => match crate::config::named_format(name) {
+            Some(named) => named,
+            None => return Err(format!("{}: unknown format", format)),
+        },
+    };
+
+    let options = match FormatOptions::try_parse(format) {
+        Ok(options) => options,
+        Err(option) => return Err(format!("'{}': invalid format option", option)),
+    };
+
+    match unknown_specifier(options.format) {
+        None => Ok(()),
+        Some(spec) => Err(format!("'{}': unknown format specifier", spec)),
+    }
+}
+
+/// Find the first unknown `%` specifier in a format string.
+///
+/// For `%(name)` specifiers, the returned string includes the name.
+fn unknown_specifier(format: &str) -> Option<&str> {
+    fn find(format: &str) -> Option<usize> {
+        include!(concat!(env!("OUT_DIR"), "/format-checker.rs"))
+    }
+
+    let spec = &format[find(format)?..];
+    let len = if spec.starts_with("%(") {
+        spec.find(')').map_or(spec.len(), |end| end + 1)
+    } else {
+        spec.chars().take(2).map(char::len_utf8).sum()
+    };
+
+    Some(&spec[..len])
+}
+
+/// Dynamic variables to set the default format strings.
+///
+/// Invalid values are rejected when they are assigned. An empty value
+/// restores the default format.
+pub enum FormatVariable {
+    /// `TIMEHISTORY_FORMAT`, to list the entries.
+    List,
+
+    /// `TIMEHISTORY_PROMPT_FORMAT`, for the prompt mode (`-p`).
+    Prompt,
+}
+
+impl Setting for FormatVariable {
+    fn get(&mut self) -> std::option::Option<CString> {
+        let history = crate::history::HISTORY.try_lock().ok()?;
+        let format = match self {
+            FormatVariable::List => history.format.as_deref().unwrap_or(crate::DEFAULT_FORMAT),
+            FormatVariable::Prompt => history
+                .prompt_format
+                .as_deref()
+                .unwrap_or(crate::DEFAULT_PROMPT_FORMAT),
+        };
+
+        CString::new(format).ok()
+    }
+
+    fn set(&mut self, value: &CStr) -> bool {
+        self.store(&crate::history::HISTORY, value)
+    }
+}
+
+impl FormatVariable {
+    /// Validate a new format, and store it in `history`.
+    fn store(&self, history: &Mutex<History>, value: &CStr) -> bool {
+        let format = match value.to_str() {
+            Ok(format) => format,
+
+            Err(_) => {
+                let _ = writeln!(io::stderr(), "timehistory: invalid format");
+                return false;
+            }
+        };
+
+        if let Err(e) = validate(format) {
+            let _ = writeln!(io::stderr(), "timehistory: {}", e);
+            return false;
+        }
+
+        match history.try_lock() {
+            Ok(mut history) => {
+                let format = Some(format.to_owned()).filter(|f| !f.is_empty());
+                match self {
+                    FormatVariable::List => history.format = format,
+                    FormatVariable::Prompt => history.prompt_format = format,
+                }
+
+                true
+            }
+
+            Err(e) => {
+                let _ = writeln!(io::stderr(), "timehistory: history unavailable: {}", e);
+                false
+            }
+        }
+    }
+}
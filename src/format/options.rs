@@ -1,5 +1,8 @@
 //! Extract options from a format string.
 
+use serde::Serialize;
+
+#[derive(Serialize)]
 #[cfg_attr(test, derive(PartialEq, Debug))]
 pub struct FormatOptions<'a> {
     pub header: bool,
@@ -8,7 +11,34 @@ pub struct FormatOptions<'a> {
 }
 
 impl FormatOptions<'_> {
-    pub fn parse(mut format: &str) -> FormatOptions {
+    /// Parse the options of a format string. Invalid options are reported
+    /// as warnings.
+    pub fn parse(format: &str) -> FormatOptions<'_> {
+        Self::parse_with(format, |o| {
+            bash_builtins::warning!("'{}': invalid format option.", o);
+        })
+    }
+
+    /// Parse the options of a format string, and fail if any of them is
+    /// invalid.
+    ///
+    /// The error contains the first invalid option.
+    pub fn try_parse(format: &str) -> Result<FormatOptions<'_>, &str> {
+        let mut invalid = None;
+        let options = Self::parse_with(format, |o| {
+            invalid.get_or_insert(o);
+        });
+
+        match invalid {
+            None => Ok(options),
+            Some(o) => Err(o),
+        }
+    }
+
+    fn parse_with<'a>(
+        mut format: &'a str,
+        mut on_invalid: impl FnMut(&'a str),
+    ) -> FormatOptions<'a> {
         let mut header = false;
         let mut table = false;
 
@@ -21,7 +51,7 @@ impl FormatOptions<'_> {
                     match option {
                         "header" => header = true,
                         "table" => table = true,
-                        o => bash_builtins::warning!("'{}': invalid format option.", o),
+                        o => on_invalid(o),
                     }
                 }
             }
@@ -74,6 +104,25 @@ fn parse_options() {
     );
 }
 
+#[test]
+fn reject_invalid_options() {
+    assert_eq!(
+        FormatOptions::try_parse("[table,heder]%n").map(|o| o.format),
+        Err("heder")
+    );
+
+    assert_eq!(
+        FormatOptions::try_parse("[table]%n").map(|o| o.format),
+        Ok("%n")
+    );
+
+    assert_eq!(FormatOptions::try_parse("[]%n").map(|o| o.format), Err(""));
+    assert_eq!(
+        FormatOptions::try_parse("[table,]%n").map(|o| o.format),
+        Err("")
+    );
+}
+
 #[cfg(test)]
 mod mock_bash_fns {
     #[no_mangle]
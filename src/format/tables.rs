@@ -69,7 +69,7 @@ impl<T: Write> Write for TableWriter<T> {
                 }
             }
 
-            self.output.write_all(&[b'\n'])?;
+            self.output.write_all(b"\n")?;
         }
 
         Ok(())
@@ -1,9 +1,15 @@
 use super::EscapeArgument;
-use crate::history::{Entry, State};
+use crate::history::{Entry, History, State};
 use chrono::{Local, TimeZone};
 use std::ffi::OsString;
 use std::time::Duration;
 
+/// Monotonic start time for finished entries.
+const START: libc::timespec = libc::timespec {
+    tv_sec: 0,
+    tv_nsec: 0,
+};
+
 fn format_entry<F>(format: &str, mut f: F) -> (Entry, String)
 where
     F: FnMut(&mut Entry),
@@ -11,15 +17,30 @@ where
     let mut entry = Entry {
         number: 1234,
         pid: 10000,
+        ppid: 8000,
+        pgid: 9000,
+        job: Some(3),
+        process_start: 0,
         start_time: Local.timestamp(1000000000, 9999),
+        histnum: Some(42),
+        depth: 0,
+        replaced_shell: false,
+        subshell: false,
+        shell_command: OsString::from("ls F | wc -l"),
         filename: OsString::from("/bin/ls"),
         args: ["ls", "F"].iter().map(OsString::from).collect(),
+        argc: 2,
+        omitted_bytes: None,
+        redacted: false,
+        tags: Vec::new(),
         state: State::Running {
             start: libc::timespec {
                 tv_sec: 0,
                 tv_nsec: 0,
             },
         },
+        job_control: Default::default(),
+        pipeline: Vec::new(),
     };
 
     f(&mut entry);
@@ -35,9 +56,25 @@ fn simple_specs() {
 
     assert_eq!(format_entry("%n %C", |_| ()).1, "1234 ls F");
 
+    assert_eq!(
+        format_entry("%(histnum) %(pgid) %(job) %(shell_cmd)", |_| ()).1,
+        "42 9000 3 ls F | wc -l"
+    );
+
+    assert_eq!(format_entry("%(depth)", |e| e.depth = 2).1, "2");
+
+    assert_eq!(format_entry("[%(job)]", |e| e.job = None).1, "[]");
+
+    let tags = vec!["v1".to_string(), "JIRA-12".to_string()];
+    assert_eq!(
+        format_entry("[%(tags)]", |e| e.tags = tags.clone()).1,
+        "[v1,JIRA-12]"
+    );
+
     assert_eq!(
         format_entry("%e %E %u", |entry| {
             entry.state = State::Finished {
+                start: START,
                 running_time: Some(Duration::from_millis(1801)),
                 status: 0,
                 rusage: unsafe { std::mem::zeroed() },
@@ -50,6 +87,7 @@ fn simple_specs() {
     assert_eq!(
         format_entry("%e %E %u", |entry| {
             entry.state = State::Finished {
+                start: START,
                 running_time: Some(Duration::from_millis(7_500_301)),
                 status: 0,
                 rusage: unsafe { std::mem::zeroed() },
@@ -73,6 +111,7 @@ fn show_cpu_usage() {
         assert_eq!(
             format_entry("%P", |entry| {
                 entry.state = State::Finished {
+                    start: START,
                     running_time: Some(Duration::from_secs(1000)),
                     status: 0,
                     rusage,
@@ -84,6 +123,115 @@ fn show_cpu_usage() {
     }
 }
 
+#[test]
+fn derived_metrics() {
+    let rusage = unsafe {
+        let mut r: libc::rusage = std::mem::zeroed();
+        r.ru_utime.tv_sec = 3;
+        r.ru_stime.tv_usec = 500_000;
+        r.ru_minflt = 90;
+        r.ru_majflt = 10;
+        r.ru_nvcsw = 15;
+        r.ru_nivcsw = 5;
+        r.ru_inblock = 6;
+        r.ru_oublock = 2;
+        r
+    };
+
+    assert_eq!(
+        format_entry(
+            "%(cpu_time) %(cpu_time_us) %(cores) %(faults_rate) %(csw_rate) %(blocks_rate)",
+            |entry| {
+                entry.state = State::Finished {
+                    start: START,
+                    running_time: Some(Duration::from_secs(2)),
+                    status: 0,
+                    rusage,
+                }
+            }
+        )
+        .1,
+        "3.500 3500000 1.75 50.0 10.0 4.0"
+    );
+
+    // Rates are not available without elapsed time.
+    assert_eq!(
+        format_entry("%(cpu_time) [%(cores)] [%(csw_rate)]", |entry| {
+            entry.state = State::Finished {
+                start: START,
+                running_time: Some(Duration::ZERO),
+                status: 0,
+                rusage,
+            }
+        })
+        .1,
+        "3.500 [] []"
+    );
+}
+
+#[test]
+fn decoded_status() {
+    let with_status = |status| {
+        move |entry: &mut Entry| {
+            entry.state = State::Finished {
+                start: START,
+                running_time: None,
+                status,
+                rusage: unsafe { std::mem::zeroed() },
+            }
+        }
+    };
+
+    let fmt = "%Tt,%(signal_name),%(core_dumped),%(shell_status)";
+
+    assert_eq!(format_entry(fmt, with_status(2 << 8)).1, "normal,,no,2");
+
+    assert_eq!(
+        format_entry(fmt, with_status(libc::SIGKILL)).1,
+        "signalled,SIGKILL,no,137"
+    );
+
+    assert_eq!(
+        format_entry(fmt, with_status(libc::SIGABRT | 0x80)).1,
+        "signalled,SIGABRT,yes,134"
+    );
+}
+
+#[test]
+fn job_control_state() {
+    let start = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+
+    let fmt = "%(state),%(signal_name),%(stops),%(stopped_time)";
+
+    assert_eq!(format_entry(fmt, |_| ()).1, "running,,0,0.000");
+
+    assert_eq!(
+        format_entry(fmt, |entry| {
+            entry.state = State::Stopped {
+                start,
+                since: start,
+                signal: libc::SIGTSTP,
+            };
+            entry.job_control.stops = 1;
+        })
+        .1,
+        "stopped,SIGTSTP,1,0.000"
+    );
+
+    assert_eq!(
+        format_entry(fmt, |entry| {
+            entry.state = State::Continued { start };
+            entry.job_control.stops = 2;
+            entry.job_control.stopped_time = Duration::from_millis(2500);
+        })
+        .1,
+        "continued,,2,2.500"
+    );
+}
+
 #[test]
 fn literal_chars() {
     assert_eq!(
@@ -109,6 +257,24 @@ fn keep_invalid_specs() {
     assert_eq!(format_entry("%nn%(time:)%(time:", |_| ()).1, "1234n%(time:");
 }
 
+#[test]
+fn find_unknown_specs() {
+    use super::unknown_specifier;
+
+    assert_eq!(
+        unknown_specifier(r"%n %(pid) %% \K \u{25} %(time:%F %X)"),
+        None
+    );
+
+    assert_eq!(unknown_specifier("%(elapsd) %Q"), Some("%(elapsd)"));
+    assert_eq!(unknown_specifier("%n %Q"), Some("%Q"));
+    assert_eq!(unknown_specifier("%n %δ"), Some("%δ"));
+    assert_eq!(unknown_specifier("%(pi"), Some("%(pi"));
+    assert_eq!(unknown_specifier("%(time:%F"), Some("%(time:%F"));
+    assert_eq!(unknown_specifier("100%"), Some("%"));
+    assert_eq!(unknown_specifier(r"\%Q"), Some("%Q"));
+}
+
 #[test]
 fn escape_strings() {
     assert_eq!(EscapeArgument(b"abc0134").to_string(), "abc0134");
@@ -134,3 +300,48 @@ fn render_labels() {
         Ok("PID - %(piMAXRSS - STARTED - \u{221e}")
     );
 }
+
+#[test]
+fn lost_entries() {
+    let (_, output) = format_entry("%(state) %e %E %Tt %x.", |entry| {
+        entry.state = State::Lost {
+            start: libc::timespec {
+                tv_sec: 0,
+                tv_nsec: 0,
+            },
+        };
+    });
+
+    assert_eq!(output, "lost lost lost lost .");
+}
+
+#[test]
+fn truncated_arguments() {
+    let (_, output) = format_entry("%C", |entry| {
+        entry.argc = 5;
+        entry.omitted_bytes = Some(1234);
+    });
+
+    assert_eq!(output, "ls F…(+1234 bytes)");
+
+    // Only empty arguments were discarded.
+    let (_, output) = format_entry("%C %(argc)", |entry| {
+        entry.argc = 3;
+        entry.omitted_bytes = Some(0);
+    });
+
+    assert_eq!(output, "ls F… 3");
+}
+
+#[test]
+fn reject_format_if_history_is_busy() {
+    let format = std::ffi::CStr::from_bytes_with_nul(b"%n\0").unwrap();
+    let history = std::sync::Mutex::new(History::new());
+
+    let lock = history.lock().unwrap();
+    assert!(!super::FormatVariable::List.store(&history, format));
+
+    drop(lock);
+    assert!(super::FormatVariable::List.store(&history, format));
+    assert_eq!(history.lock().unwrap().format.as_deref(), Some("%n"));
+}
@@ -1,7 +1,7 @@
 //! Command history.
 
 use std::collections::VecDeque;
-use std::ffi::{CStr, CString, OsString};
+use std::ffi::{CStr, CString, OsStr, OsString};
 use std::io::{self, Write};
 use std::sync::Mutex;
 use std::time::Duration;
@@ -10,6 +10,9 @@ use bash_builtins::variables::DynamicVariable;
 use chrono::{DateTime, Local, TimeZone};
 use once_cell::sync::Lazy;
 
+use crate::filters::{Filters, Patterns};
+use crate::waitstatus::WaitStatus;
+
 /// Default size of the history.
 const DEFAULT_SIZE: usize = 100;
 
@@ -20,34 +23,133 @@ pub static HISTORY: Lazy<Mutex<History>> = Lazy::new(|| Mutex::new(History::new(
 pub static mut OWNER_PID: libc::pid_t = 0;
 
 /// History entry.
-#[derive(serde::Serialize)]
+#[derive(Clone, serde::Serialize)]
 pub struct Entry {
     pub number: usize,
 
     pub pid: libc::pid_t,
 
+    /// Process that forked the program. It is used to group pipelines.
+    #[serde(skip)]
+    pub ppid: libc::pid_t,
+
+    pub pgid: libc::pid_t,
+
+    /// Job number assigned by bash (like `%1`), if the process is a job of
+    /// the main shell. See `crate::procs::job_number`.
+    pub job: Option<usize>,
+
+    /// Start time of the process, from `/proc/<pid>/stat`, or `0` if it is
+    /// not available. It is used to detect reused pids.
+    #[serde(skip)]
+    pub process_start: u64,
+
     pub start_time: DateTime<Local>,
 
+    /// History number of the command in the shell.
+    pub histnum: Option<usize>,
+
+    /// Nesting depth of the shell that executed the program. `0` is the
+    /// main shell.
+    pub depth: usize,
+
+    /// `true` if the program was executed without a fork, replacing the
+    /// process of the shell (like `exec cmd`).
+    pub replaced_shell: bool,
+
+    /// `true` if the program was executed by a subshell in a pipeline, after
+    /// the other commands of the subshell. It is set by
+    /// `crate::pipeline::group`.
+    #[serde(skip)]
+    pub subshell: bool,
+
+    /// Command line, as seen by the shell in `$BASH_COMMAND`.
+    #[serde(serialize_with = "crate::jsonext::serialize_os_string")]
+    pub shell_command: OsString,
+
     #[serde(serialize_with = "crate::jsonext::serialize_os_string")]
     pub filename: OsString,
 
     #[serde(serialize_with = "crate::jsonext::serialize_vec_os_string")]
     pub args: Vec<OsString>,
 
+    /// Number of arguments, including the ones discarded because of the
+    /// `TIMEHISTORY_CMDLINE_LIMIT` setting.
+    pub argc: usize,
+
+    /// Bytes discarded from the arguments because of the
+    /// `TIMEHISTORY_CMDLINE_LIMIT` setting, or `None` if the arguments were
+    /// not truncated. It can be `Some(0)` if only empty arguments were
+    /// discarded. The JSON output only shows if it is present, as `truncated`.
+    #[serde(
+        rename = "truncated",
+        serialize_with = "crate::jsonext::serialize_is_some"
+    )]
+    pub omitted_bytes: Option<usize>,
+
+    /// `true` if secrets were removed from the arguments or the shell
+    /// command, because of the `TIMEHISTORY_REDACT` setting.
+    pub redacted: bool,
+
+    /// Tags and notes attached to the entry, with `TIMEHISTORY_TAG` or the
+    /// `-t` option.
+    pub tags: Vec<String>,
+
     #[serde(serialize_with = "crate::jsonext::serialize_state")]
     pub state: State,
+
+    pub job_control: JobControl,
+
+    /// Members of a pipeline, if this entry is built by
+    /// `crate::pipeline::group`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub pipeline: Vec<Entry>,
 }
 
+#[derive(Clone)]
 pub enum State {
     Running {
         start: libc::timespec,
     },
 
+    /// The process was stopped by a signal (like `SIGTSTP` from Ctrl-Z).
+    Stopped {
+        start: libc::timespec,
+        since: libc::timespec,
+        signal: libc::c_int,
+    },
+
+    /// The process was resumed after being stopped.
+    Continued {
+        start: libc::timespec,
+    },
+
     Finished {
+        start: libc::timespec,
         running_time: Option<Duration>,
         status: libc::c_int,
         rusage: libc::rusage,
     },
+
+    /// The process does not exist anymore, but its results were never
+    /// received (for example, if it was reaped by a function that is not
+    /// replaced).
+    ///
+    /// The entry can still be updated if a `WaitEvent` arrives later.
+    Lost {
+        start: libc::timespec,
+    },
+}
+
+/// Job-control transitions of a process.
+#[derive(Clone, Default, serde::Serialize)]
+pub struct JobControl {
+    /// How many times the process was stopped.
+    pub stops: usize,
+
+    /// Total time spent in the stopped state.
+    #[serde(serialize_with = "crate::jsonext::serialize_duration_secs")]
+    pub stopped_time: Duration,
 }
 
 /// History.
@@ -55,20 +157,64 @@ pub struct History {
     last_number: usize,
     size: usize,
     pub entries: VecDeque<Entry>,
+    pub filters: Filters,
+
+    /// Tag for new entries, from `TIMEHISTORY_TAG`.
+    pub tag: Option<String>,
+
+    /// Default format string, from `TIMEHISTORY_FORMAT`.
+    pub format: Option<String>,
+
+    /// Format string for the prompt mode, from `TIMEHISTORY_PROMPT_FORMAT`.
+    pub prompt_format: Option<String>,
+
+    /// Number of the most recently finished entry.
+    last_finished: Option<usize>,
+
+    /// Numbers of the entries finished since the last call to
+    /// `clear_finished`.
+    finished: Vec<usize>,
 }
 
 impl History {
-    fn new() -> History {
+    pub(crate) fn new() -> History {
         History {
             last_number: 0,
             size: DEFAULT_SIZE,
             entries: VecDeque::with_capacity(DEFAULT_SIZE),
+            filters: Filters::default(),
+            tag: None,
+            format: None,
+            prompt_format: None,
+            last_finished: None,
+            finished: Vec::new(),
         }
     }
 
-    /// Compute a history entry number by an offset.
-    pub fn offset_number(&self, offset: usize) -> usize {
-        self.last_number.saturating_sub(offset - 1)
+    /// Compute a history entry number by an offset. `+1` is the most recent
+    /// entry in the history, even if there are gaps in the numbers.
+    ///
+    /// Returns `None` if there are less than `offset` entries.
+    pub fn offset_number(&self, offset: usize) -> Option<usize> {
+        self.entries.get(offset.checked_sub(1)?).map(|e| e.number)
+    }
+
+    /// Returns the most recently finished entry, if it is still in the
+    /// history.
+    pub fn last_finished(&self) -> Option<&Entry> {
+        let number = self.last_finished?;
+        self.entries.iter().find(|e| e.number == number)
+    }
+
+    /// Returns the numbers of the entries finished since the last call to
+    /// `clear_finished`, in the order they were finished.
+    pub fn finished(&self) -> &[usize] {
+        &self.finished
+    }
+
+    /// Discard the numbers returned by `finished`.
+    pub fn clear_finished(&mut self) {
+        self.finished.clear();
     }
 
     /// Returns the current size.
@@ -83,68 +229,351 @@ impl History {
         self.size = size;
     }
 
+    /// Remove the entries in a selection. The numbers of the other entries
+    /// are not modified.
+    ///
+    /// Returns how many entries were removed.
+    pub fn delete(&mut self, selection: &Selection) -> usize {
+        let selected = self.selector(selection);
+
+        let count = self.entries.len();
+        self.entries.retain(|e| !selected(e));
+        count - self.entries.len()
+    }
+
+    /// Attach tags to the entries in a selection. Tags already present in
+    /// an entry are not duplicated.
+    ///
+    /// Returns how many entries were selected.
+    pub fn annotate(&mut self, selection: &Selection, tags: &[String]) -> usize {
+        let selected = self.selector(selection);
+
+        let mut count = 0;
+        for entry in self.entries.iter_mut().filter(|e| selected(e)) {
+            for tag in tags {
+                if !entry.tags.contains(tag) {
+                    entry.tags.push(tag.clone());
+                }
+            }
+
+            count += 1;
+        }
+
+        count
+    }
+
+    /// Returns a function to check if an entry is in a selection.
+    fn selector<'a>(&self, selection: &'a Selection) -> impl Fn(&Entry) -> bool + 'a {
+        let (first, last) = match selection {
+            Selection::Number(n) => (*n, *n),
+            Selection::Offset(n) => match self.offset_number(*n) {
+                Some(n) => (n, n),
+                None => (0, 0),
+            },
+            Selection::Range(first, last) => (*first, *last),
+            Selection::Patterns(_) => (0, 0),
+        };
+
+        move |entry| match selection {
+            Selection::Patterns(patterns) => selection_matches(patterns, entry),
+            _ => entry.number >= first && entry.number <= last,
+        }
+    }
+
     /// Add a new entry to the history, and discard old entries if
     /// capacity is exceeded.
     pub fn add_entry(&mut self, event: crate::ipc::events::ExecEvent) {
-        if self.size == 0 {
+        if self.size == 0 || !self.filters.accept(&event.filename, &event.args) {
             return;
         }
 
         self.last_number += 1;
 
+        let omitted_bytes =
+            Some(event.omitted_bytes()).filter(|bytes| *bytes > 0 || event.args.len() < event.argc);
+
         self.entries.truncate(self.size - 1);
         self.entries.push_front(Entry {
             number: self.last_number,
             pid: event.pid,
+            ppid: event.ppid,
+            pgid: event.pgid,
+            job: None,
+            process_start: event.process_start,
             start_time: Local.timestamp(event.start_time.tv_sec, event.start_time.tv_nsec as u32),
+            histnum: Some(event.histnum).filter(|n| *n > 0),
+            depth: event.depth,
+            replaced_shell: event.replaces_shell,
+            subshell: false,
+            argc: event.argc,
+            omitted_bytes,
+            redacted: event.redacted,
+            tags: self.tag.iter().cloned().collect(),
+            shell_command: event.shell_command,
             filename: event.filename,
             args: event.args,
             state: State::Running {
                 start: event.monotonic_time,
             },
+            job_control: JobControl::default(),
+            pipeline: Vec::new(),
         });
     }
 
     /// Updates a history entry with the results from `wait4`.
+    ///
+    /// If the process was stopped or continued, the entry is kept until the
+    /// process is finished.
+    ///
+    /// The entry is located by `pid` and `process_start`, so an old entry is
+    /// not updated if the pid is reused by a new process. `process_start` is
+    /// ignored if it is not available (`0`) in the event or in the entry.
+    ///
+    /// If the program replaced the process of a shell (like `exec cmd`), the
+    /// entries for the same process are updated too.
+    ///
+    /// Finished entries are removed if they are shorter than the minimum
+    /// duration in the filters.
     pub fn update_entry(
         &mut self,
         pid: libc::pid_t,
+        process_start: u64,
         status: libc::c_int,
         finish_time: libc::timespec,
         rusage: libc::rusage,
     ) {
-        // Locate the entry for this process in the history.
-        let entry = match self.entries.iter_mut().find(|e| e.pid == pid) {
-            Some(e) => e,
-            None => return,
-        };
+        // Locate the entries for this process in the history.
+        let entries = self.entries.iter_mut().filter(|e| {
+            e.pid == pid
+                && (process_start == 0 || e.process_start == 0 || e.process_start == process_start)
+        });
+
+        let mut short_entries = Vec::new();
+        let mut finished = false;
 
-        // Compute elapsed time since start.
-        let running_time = match &entry.state {
-            State::Running { start } => {
-                fn duration(ts: &libc::timespec) -> Duration {
-                    Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32)
+        for entry in entries {
+            // Without the start time, the filter can select entries of a
+            // previous process with the same pid.
+            if !update_state(entry, status, finish_time, rusage) {
+                continue;
+            }
+
+            if let State::Finished { running_time, .. } = &entry.state {
+                if self.filters.too_short(*running_time) {
+                    short_entries.push(entry.number);
+                } else if !finished {
+                    self.last_finished = Some(entry.number);
+                    finished = true;
+
+                    if self.finished.len() >= self.size {
+                        self.finished.remove(0);
+                    }
+
+                    self.finished.push(entry.number);
                 }
+            }
 
-                duration(&finish_time).checked_sub(duration(start))
+            // If the program replaced a shell (or another program) in the
+            // same process, the previous entry is updated too.
+            if !entry.replaced_shell {
+                break;
             }
+        }
 
-            _ => None,
-        };
+        if !short_entries.is_empty() {
+            self.entries.retain(|e| !short_entries.contains(&e.number));
+        }
+    }
 
-        // Update state in the history.
-        entry.state = State::Finished {
-            running_time,
+    /// Set the job number of the entries for a process.
+    ///
+    /// Entries are located like in `update_entry`.
+    pub fn set_job(&mut self, pid: libc::pid_t, process_start: u64, job: usize) {
+        for entry in self.entries.iter_mut().filter(|e| {
+            e.pid == pid
+                && (process_start == 0 || e.process_start == 0 || e.process_start == process_start)
+        }) {
+            entry.job.get_or_insert(job);
+        }
+    }
+
+    /// Set the job number of the running entries, using the value returned
+    /// by `job_number` for their pids.
+    pub fn assign_jobs<F>(&mut self, mut job_number: F)
+    where
+        F: FnMut(libc::pid_t) -> Option<usize>,
+    {
+        for entry in &mut self.entries {
+            let running = matches!(
+                entry.state,
+                State::Running { .. } | State::Stopped { .. } | State::Continued { .. }
+            );
+
+            if running && entry.job.is_none() {
+                entry.job = job_number(entry.pid);
+            }
+        }
+    }
+
+    /// Mark entries as lost if they are still running, but the process does
+    /// not exist anymore.
+    ///
+    /// `is_alive` receives the pid, the process start time, and the
+    /// monotonic start time of the entry.
+    ///
+    /// Lost entries are reported like finished entries, so they are visible
+    /// to the prompt mode and to the `TIMEHISTORY_LAST_*` variables.
+    pub fn mark_lost_entries<F>(&mut self, mut is_alive: F)
+    where
+        F: FnMut(libc::pid_t, u64, &libc::timespec) -> bool,
+    {
+        let mut lost = Vec::new();
+
+        for entry in &mut self.entries {
+            let start = match &entry.state {
+                State::Running { start }
+                | State::Continued { start }
+                | State::Stopped { start, .. } => *start,
+
+                _ => continue,
+            };
+
+            if !is_alive(entry.pid, entry.process_start, &start) {
+                entry.state = State::Lost { start };
+                lost.push(entry.number);
+            }
+        }
+
+        // Entries are in reverse order.
+        for number in lost.into_iter().rev() {
+            self.last_finished = Some(number);
+
+            if self.finished.len() >= self.size {
+                self.finished.remove(0);
+            }
+
+            self.finished.push(number);
+        }
+    }
+}
+
+/// Entries to remove with `History::delete`.
+pub enum Selection {
+    /// Entry number.
+    Number(usize),
+
+    /// Offset from the end of the history (`1` is the last entry).
+    Offset(usize),
+
+    /// Range of entry numbers, including both ends.
+    Range(usize, usize),
+
+    /// Entries where the filename, the first argument, or the command line
+    /// match any of the patterns.
+    Patterns(Patterns),
+}
+
+impl Selection {
+    /// Parse a selection from the argument of the delete or tag actions.
+    ///
+    /// It can be a number (`<n>` or `+<n>`), a range (`<n>-<m>`), or a list
+    /// of patterns separated by colons.
+    pub fn parse(spec: &str) -> Selection {
+        if let Some((first, last)) = spec.split_once('-') {
+            if let (Ok(first), Ok(last)) = (first.parse(), last.parse()) {
+                return Selection::Range(first, last);
+            }
+        }
+
+        match spec.parse() {
+            Ok(n) if n > 0 && spec.starts_with('+') => Selection::Offset(n),
+            Ok(n) => Selection::Number(n),
+            Err(_) => {
+                let spec = CString::new(spec).unwrap_or_default();
+                Selection::Patterns(Patterns::parse(&spec))
+            }
+        }
+    }
+}
+
+/// Check if an entry matches the patterns of a selection.
+fn selection_matches(patterns: &Patterns, entry: &Entry) -> bool {
+    if patterns.matches(&entry.filename) {
+        return true;
+    }
+
+    if let Some(arg) = entry.args.first() {
+        if patterns.matches(arg) {
+            return true;
+        }
+    }
+
+    let command_line = entry.args.join(OsStr::new(" "));
+    patterns.matches(&command_line)
+}
+
+/// Update the state of an entry with the results from `wait4`.
+///
+/// Returns `false` if the entry was already finished.
+fn update_state(
+    entry: &mut Entry,
+    status: libc::c_int,
+    finish_time: libc::timespec,
+    rusage: libc::rusage,
+) -> bool {
+    let start = match &entry.state {
+        State::Running { start } | State::Continued { start } | State::Lost { start } => *start,
+
+        State::Stopped { start, since, .. } => {
+            // Accumulate the time since the process was stopped.
+            let stopped = duration(&finish_time).checked_sub(duration(since));
+            entry.job_control.stopped_time += stopped.unwrap_or_default();
+            *start
+        }
+
+        State::Finished { .. } => return false,
+    };
+
+    entry.state = match WaitStatus::new(status) {
+        WaitStatus::Stopped(signal) => {
+            entry.job_control.stops += 1;
+            State::Stopped {
+                start,
+                since: finish_time,
+                signal,
+            }
+        }
+
+        WaitStatus::Continued => State::Continued { start },
+
+        _ => State::Finished {
+            start,
+            running_time: duration(&finish_time).checked_sub(duration(&start)),
             status,
             rusage,
-        };
-    }
+        },
+    };
+
+    true
+}
+
+/// Convert a `timespec` to a `Duration`.
+pub(crate) fn duration(ts: &libc::timespec) -> Duration {
+    Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32)
+}
+
+/// Returns `true` if any tag of the entry matches the patterns.
+pub fn tags_match(patterns: &Patterns, entry: &Entry) -> bool {
+    entry
+        .tags
+        .iter()
+        .any(|tag| patterns.matches(OsStr::new(tag)))
 }
 
 /// Dynamic variable to control the history limit.
 pub struct LimitVariable;
 
-impl DynamicVariable for LimitVariable {
+impl crate::config::Setting for LimitVariable {
     fn get(&mut self) -> std::option::Option<CString> {
         let size = match crate::history::HISTORY.try_lock() {
             Ok(h) => h.size,
@@ -154,18 +583,414 @@ impl DynamicVariable for LimitVariable {
         CString::new(size.to_string()).ok()
     }
 
-    fn set(&mut self, value: &CStr) {
+    fn set(&mut self, value: &CStr) -> bool {
         let size = match value.to_str().map(str::parse) {
             Ok(Ok(n)) => n,
 
             _ => {
                 let _ = writeln!(io::stderr(), "timehistory: invalid number");
-                return;
+                return false;
             }
         };
 
-        if let Ok(mut history) = crate::history::HISTORY.try_lock() {
-            history.set_size(size);
+        match crate::history::HISTORY.try_lock() {
+            Ok(mut history) => {
+                history.set_size(size);
+                true
+            }
+
+            Err(_) => false,
+        }
+    }
+}
+
+/// Dynamic read-only variables with data from the most recently finished
+/// entry.
+pub enum LastEntryVariable {
+    Elapsed,
+    MaxRss,
+    Cpu,
+}
+
+impl DynamicVariable for LastEntryVariable {
+    fn get(&mut self) -> std::option::Option<CString> {
+        // The shared buffer is read to find entries that were lost, or
+        // finished in a subshell.
+        let history = crate::ipc::events::collect_events(false)?;
+
+        let format = match self {
+            LastEntryVariable::Elapsed => "%e",
+            LastEntryVariable::MaxRss => "%M",
+            LastEntryVariable::Cpu => "%P",
+        };
+
+        let mut value = Vec::new();
+        if let Some(entry) = history.last_finished() {
+            crate::format::render(entry, format, &mut value).ok()?;
+        }
+
+        CString::new(value).ok()
+    }
+
+    fn set(&mut self, _: &CStr) {
+        let _ = writeln!(io::stderr(), "timehistory: read-only variable");
+    }
+}
+
+/// Dynamic variable to set the tag of new entries.
+pub struct TagVariable;
+
+impl crate::config::Setting for TagVariable {
+    fn get(&mut self) -> std::option::Option<CString> {
+        let history = crate::history::HISTORY.try_lock().ok()?;
+        CString::new(history.tag.clone().unwrap_or_default()).ok()
+    }
+
+    fn set(&mut self, value: &CStr) -> bool {
+        let tag = match value.to_str() {
+            Ok(tag) => tag,
+
+            Err(_) => {
+                let _ = writeln!(io::stderr(), "timehistory: invalid tag");
+                return false;
+            }
+        };
+
+        // Entries for the previous commands use the previous tag.
+        crate::ipc::events::flush_pending_events();
+
+        match crate::history::HISTORY.try_lock() {
+            Ok(mut history) => {
+                history.tag = Some(tag.to_owned()).filter(|t| !t.is_empty());
+                true
+            }
+
+            Err(e) => {
+                let _ = writeln!(io::stderr(), "timehistory: history unavailable: {}", e);
+                false
+            }
+        }
+    }
+}
+
+#[test]
+fn stop_and_continue() {
+    fn timespec(tv_sec: libc::time_t) -> libc::timespec {
+        libc::timespec { tv_sec, tv_nsec: 0 }
+    }
+
+    let mut history = History::new();
+    history.add_entry(crate::ipc::events::ExecEvent {
+        pid: 100,
+        ppid: 1,
+        pgid: 100,
+        process_start: 0,
+        monotonic_time: timespec(10),
+        start_time: timespec(0),
+        histnum: 0,
+        depth: 0,
+        replaces_shell: false,
+        redacted: false,
+        shell_command: OsString::new(),
+        argc: 1,
+        args_len: 5,
+        filename: OsString::from("/bin/sleep"),
+        args: vec![OsString::from("sleep")],
+    });
+
+    let rusage = unsafe { std::mem::zeroed() };
+
+    // Stopped by SIGTSTP at 12, continued at 15, stopped again at 16,
+    // killed at 20.
+    history.update_entry(100, 0, (libc::SIGTSTP << 8) | 0x7f, timespec(12), rusage);
+    assert!(
+        matches!(history.entries[0].state, State::Stopped { signal, .. } if signal == libc::SIGTSTP)
+    );
+
+    history.update_entry(100, 0, 0xffff, timespec(15), rusage);
+    assert!(matches!(history.entries[0].state, State::Continued { .. }));
+
+    history.update_entry(100, 0, (libc::SIGSTOP << 8) | 0x7f, timespec(16), rusage);
+    history.update_entry(100, 0, libc::SIGKILL, timespec(20), rusage);
+
+    let entry = &history.entries[0];
+    assert_eq!(entry.job_control.stops, 2);
+    assert_eq!(entry.job_control.stopped_time, Duration::from_secs(7));
+
+    match &entry.state {
+        State::Finished {
+            running_time,
+            status,
+            ..
+        } => {
+            assert_eq!(*running_time, Some(Duration::from_secs(10)));
+            assert_eq!(*status, libc::SIGKILL);
         }
+
+        _ => panic!("entry should be finished"),
+    }
+}
+
+#[test]
+fn lost_entries() {
+    fn timespec(tv_sec: libc::time_t) -> libc::timespec {
+        libc::timespec { tv_sec, tv_nsec: 0 }
+    }
+
+    let mut history = History::new();
+    for pid in [100, 200] {
+        history.add_entry(crate::ipc::events::ExecEvent {
+            pid,
+            ppid: 1,
+            pgid: pid,
+            process_start: 0,
+            monotonic_time: timespec(10),
+            start_time: timespec(0),
+            histnum: 0,
+            depth: 0,
+            replaces_shell: false,
+            redacted: false,
+            shell_command: OsString::new(),
+            argc: 1,
+            args_len: 4,
+            filename: OsString::from("/bin/true"),
+            args: vec![OsString::from("true")],
+        });
+    }
+
+    history.mark_lost_entries(|pid, _, _| pid == 200);
+    assert!(matches!(history.entries[0].state, State::Running { .. }));
+    assert!(matches!(history.entries[1].state, State::Lost { .. }));
+    assert_eq!(history.finished(), [1]);
+    assert_eq!(history.last_finished().map(|e| e.number), Some(1));
+
+    // A lost entry is updated if its results are received later.
+    history.update_entry(100, 0, 0, timespec(12), unsafe { std::mem::zeroed() });
+    assert!(matches!(
+        history.entries[1].state,
+        State::Finished { running_time: Some(t), .. } if t == Duration::from_secs(2)
+    ));
+}
+
+#[test]
+fn job_numbers() {
+    fn timespec(tv_sec: libc::time_t) -> libc::timespec {
+        libc::timespec { tv_sec, tv_nsec: 0 }
+    }
+
+    let mut history = History::new();
+    for pid in [100, 200, 300] {
+        history.add_entry(crate::ipc::events::ExecEvent {
+            pid,
+            ppid: 1,
+            pgid: pid,
+            process_start: 0,
+            monotonic_time: timespec(10),
+            start_time: timespec(0),
+            histnum: 0,
+            depth: 0,
+            replaces_shell: false,
+            redacted: false,
+            shell_command: OsString::new(),
+            argc: 1,
+            args_len: 4,
+            filename: OsString::from("/bin/true"),
+            args: vec![OsString::from("true")],
+        });
     }
+
+    history.update_entry(100, 0, 0, timespec(12), unsafe { std::mem::zeroed() });
+    history.set_job(200, 0, 2);
+
+    // Finished entries and entries with a job number are not modified.
+    history.assign_jobs(|pid| Some(pid as usize / 100));
+
+    let jobs: Vec<_> = history.entries.iter().map(|e| e.job).collect();
+    assert_eq!(jobs, [Some(3), Some(2), None]);
+}
+
+#[test]
+fn reused_pid() {
+    let mut history = History::new();
+    for process_start in [1000, 2000] {
+        history.add_entry(crate::ipc::events::ExecEvent {
+            pid: 100,
+            ppid: 1,
+            pgid: 100,
+            process_start,
+            monotonic_time: libc::timespec {
+                tv_sec: 0,
+                tv_nsec: 0,
+            },
+            start_time: libc::timespec {
+                tv_sec: 0,
+                tv_nsec: 0,
+            },
+            histnum: 0,
+            depth: 0,
+            replaces_shell: false,
+            redacted: false,
+            shell_command: OsString::new(),
+            argc: 1,
+            args_len: 4,
+            filename: OsString::from("/bin/true"),
+            args: vec![OsString::from("true")],
+        });
+    }
+
+    let finish = libc::timespec {
+        tv_sec: 1,
+        tv_nsec: 0,
+    };
+
+    // The event for the old process must not update the new entry.
+    history.update_entry(100, 1000, 0, finish, unsafe { std::mem::zeroed() });
+    assert!(matches!(history.entries[0].state, State::Running { .. }));
+    assert!(matches!(history.entries[1].state, State::Finished { .. }));
+
+    // Unknown start times are matched by pid.
+    history.update_entry(100, 0, 0, finish, unsafe { std::mem::zeroed() });
+    assert!(matches!(history.entries[0].state, State::Finished { .. }));
+}
+
+#[test]
+fn reused_pid_without_start_time() {
+    let timespec = |tv_sec| libc::timespec { tv_sec, tv_nsec: 0 };
+
+    let mut history = History::new();
+    for monotonic_time in [0, 1] {
+        history.add_entry(crate::ipc::events::ExecEvent {
+            pid: 100,
+            ppid: 1,
+            pgid: 100,
+            process_start: 0,
+            monotonic_time: timespec(monotonic_time),
+            start_time: timespec(0),
+            histnum: 0,
+            depth: 0,
+            replaces_shell: false,
+            redacted: false,
+            shell_command: OsString::new(),
+            argc: 1,
+            args_len: 4,
+            filename: OsString::from("/bin/true"),
+            args: vec![OsString::from("true")],
+        });
+    }
+
+    // The second process finishes first.
+    history.update_entry(100, 0, 0, timespec(2), unsafe { std::mem::zeroed() });
+    assert!(matches!(history.entries[0].state, State::Finished { .. }));
+    assert!(matches!(history.entries[1].state, State::Running { .. }));
+
+    // The finished entry is skipped.
+    history.update_entry(100, 0, 0, timespec(3), unsafe { std::mem::zeroed() });
+    assert!(matches!(
+        history.entries[1].state,
+        State::Finished { running_time: Some(t), .. } if t == Duration::from_secs(3)
+    ));
+}
+
+#[test]
+fn replaced_shell() {
+    let mut history = History::new();
+
+    // A shell in the process 100 executes two programs: the first one in a
+    // different process, and the second one replacing the shell.
+    for (pid, process_start, replaces_shell) in
+        [(100, 1000, false), (200, 1500, false), (100, 1000, true)]
+    {
+        history.add_entry(crate::ipc::events::ExecEvent {
+            pid,
+            ppid: 1,
+            pgid: 100,
+            process_start,
+            monotonic_time: libc::timespec {
+                tv_sec: 0,
+                tv_nsec: 0,
+            },
+            start_time: libc::timespec {
+                tv_sec: 0,
+                tv_nsec: 0,
+            },
+            histnum: 0,
+            depth: usize::from(replaces_shell),
+            replaces_shell,
+            redacted: false,
+            shell_command: OsString::new(),
+            argc: 1,
+            args_len: 4,
+            filename: OsString::from("/bin/true"),
+            args: vec![OsString::from("true")],
+        });
+    }
+
+    let finish = libc::timespec {
+        tv_sec: 1,
+        tv_nsec: 0,
+    };
+
+    // Both entries of the process are updated when it is reaped.
+    history.update_entry(100, 1000, 3 << 8, finish, unsafe { std::mem::zeroed() });
+
+    assert!(history.entries[0].replaced_shell);
+    assert!(matches!(history.entries[0].state, State::Finished { status, .. } if status == 3 << 8));
+    assert!(matches!(history.entries[1].state, State::Running { .. }));
+    assert!(matches!(history.entries[2].state, State::Finished { status, .. } if status == 3 << 8));
+}
+
+#[test]
+fn last_finished() {
+    fn timespec(tv_sec: libc::time_t) -> libc::timespec {
+        libc::timespec { tv_sec, tv_nsec: 0 }
+    }
+
+    let mut history = History::new();
+    for pid in [100, 200] {
+        history.add_entry(crate::ipc::events::ExecEvent {
+            pid,
+            ppid: 1,
+            pgid: pid,
+            process_start: 0,
+            monotonic_time: timespec(0),
+            start_time: timespec(0),
+            histnum: 0,
+            depth: 0,
+            replaces_shell: false,
+            redacted: false,
+            shell_command: OsString::new(),
+            argc: 1,
+            args_len: 4,
+            filename: OsString::from("/bin/true"),
+            args: vec![OsString::from("true")],
+        });
+    }
+
+    let finish = timespec(1);
+
+    assert!(history.last_finished().is_none());
+
+    // The first entry finishes after the second one.
+    history.update_entry(200, 0, 0, finish, unsafe { std::mem::zeroed() });
+    history.update_entry(100, 0, 0, finish, unsafe { std::mem::zeroed() });
+    assert_eq!(history.last_finished().map(|e| e.pid), Some(100));
+
+    assert_eq!(history.finished(), [2, 1]);
+    history.clear_finished();
+    assert!(history.finished().is_empty());
+
+    history.entries.clear();
+    assert!(history.last_finished().is_none());
+}
+
+#[test]
+fn parse_selections() {
+    assert!(matches!(Selection::parse("7"), Selection::Number(7)));
+    assert!(matches!(Selection::parse("+2"), Selection::Offset(2)));
+    assert!(matches!(Selection::parse("3-5"), Selection::Range(3, 5)));
+    assert!(matches!(Selection::parse("-5"), Selection::Patterns(_)));
+    assert!(matches!(
+        Selection::parse("*/ls:git"),
+        Selection::Patterns(_)
+    ));
 }
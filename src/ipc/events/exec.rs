@@ -2,12 +2,29 @@
 //!
 //! # Serialization Data
 //!
-//! * Process identifier (`pid_t`).
-//! * Monotonic time of the event (`timespec`).
-//! * Real time (wall-clock) of the event (`timespec`).
+//! All integers are encoded as little-endian `i64`/`u64` values.
+//!
+//! * Process identifier (`i64`).
+//! * Parent process identifier (`i64`).
+//! * Process group identifier (`i64`).
+//! * Start time of the process, in clock ticks since boot (`u64`).
+//! * Monotonic time of the event (`timespec`, as two `i64`).
+//! * Real time (wall-clock) of the event (`timespec`, as two `i64`).
+//! * History number in the shell (`u64`).
+//! * Nesting depth of the shell (`u64`).
+//! * `1` if the program replaces the shell process, `0` otherwise (`u64`).
+//! * `1` if any secret was redacted in the command line, `0` otherwise
+//!   (`u64`).
+//! * Command from `$BASH_COMMAND` (C string).
+//! * Number of arguments, before applying `max_cmdline` (`u64`).
+//! * Total size in bytes of the arguments, before applying `max_cmdline`
+//!   (`u64`).
+//! * Filename of the executed program (C string).
 //! * Arguments of the executed program (array of C strings).
+//!
+//! C strings are encoded as their length (`u64`) followed by the bytes.
 
-use std::ffi::OsString;
+use std::ffi::{CStr, OsString};
 use std::io::{self, Seek, Write};
 
 use super::ioext::{ReadExt, WriteExt};
@@ -19,19 +36,82 @@ pub const EXECVE_TAG: u8 = 1;
 /// Events from an `execve` function.
 pub struct ExecEvent {
     pub pid: libc::pid_t,
+
+    /// Process that forked (or spawned) the program.
+    pub ppid: libc::pid_t,
+
+    pub pgid: libc::pid_t,
+    pub process_start: u64,
     pub monotonic_time: libc::timespec,
     pub start_time: libc::timespec,
+    pub histnum: usize,
+
+    /// Nesting depth of the shell that executed the program. `0` is the
+    /// main shell.
+    pub depth: usize,
+
+    /// `true` if the program was executed without a fork, replacing the
+    /// shell process.
+    pub replaces_shell: bool,
+
+    /// `true` if any secret was redacted in the command line.
+    pub redacted: bool,
+
+    pub shell_command: OsString,
+
+    /// Number of arguments in `argv`. It can be greater than `args.len()` if
+    /// the command line was truncated.
+    pub argc: usize,
+
+    /// Total size of the arguments in `argv`, in bytes.
+    pub args_len: usize,
+
     pub filename: OsString,
     pub args: Vec<OsString>,
 }
 
+impl ExecEvent {
+    /// Number of bytes from `argv` that were discarded because of the
+    /// `max_cmdline` limit.
+    pub fn omitted_bytes(&self) -> usize {
+        let written: usize = self.args.iter().map(|a| a.len()).sum();
+        self.args_len.saturating_sub(written)
+    }
+}
+
+/// Data from the process and the shell when a program is executed.
+pub struct ProcessInfo<'a> {
+    pub pid: libc::pid_t,
+    pub ppid: libc::pid_t,
+    pub pgid: libc::pid_t,
+
+    /// Start time of the process, from `/proc/<pid>/stat`, or `0` if it is
+    /// not available. With the pid, it identifies the process.
+    pub process_start: u64,
+
+    /// Value of `$HISTCMD`, or `0` if it is not available.
+    pub histnum: usize,
+
+    /// Nesting depth of the shell.
+    pub depth: usize,
+
+    /// `true` if the program is executed in the shell process.
+    pub replaces_shell: bool,
+
+    /// `true` if any secret was redacted in `argv` or `shell_command`.
+    pub redacted: bool,
+
+    /// Value of `$BASH_COMMAND`.
+    pub shell_command: Option<&'a CStr>,
+}
+
 impl ExecEvent {
     /// Serialize data for an `ExecEvent` value.
     ///
     /// It is unsafe because it trusts the `filename` and `argv` addresses.
     pub unsafe fn serialize<T>(
         output: T,
-        pid: libc::pid_t,
+        process: &ProcessInfo,
         monotonic_time: libc::timespec,
         start_time: libc::timespec,
         filename: *const libc::c_char,
@@ -45,9 +125,34 @@ impl ExecEvent {
         let output = payload.as_mut();
 
         // pid and timespec fields.
-        output.write_value(&pid)?;
-        output.write_value(&monotonic_time)?;
-        output.write_value(&start_time)?;
+        output.write_i64(process.pid.into())?;
+        output.write_i64(process.ppid.into())?;
+        output.write_i64(process.pgid.into())?;
+        output.write_u64(process.process_start)?;
+        output.write_timespec(&monotonic_time)?;
+        output.write_timespec(&start_time)?;
+
+        // Shell data.
+        output.write_u64(process.histnum as u64)?;
+        output.write_u64(process.depth as u64)?;
+        output.write_u64(process.replaces_shell as u64)?;
+        output.write_u64(process.redacted as u64)?;
+        if let Some(cmd) = process.shell_command {
+            output.write_cstr(cmd.as_ptr(), max_cmdline)?;
+        } else {
+            output.write_u64(0)?;
+        }
+
+        // Size of argv, before truncating it.
+        let mut argc = 0;
+        let mut args_len = 0;
+        while !(*argv.add(argc)).is_null() {
+            args_len += libc::strlen(*argv.add(argc));
+            argc += 1;
+        }
+
+        output.write_u64(argc as u64)?;
+        output.write_u64(args_len as u64)?;
 
         // filename and argv fields.
         output.write_cstr(filename, max_cmdline)?;
@@ -69,9 +174,21 @@ impl ExecEvent {
         let mut reader = io::Cursor::new(buf);
 
         // Read pid and timespec fields.
-        let pid = unsafe { reader.read_value()? };
-        let monotonic_time = unsafe { reader.read_value()? };
-        let start_time = unsafe { reader.read_value()? };
+        let pid = reader.read_i64()? as libc::pid_t;
+        let ppid = reader.read_i64()? as libc::pid_t;
+        let pgid = reader.read_i64()? as libc::pid_t;
+        let process_start = reader.read_u64()?;
+        let monotonic_time = reader.read_timespec()?;
+        let start_time = reader.read_timespec()?;
+
+        // Shell data.
+        let histnum = reader.read_u64()? as usize;
+        let depth = reader.read_u64()? as usize;
+        let replaces_shell = reader.read_u64()? != 0;
+        let redacted = reader.read_u64()? != 0;
+        let shell_command = reader.read_cstr()?;
+        let argc = reader.read_u64()? as usize;
+        let args_len = reader.read_u64()? as usize;
 
         // Read arguments as C strings.
         let filename = reader.read_cstr()?;
@@ -82,8 +199,18 @@ impl ExecEvent {
 
         Ok(ExecEvent {
             pid,
+            ppid,
+            pgid,
+            process_start,
             monotonic_time,
             start_time,
+            histnum,
+            depth,
+            replaces_shell,
+            redacted,
+            shell_command,
+            argc,
+            args_len,
             filename,
             args,
         })
@@ -1,29 +1,85 @@
 //! Extensions for the `Read`/`Write` traits.
+//!
+//! All values are written with an explicit encoding (little-endian, with a
+//! fixed size), so the data does not depend on the layout of the types in
+//! the platform.
 
 use std::ffi::OsString;
 use std::io::{self, Read, Write};
-use std::mem::{self, MaybeUninit};
 use std::os::unix::ffi::OsStringExt;
-use std::{ptr, slice};
 
 pub(super) trait ReadExt {
-    /// Read any `Copy` value.
-    ///
-    /// This function is unsafe because the caller has to ensure that `T`
-    /// does not contain any reference.
-    unsafe fn read_value<T: Copy + 'static>(&mut self) -> io::Result<T>;
+    /// Read a `u64` value.
+    fn read_u64(&mut self) -> io::Result<u64>;
+
+    /// Read a `i64` value.
+    fn read_i64(&mut self) -> io::Result<i64> {
+        self.read_u64().map(|n| n as i64)
+    }
+
+    /// Read a `timespec` value, as written by `WriteExt::write_timespec`.
+    fn read_timespec(&mut self) -> io::Result<libc::timespec> {
+        Ok(libc::timespec {
+            tv_sec: self.read_i64()? as libc::time_t,
+            tv_nsec: self.read_i64()? as libc::c_long,
+        })
+    }
+
+    /// Read a `rusage` value, as written by `WriteExt::write_rusage`.
+    fn read_rusage(&mut self) -> io::Result<libc::rusage> {
+        let mut rusage: libc::rusage = unsafe { std::mem::zeroed() };
+
+        for tv in [&mut rusage.ru_utime, &mut rusage.ru_stime] {
+            tv.tv_sec = self.read_i64()? as libc::time_t;
+            tv.tv_usec = self.read_i64()? as libc::suseconds_t;
+        }
+
+        for field in rusage_fields(&mut rusage) {
+            *field = self.read_i64()? as libc::c_long;
+        }
+
+        Ok(rusage)
+    }
 
     /// Extract a C string, as written by `WriteExt::write_cstr`.
     fn read_cstr(&mut self) -> io::Result<OsString>;
 }
 
+// `time_t` and `c_long` are `i64` only in 64-bit targets.
+#[allow(clippy::useless_conversion)]
 pub(super) trait WriteExt {
-    /// Write any `Copy` value.
-    fn write_value<T: Copy + 'static>(&mut self, value: &T) -> io::Result<()>;
+    /// Write a `u64` value.
+    fn write_u64(&mut self, value: u64) -> io::Result<()>;
+
+    /// Write a `i64` value.
+    fn write_i64(&mut self, value: i64) -> io::Result<()> {
+        self.write_u64(value as u64)
+    }
+
+    /// Write a `timespec` value, as two `i64` values.
+    fn write_timespec(&mut self, ts: &libc::timespec) -> io::Result<()> {
+        self.write_i64(ts.tv_sec.into())?;
+        self.write_i64(ts.tv_nsec.into())
+    }
+
+    /// Write a `rusage` value, as a sequence of `i64` values.
+    fn write_rusage(&mut self, rusage: &libc::rusage) -> io::Result<()> {
+        for tv in [&rusage.ru_utime, &rusage.ru_stime] {
+            self.write_i64(tv.tv_sec.into())?;
+            self.write_i64(tv.tv_usec.into())?;
+        }
+
+        let mut rusage = *rusage;
+        for field in rusage_fields(&mut rusage) {
+            self.write_i64((*field).into())?;
+        }
+
+        Ok(())
+    }
 
     /// Write a C string to `output`.
     ///
-    /// The size is written as a `usize` before the string, and it is limited to
+    /// The size is written as a `u64` before the string, and it is limited to
     /// `limit`.
     ///
     /// Returns how many bytes are written.
@@ -31,33 +87,35 @@ pub(super) trait WriteExt {
 }
 
 impl<R: Read> ReadExt for R {
-    unsafe fn read_value<T: Copy + 'static>(&mut self) -> io::Result<T> {
-        let mut data = MaybeUninit::<T>::uninit();
-        let buf = slice::from_raw_parts_mut(data.as_mut_ptr().cast(), mem::size_of::<T>());
-        self.read_exact(buf)?;
-        Ok(ptr::read(data.as_ptr()))
+    fn read_u64(&mut self) -> io::Result<u64> {
+        let mut bytes = [0; 8];
+        self.read_exact(&mut bytes)?;
+        Ok(u64::from_le_bytes(bytes))
     }
 
     fn read_cstr(&mut self) -> io::Result<OsString> {
-        let size = unsafe { self.read_value::<usize>()? };
-        let mut bytes = vec![0; size];
-        self.read_exact(&mut bytes)?;
+        let size = self.read_u64()? as usize;
+        let mut bytes = Vec::new();
+        self.take(size as u64).read_to_end(&mut bytes)?;
+
+        if bytes.len() != size {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
         Ok(OsString::from_vec(bytes))
     }
 }
 
 impl<W: Write> WriteExt for W {
-    fn write_value<T: Copy + 'static>(&mut self, value: &T) -> io::Result<()> {
-        let slice =
-            unsafe { slice::from_raw_parts(value as *const T as *const u8, mem::size_of::<T>()) };
-        self.write_all(slice)
+    fn write_u64(&mut self, value: u64) -> io::Result<()> {
+        self.write_all(&value.to_le_bytes())
     }
 
     unsafe fn write_cstr(&mut self, ptr: *const libc::c_char, limit: usize) -> io::Result<usize> {
         // String size.
         let size = libc::strnlen(ptr.cast(), limit);
 
-        self.write_value(&size)?;
+        self.write_u64(size as u64)?;
 
         // String bytes.
         let slice = std::slice::from_raw_parts(ptr.cast(), size);
@@ -67,25 +125,60 @@ impl<W: Write> WriteExt for W {
     }
 }
 
+/// Counters in a `rusage` value, in the order used to serialize them.
+fn rusage_fields(rusage: &mut libc::rusage) -> [&mut libc::c_long; 14] {
+    [
+        &mut rusage.ru_maxrss,
+        &mut rusage.ru_ixrss,
+        &mut rusage.ru_idrss,
+        &mut rusage.ru_isrss,
+        &mut rusage.ru_minflt,
+        &mut rusage.ru_majflt,
+        &mut rusage.ru_nswap,
+        &mut rusage.ru_inblock,
+        &mut rusage.ru_oublock,
+        &mut rusage.ru_msgsnd,
+        &mut rusage.ru_msgrcv,
+        &mut rusage.ru_nsignals,
+        &mut rusage.ru_nvcsw,
+        &mut rusage.ru_nivcsw,
+    ]
+}
+
 #[test]
 fn read_and_write_primitives() {
-    #[derive(Copy, Clone, Debug, PartialEq)]
-    struct SomeData {
-        a: u32,
-        b: u32,
-    }
-
     let mut data: Vec<u8> = vec![];
 
-    let mut output = io::Cursor::new(&mut data);
-    output.write_value(&10_u16).unwrap();
-    output.write_value(&SomeData { a: 1, b: 2 }).unwrap();
-    drop(output);
+    let mut rusage: libc::rusage = unsafe { std::mem::zeroed() };
+    rusage.ru_stime.tv_usec = 500;
+    rusage.ru_maxrss = 1000;
+    rusage.ru_nivcsw = 20;
+
+    {
+        let mut output = io::Cursor::new(&mut data);
+        output.write_u64(10).unwrap();
+        output.write_i64(-2).unwrap();
+        output
+            .write_timespec(&libc::timespec {
+                tv_sec: 3,
+                tv_nsec: 4,
+            })
+            .unwrap();
+        output.write_rusage(&rusage).unwrap();
+    }
+
+    assert_eq!(&data[..8], &[10, 0, 0, 0, 0, 0, 0, 0]);
+    assert_eq!(data.len(), 8 * (2 + 2 + 4 + 14));
 
     let mut input = io::Cursor::new(&data);
-    assert_eq!(unsafe { input.read_value::<u16>().unwrap() }, 10_u16);
-    assert_eq!(
-        unsafe { input.read_value::<SomeData>().unwrap() },
-        SomeData { a: 1, b: 2 }
-    );
+    assert_eq!(input.read_u64().unwrap(), 10);
+    assert_eq!(input.read_i64().unwrap(), -2);
+
+    let ts = input.read_timespec().unwrap();
+    assert_eq!((ts.tv_sec, ts.tv_nsec), (3, 4));
+
+    let r = input.read_rusage().unwrap();
+    assert_eq!(r.ru_stime.tv_usec, 500);
+    assert_eq!(r.ru_maxrss, 1000);
+    assert_eq!(r.ru_nivcsw, 20);
 }
@@ -5,24 +5,43 @@
 //! Events are serialized to any byte buffer. Each event is written in the
 //! following format:
 //!
+//! * Magic byte, to detect data not written by `EventPayload` (`u8`).
+//! * Version of the format (`u8`).
 //! * Tag to identify the event type (`u8`).
-//! * Size in bytes of the event (`u16`).
+//! * Size in bytes of the event, including this header (`u32`,
+//!   little-endian).
 //! * Event data.
 //!
 //! This format is generated by the `EventPayload` wrapper.
+//!
+//! The version has to be increased when the data of any event is changed.
+//! `EventsParser` skips events with a different version, since their size is
+//! still known.
 
 mod exec;
 mod ioext;
+pub mod pending;
 mod wait;
 
 use crate::history::History;
+use std::convert::TryFrom;
 use std::io::{self, Seek, Write};
+use std::ptr;
 use std::sync::MutexGuard;
 use std::time::Duration;
 
-pub use exec::ExecEvent;
+pub use exec::{ExecEvent, ProcessInfo};
 pub use wait::WaitEvent;
 
+/// First byte of every event.
+const EVENT_MAGIC: u8 = 0xE7;
+
+/// Version of the format for the events.
+const EVENT_VERSION: u8 = 6;
+
+/// Size of the header written by `EventPayload`.
+const EVENT_HEADER_SIZE: usize = 7;
+
 /// Wrapper to serialize events.
 pub struct EventPayload<T> {
     stream: T,
@@ -33,7 +52,7 @@ impl<T: Write + Seek> EventPayload<T> {
     pub fn new(mut stream: T, tag: u8) -> io::Result<Self> {
         let start_position = stream.stream_position()?;
 
-        stream.write_all(&[0, 0, tag])?;
+        stream.write_all(&[EVENT_MAGIC, EVENT_VERSION, tag, 0, 0, 0, 0])?;
 
         Ok(EventPayload {
             stream,
@@ -41,14 +60,20 @@ impl<T: Write + Seek> EventPayload<T> {
         })
     }
 
-    /// Writes the leading two bytes to store the size of the payload, and
-    /// returns the size.
+    /// Writes the size of the payload in the header, and returns the size.
     pub fn finish(mut self) -> io::Result<u64> {
         let current_position = self.stream.stream_position()?;
         let size = current_position - self.start_position;
 
-        self.stream.seek(io::SeekFrom::Start(self.start_position))?;
-        self.stream.write_all(&u16::to_ne_bytes(size as u16))?;
+        let size_field = match u32::try_from(size) {
+            Ok(s) => s,
+            Err(_) => return Err(io::Error::new(io::ErrorKind::InvalidData, "event too big")),
+        };
+
+        self.stream
+            .seek(io::SeekFrom::Start(self.start_position + 3))?;
+        self.stream.write_all(&size_field.to_le_bytes())?;
+        self.stream.seek(io::SeekFrom::Start(current_position))?;
         Ok(size)
     }
 }
@@ -66,12 +91,27 @@ pub enum Event {
 }
 
 /// Parser to extract events from a byte slice.
-pub struct EventsParser<'a>(&'a [u8]);
+pub struct EventsParser<'a> {
+    buffer: &'a [u8],
+    rejected: usize,
+}
 
 impl EventsParser<'_> {
     /// Returns an iterator to red events from a byte slice.
-    pub fn new(buffer: &[u8]) -> EventsParser {
-        EventsParser(buffer)
+    pub fn new(buffer: &[u8]) -> EventsParser<'_> {
+        EventsParser {
+            buffer,
+            rejected: 0,
+        }
+    }
+
+    /// Number of events that were discarded, because they were written by an
+    /// incompatible version, or they are corrupted.
+    ///
+    /// If the buffer contains data that is not a valid event, the parser stops
+    /// and the remaining bytes are counted as a single rejected event.
+    pub fn rejected(&self) -> usize {
+        self.rejected
     }
 }
 
@@ -79,34 +119,81 @@ impl Iterator for EventsParser<'_> {
     type Item = Event;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let buf = self.0;
+        loop {
+            let buf = self.buffer;
 
-        if buf.len() < 3 {
-            return None;
-        }
+            if buf.is_empty() {
+                return None;
+            }
 
-        // Size of the current event.
-        let event_size = {
-            let bytes = [buf[0], buf[1]];
-            u16::from_ne_bytes(bytes) as usize
-        };
+            // Validate the header.
+            let event_size = match buf.get(..EVENT_HEADER_SIZE) {
+                Some(&[EVENT_MAGIC, _, _, s0, s1, s2, s3]) => {
+                    u32::from_le_bytes([s0, s1, s2, s3]) as usize
+                }
+                _ => 0,
+            };
 
-        let event_tag = buf[2];
-        let event_data = buf.get(3..event_size)?;
+            if event_size < EVENT_HEADER_SIZE || event_size > buf.len() {
+                self.buffer = &[];
+                self.rejected += 1;
+                return None;
+            }
 
-        let event = match event_tag {
-            exec::EXECVE_TAG => Event::Exec(ExecEvent::deserialize(event_data).ok()?),
-            wait::WAIT_TAG => Event::Wait(WaitEvent::deserialize(event_data).ok()?),
-            _ => return None,
-        };
+            let version = buf[1];
+            let event_tag = buf[2];
+            let event_data = &buf[EVENT_HEADER_SIZE..event_size];
+            self.buffer = &buf[event_size..];
+
+            let event = match (version, event_tag) {
+                (EVENT_VERSION, exec::EXECVE_TAG) => {
+                    ExecEvent::deserialize(event_data).map(Event::Exec)
+                }
+                (EVENT_VERSION, wait::WAIT_TAG) => {
+                    WaitEvent::deserialize(event_data).map(Event::Wait)
+                }
+                _ => Err(io::ErrorKind::InvalidData.into()),
+            };
 
-        self.0 = &self.0[event_size..];
-        Some(event)
+            match event {
+                Ok(event) => return Some(event),
+                Err(_) => self.rejected += 1,
+            }
+        }
     }
 }
 
+/// Position in the shared buffer of the events already added to the history
+/// of a subshell.
+struct ReadPosition {
+    generation: u64,
+    offset: usize,
+}
+
+/// Events read by this process, if it is a subshell.
+///
+/// It is only accessed while the history is locked. Since the history and
+/// this position are copied together when a process is forked, nested
+/// subshells skip the same events.
+static mut SUBSHELL_POSITION: Option<ReadPosition> = None;
+
 /// Extract events from the shared buffers and update the history.
+///
+/// The buffer is cleared only in the process where the history is stored.
 pub fn collect_events(show_errors: bool) -> Option<MutexGuard<'static, History>> {
+    collect_events_with_timeout(show_errors, Duration::from_millis(50))
+}
+
+/// Like `collect_events`, but it fails immediately, without printing any
+/// error, if the history or the shared buffer are locked.
+pub fn try_collect_events() -> Option<MutexGuard<'static, History>> {
+    collect_events_with_timeout(false, Duration::ZERO)
+}
+
+fn collect_events_with_timeout(
+    show_errors: bool,
+    timeout: Duration,
+) -> Option<MutexGuard<'static, History>> {
     let mut history = match crate::history::HISTORY.try_lock() {
         Ok(l) => l,
 
@@ -118,7 +205,7 @@ pub fn collect_events(show_errors: bool) -> Option<MutexGuard<'static, History>>
         }
     };
 
-    let mut shared_buffer = match crate::ipc::global_shared_buffer(Duration::from_millis(50)) {
+    let mut shared_buffer = match crate::ipc::global_shared_buffer(timeout) {
         Some(sb) => sb,
 
         None => {
@@ -129,22 +216,82 @@ pub fn collect_events(show_errors: bool) -> Option<MutexGuard<'static, History>>
         }
     };
 
-    for event in EventsParser::new(shared_buffer.input()) {
+    let is_owner = unsafe { libc::getpid() == crate::history::OWNER_PID };
+    let generation = shared_buffer.generation();
+    let input = shared_buffer.input();
+    let input_len = input.len();
+
+    // A subshell can't clear the buffer, so it skips the events that were
+    // added to its history in a previous call.
+    let position = unsafe { &mut *ptr::addr_of_mut!(SUBSHELL_POSITION) };
+    let start = match position {
+        Some(p) if !is_owner && p.generation == generation => p.offset.min(input_len),
+        _ => 0,
+    };
+
+    let mut parser = EventsParser::new(&input[start..]);
+    for event in &mut parser {
         match event {
             Event::Exec(e) => history.add_entry(e),
-            Event::Wait(w) => history.update_entry(w.pid, w.status, w.finish_time, w.rusage),
+            Event::Wait(w) => {
+                history.update_entry(w.pid, w.process_start, w.status, w.finish_time, w.rusage)
+            }
         }
     }
 
-    shared_buffer.clear();
+    // Results of the wait functions in this process. They are added after
+    // the events in the shared buffer, which can contain the entries for
+    // these processes.
+    pending::drain(|w| {
+        if let Some(job) = w.job {
+            history.set_job(w.pid, w.process_start, job);
+        }
+
+        history.update_entry(w.pid, w.process_start, w.status, w.finish_time, w.rusage)
+    });
+
+    if show_errors && parser.rejected() > 0 {
+        let _ = writeln!(
+            io::stderr(),
+            "timehistory: {} events discarded from an incompatible version",
+            parser.rejected()
+        );
+    }
+
+    // In a subshell (like `$(timehistory)`), the events are kept in the
+    // buffer, since the history of the main shell is not updated.
+    if is_owner {
+        shared_buffer.clear();
+    } else {
+        *position = Some(ReadPosition {
+            generation,
+            offset: input_len,
+        });
+    }
+
+    // Job numbers are only available while bash keeps the job in its table.
+    history.assign_jobs(crate::procs::job_number);
+
+    // Entries for processes reaped by other paths (or by subshells that
+    // exited before sending their events) would be running forever.
+    history.mark_lost_entries(crate::procs::is_alive);
 
     Some(history)
 }
 
+/// Move the pending events of the main shell to the history.
+///
+/// It does nothing in subshells.
+pub fn flush_pending_events() {
+    if unsafe { libc::getpid() == crate::history::OWNER_PID } {
+        collect_events(false);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::ffi::OsString;
+    use std::ffi::{CStr, OsString};
     use std::io::Cursor;
     use std::mem::discriminant;
 
@@ -156,7 +303,7 @@ mod tests {
 
     #[test]
     fn send_exec_events() {
-        let mut output = vec![0; 512];
+        let mut output = vec![0; 1024];
 
         // Send three events.
 
@@ -166,7 +313,17 @@ mod tests {
             let size = unsafe {
                 ExecEvent::serialize(
                     Cursor::new(&mut *buffer),
-                    1000 + idx as libc::pid_t,
+                    &ProcessInfo {
+                        pid: 1000 + idx as libc::pid_t,
+                        ppid: 800,
+                        pgid: 900,
+                        process_start: 5000 + idx as u64,
+                        histnum: 10 + idx as usize,
+                        depth: idx as usize,
+                        replaces_shell: idx == 1,
+                        redacted: idx == 2,
+                        shell_command: Some(CStr::from_bytes_with_nul(b"ls -l file*\0").unwrap()),
+                    },
                     libc::timespec {
                         tv_sec: 10000 + idx,
                         tv_nsec: 20000 + idx,
@@ -202,11 +359,22 @@ mod tests {
             };
 
             assert_eq!(event.pid, 1000 + idx as libc::pid_t);
+            assert_eq!(event.ppid, 800);
+            assert_eq!(event.pgid, 900);
+            assert_eq!(event.process_start, 5000 + idx as u64);
+            assert_eq!(event.histnum, 10 + idx as usize);
+            assert_eq!(event.depth, idx as usize);
+            assert_eq!(event.replaces_shell, idx == 1);
+            assert_eq!(event.redacted, idx == 2);
+            assert_eq!(event.shell_command, OsString::from("ls -l file*"));
             assert_eq!(event.monotonic_time.tv_sec, 10000 + idx);
             assert_eq!(event.monotonic_time.tv_nsec, 20000 + idx);
             assert_eq!(event.start_time.tv_sec, 1000000 + idx);
             assert_eq!(event.start_time.tv_nsec, 2000000 + idx);
             assert_eq!(event.filename, OsString::from("/bin/ls"));
+            assert_eq!(event.argc, 3);
+            assert_eq!(event.args_len, 9);
+            assert_eq!(event.omitted_bytes(), 0);
             assert_eq!(
                 event.args,
                 [
@@ -219,4 +387,44 @@ mod tests {
 
         assert!(events.next().is_none());
     }
+
+    #[test]
+    fn reject_incompatible_events() {
+        let rusage = unsafe { std::mem::zeroed() };
+        let finish_time = libc::timespec {
+            tv_sec: 1,
+            tv_nsec: 2,
+        };
+
+        let mut output = vec![0; 1024];
+        let mut written = 0;
+        for pid in [100, 200, 300] {
+            let cursor = Cursor::new(&mut output[written..]);
+            written += WaitEvent::serialize(cursor, pid, 0, 0, finish_time, rusage).unwrap();
+        }
+
+        let event_size = written / 3;
+        assert_eq!(&output[..3], &[EVENT_MAGIC, EVENT_VERSION, wait::WAIT_TAG]);
+
+        // Change the version of the second event. It is skipped, but the
+        // next one is still available.
+        output[event_size + 1] = EVENT_VERSION + 1;
+
+        let mut parser = EventsParser::new(&output[..written]);
+        let pids: Vec<_> = (&mut parser)
+            .map(|e| match e {
+                Event::Wait(w) => w.pid,
+                _ => panic!("invalid event"),
+            })
+            .collect();
+
+        assert_eq!(pids, [100, 300]);
+        assert_eq!(parser.rejected(), 1);
+
+        // Invalid data stops the parser.
+        output[event_size] = 0;
+        let mut parser = EventsParser::new(&output[..written]);
+        assert_eq!((&mut parser).count(), 1);
+        assert_eq!(parser.rejected(), 1);
+    }
 }
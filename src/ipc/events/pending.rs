@@ -0,0 +1,112 @@
+//! Wait events of the main shell, pending to be added to the history.
+//!
+//! bash can call the wait functions from its `SIGCHLD` handler, so their
+//! wrappers can't update the history directly: it needs to allocate memory,
+//! and the handler can interrupt a call to `malloc`. Instead, the events are
+//! stored in a fixed-size queue, owned by the process, and `collect_events`
+//! moves them to the history.
+//!
+//! `SIGCHLD` is blocked while the queue is accessed, so the handler never
+//! finds it in an inconsistent state.
+
+use super::WaitEvent;
+use crate::ipc::{block_sigchld, restore_sigmask};
+use std::mem::MaybeUninit;
+use std::{ptr, slice};
+
+/// Maximum number of events in the queue.
+const CAPACITY: usize = 256;
+
+/// Only the first `len` items of `events` are initialized.
+struct Queue {
+    events: MaybeUninit<[WaitEvent; CAPACITY]>,
+    len: usize,
+}
+
+static mut QUEUE: Queue = Queue {
+    events: MaybeUninit::uninit(),
+    len: 0,
+};
+
+/// Add an event to the queue.
+///
+/// Returns `false` if the queue is full.
+pub fn push(event: WaitEvent) -> bool {
+    let sigmask = block_sigchld();
+
+    let queue = unsafe { &mut *ptr::addr_of_mut!(QUEUE) };
+    let added = if queue.len < CAPACITY {
+        unsafe {
+            let slot = queue.events.as_mut_ptr().cast::<WaitEvent>().add(queue.len);
+            ptr::write(slot, event);
+        }
+
+        queue.len += 1;
+        true
+    } else {
+        false
+    };
+
+    restore_sigmask(&sigmask);
+    added
+}
+
+/// Returns `true` if there are no events in the queue.
+pub fn is_empty() -> bool {
+    let sigmask = block_sigchld();
+    let empty = unsafe { (*ptr::addr_of!(QUEUE)).len == 0 };
+    restore_sigmask(&sigmask);
+    empty
+}
+
+/// Remove all events from the queue, and send them to `f` in the order they
+/// were added.
+///
+/// `SIGCHLD` is blocked until all events are processed.
+pub fn drain<F>(mut f: F)
+where
+    F: FnMut(&WaitEvent),
+{
+    let sigmask = block_sigchld();
+
+    let queue = unsafe { &mut *ptr::addr_of_mut!(QUEUE) };
+    let events = unsafe { slice::from_raw_parts(queue.events.as_ptr().cast(), queue.len) };
+    for event in events {
+        f(event);
+    }
+
+    queue.len = 0;
+
+    restore_sigmask(&sigmask);
+}
+
+#[test]
+fn push_and_drain() {
+    let event = |pid| WaitEvent {
+        pid,
+        process_start: 0,
+        status: 0,
+        finish_time: libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        },
+        rusage: unsafe { std::mem::zeroed() },
+        job: None,
+    };
+
+    for pid in 0..CAPACITY {
+        assert!(push(event(pid as libc::pid_t)));
+    }
+
+    assert!(!push(event(-1)));
+    assert!(!is_empty());
+
+    let mut pids = Vec::new();
+    drain(|e| pids.push(e.pid as usize));
+    assert_eq!(pids, (0..CAPACITY).collect::<Vec<_>>());
+
+    assert!(push(event(1)));
+    drain(|e| assert_eq!(e.pid, 1));
+    drain(|_| panic!("queue should be empty"));
+    assert!(is_empty());
+}
@@ -2,10 +2,13 @@
 //!
 //! # Serialization Data
 //!
-//! * Process identifier (`pid_t`).
-//! * Exit code (`c_int`).
-//! * Finish time (`timestamp`).
-//! * Resources usage (`rusage`).
+//! All integers are encoded as little-endian `i64`/`u64` values.
+//!
+//! * Process identifier (`i64`).
+//! * Start time of the process, in clock ticks since boot (`u64`).
+//! * Exit code (`i64`).
+//! * Finish time (`timespec`, as two `i64`).
+//! * Resources usage (`rusage`, as a sequence of `i64`).
 
 use super::ioext::{ReadExt, WriteExt};
 use super::EventPayload;
@@ -18,9 +21,18 @@ pub const WAIT_TAG: u8 = 2;
 #[derive(Copy, Clone)]
 pub struct WaitEvent {
     pub pid: libc::pid_t,
+
+    /// Start time of the process, or `0` if it is not available. See
+    /// `ProcessInfo::process_start`.
+    pub process_start: u64,
+
     pub status: libc::c_int,
     pub finish_time: libc::timespec,
     pub rusage: libc::rusage,
+
+    /// Job number of the process in the main shell. It is not serialized,
+    /// since the job table of bash is only available in that process.
+    pub job: Option<usize>,
 }
 
 impl WaitEvent {
@@ -28,6 +40,7 @@ impl WaitEvent {
     pub fn serialize<T>(
         output: T,
         pid: libc::pid_t,
+        process_start: u64,
         status: libc::c_int,
         finish_time: libc::timespec,
         rusage: libc::rusage,
@@ -38,12 +51,11 @@ impl WaitEvent {
         let mut payload = EventPayload::new(output, WAIT_TAG)?;
         let output = payload.as_mut();
 
-        output.write_value(&WaitEvent {
-            pid,
-            status,
-            finish_time,
-            rusage,
-        })?;
+        output.write_i64(pid.into())?;
+        output.write_u64(process_start)?;
+        output.write_i64(status.into())?;
+        output.write_timespec(&finish_time)?;
+        output.write_rusage(&rusage)?;
 
         // Compute written bytes.
         let size = payload.finish()?;
@@ -53,6 +65,14 @@ impl WaitEvent {
     /// Deserialize data.
     pub fn deserialize(buf: &[u8]) -> io::Result<WaitEvent> {
         let mut reader = io::Cursor::new(buf);
-        unsafe { reader.read_value() }
+
+        Ok(WaitEvent {
+            pid: reader.read_i64()? as libc::pid_t,
+            process_start: reader.read_u64()?,
+            status: reader.read_i64()? as libc::c_int,
+            finish_time: reader.read_timespec()?,
+            rusage: reader.read_rusage()?,
+            job: None,
+        })
     }
 }
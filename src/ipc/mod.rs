@@ -4,15 +4,21 @@ pub mod sharedbuffer;
 use std::ffi::{CStr, CString};
 use std::io::{self, Write};
 use std::mem::MaybeUninit;
+use std::ptr;
 use std::sync::Once;
 use std::time::Duration;
 
-use bash_builtins::{error, variables::DynamicVariable};
+use bash_builtins::error;
+
+use crate::config::Setting;
 
 pub use sharedbuffer::{SharedBuffer, SharedBufferGuard};
 
-/// Size for the shared buffer;
-const SHARED_BUFFER_SIZE: usize = 16 * 1024;
+/// Size for the shared buffer.
+///
+/// Memory for the pages is allocated only when they are used, so a large
+/// size is needed only for long command lines.
+const SHARED_BUFFER_SIZE: usize = 1024 * 1024;
 
 /// Timeout to access the inner value of `max_cmdline` from the
 /// `TIMEHISTORY_CMDLINE_LIMIT` variable.
@@ -20,11 +26,34 @@ const TIMEOUT_CMDLINE_VAR: Duration = Duration::from_secs(1);
 
 /// Global reference to the shared buffer.
 pub fn global_shared_buffer(timeout: Duration) -> Option<SharedBufferGuard<'static>> {
+    global_buffer().and_then(|b| b.lock(timeout).ok())
+}
+
+/// File descriptor of the global shared buffer.
+pub fn global_shared_buffer_fd() -> Option<libc::c_int> {
+    global_buffer().map(SharedBuffer::fd)
+}
+
+/// Initialize the global shared buffer, if needed, and return a reference
+/// to it.
+///
+/// If the shell was launched from a tracked shell, the buffer is inherited
+/// from it.
+fn global_buffer() -> Option<&'static SharedBuffer> {
     static mut BUFFER: MaybeUninit<Option<SharedBuffer>> = MaybeUninit::uninit();
     static INIT: Once = Once::new();
 
     INIT.call_once(|| {
-        let sb = match SharedBuffer::new(SHARED_BUFFER_SIZE) {
+        let sb = match crate::procs::nested::inherited_buffer() {
+            Some(fd) => {
+                // The descriptor is passed again only to nested instances.
+                unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+                SharedBuffer::from_fd(fd)
+            }
+            None => SharedBuffer::new(SHARED_BUFFER_SIZE),
+        };
+
+        let sb = match sb {
             Ok(sb) => Some(sb),
 
             Err(e) => {
@@ -38,32 +67,55 @@ pub fn global_shared_buffer(timeout: Duration) -> Option<SharedBufferGuard<'stat
         }
     });
 
-    let buffer = unsafe { (&*BUFFER.as_ptr()).as_ref() };
-    buffer.and_then(|b| b.lock(timeout).ok())
+    unsafe { (*ptr::addr_of!(BUFFER).cast::<Option<SharedBuffer>>()).as_ref() }
+}
+
+/// Block `SIGCHLD` in the current thread, and return the previous mask.
+pub(crate) fn block_sigchld() -> libc::sigset_t {
+    unsafe {
+        let mut set = MaybeUninit::uninit();
+        let mut old = MaybeUninit::zeroed();
+        libc::sigemptyset(set.as_mut_ptr());
+        libc::sigaddset(set.as_mut_ptr(), libc::SIGCHLD);
+        libc::pthread_sigmask(libc::SIG_BLOCK, set.as_ptr(), old.as_mut_ptr());
+        old.assume_init()
+    }
+}
+
+/// Restore a signal mask returned by `block_sigchld`.
+pub(crate) fn restore_sigmask(sigmask: &libc::sigset_t) {
+    unsafe {
+        libc::pthread_sigmask(libc::SIG_SETMASK, sigmask, ptr::null_mut());
+    }
 }
 
 /// Dynamic variable to control the cmdline limit.
 pub struct CmdLineLimitVariable;
 
-impl DynamicVariable for CmdLineLimitVariable {
+impl Setting for CmdLineLimitVariable {
     fn get(&mut self) -> std::option::Option<CString> {
         let max_cmdline = global_shared_buffer(TIMEOUT_CMDLINE_VAR)?.max_cmdline();
 
         CString::new(max_cmdline.to_string()).ok()
     }
 
-    fn set(&mut self, value: &CStr) {
+    fn set(&mut self, value: &CStr) -> bool {
         let max_cmdline = match value.to_str().map(str::parse) {
             Ok(Ok(n)) => n,
 
             _ => {
                 let _ = writeln!(io::stderr(), "timehistory: invalid number");
-                return;
+                return false;
             }
         };
 
-        if let Some(mut buffer) = global_shared_buffer(TIMEOUT_CMDLINE_VAR) {
-            buffer.set_max_cmdline(max_cmdline);
+        match global_shared_buffer(TIMEOUT_CMDLINE_VAR) {
+            Some(mut buffer) => {
+                buffer.set_max_cmdline(max_cmdline);
+                true
+            }
+
+            None => false,
         }
     }
 }
@@ -7,16 +7,47 @@ use std::ptr;
 use std::slice;
 use std::time::Duration;
 
+use super::{block_sigchld, restore_sigmask};
+
 /// Minimum size for the shared buffer.
 const MIN_BUFFER_SIZE: usize = 4 * 1024;
 
+/// Value to identify a shared buffer created by this library.
+const BUFFER_MAGIC: u32 = u32::from_le_bytes(*b"TMHB");
+
+/// Version of the layout of the shared buffer.
+///
+/// It has to be increased if `SharedBufferHeader` is changed.
+const BUFFER_VERSION: u32 = 2;
+
 /// Default value for `max_cmdline`.
 const DEFAULT_MAX_CMDLINE: usize = 512;
 
+// Robust mutexes are not available in the `libc` crate.
+
+/// Value for `PTHREAD_MUTEX_ROBUST` in glibc and musl.
+const PTHREAD_MUTEX_ROBUST: libc::c_int = 1;
+
+extern "C" {
+    fn pthread_mutexattr_setrobust(
+        attr: *mut libc::pthread_mutexattr_t,
+        robustness: libc::c_int,
+    ) -> libc::c_int;
+
+    fn pthread_mutex_consistent(mutex: *mut libc::pthread_mutex_t) -> libc::c_int;
+}
+
 /// Buffer that can be shared between multiple processes.
 pub struct SharedBuffer {
     buf: *mut libc::c_void,
     len: usize,
+
+    /// File descriptor of the memory file, to share the buffer with other
+    /// programs.
+    fd: libc::c_int,
+
+    /// `true` if the buffer was created by this process.
+    owner: bool,
 }
 
 /// Data at the beginning of the buffer.
@@ -25,9 +56,15 @@ pub struct SharedBuffer {
 /// by the value of `N`.
 #[repr(C)]
 struct SharedBufferHeader<const N: usize> {
+    magic: u32,
+    version: u32,
     mutex: UnsafeCell<libc::pthread_mutex_t>,
     max_cmdline: usize,
     cursor: usize,
+
+    /// Incremented every time the buffer is cleared.
+    generation: u64,
+
     data: [u8; N],
 }
 
@@ -46,23 +83,37 @@ impl SharedBuffer {
             ));
         }
 
-        // Allocate memory.
+        // Allocate memory in a memory file, so it can be shared with other
+        // programs launched from the shell.
 
-        let buf = unsafe {
-            libc::mmap(
-                ptr::null_mut(),
-                len,
-                libc::PROT_READ | libc::PROT_WRITE,
-                libc::MAP_SHARED | libc::MAP_ANONYMOUS,
-                -1,
-                0,
-            )
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_memfd_create,
+                b"timehistory\0".as_ptr(),
+                libc::MFD_CLOEXEC,
+            ) as libc::c_int
         };
 
-        if buf == libc::MAP_FAILED {
+        if fd == -1 {
             return Err(io::Error::last_os_error());
         }
 
+        let buf = unsafe {
+            if libc::ftruncate(fd, len as libc::off_t) == 0 {
+                map_file(fd, len)
+            } else {
+                Err(io::Error::last_os_error())
+            }
+        };
+
+        let buf = match buf {
+            Ok(buf) => buf,
+            Err(e) => {
+                unsafe { libc::close(fd) };
+                return Err(e);
+            }
+        };
+
         // Initialize a process-shared mutex.
 
         unsafe {
@@ -73,6 +124,7 @@ impl SharedBuffer {
 
                         e => {
                             libc::munmap(buf, len);
+                            libc::close(fd);
                             return Err(io::Error::from_raw_os_error(e));
                         }
                     }
@@ -95,15 +147,66 @@ impl SharedBuffer {
                 libc::PTHREAD_PROCESS_SHARED
             ));
 
+            // If a process dies while holding the lock, the next call to
+            // `lock` can recover it, instead of waiting for the timeout.
+            check!(pthread_mutexattr_setrobust(
+                attr.as_mut_ptr(),
+                PTHREAD_MUTEX_ROBUST
+            ));
+
             header.mutex = UnsafeCell::new(libc::PTHREAD_MUTEX_INITIALIZER);
             check!(libc::pthread_mutex_init(header.mutex.get(), attr.as_ptr()));
 
             // Data for the underlying buffer.
+            header.magic = BUFFER_MAGIC;
+            header.version = BUFFER_VERSION;
             header.cursor = 0;
+            header.generation = 0;
             header.max_cmdline = DEFAULT_MAX_CMDLINE;
         }
 
-        Ok(SharedBuffer { buf, len })
+        Ok(SharedBuffer {
+            buf,
+            len,
+            fd,
+            owner: true,
+        })
+    }
+
+    /// Open a shared buffer created by another process, from the file
+    /// descriptor returned by [`SharedBuffer::fd`].
+    ///
+    /// The compatibility of the buffer is checked when it is locked.
+    pub fn from_fd(fd: libc::c_int) -> io::Result<Self> {
+        let len = unsafe {
+            let mut stat = MaybeUninit::<libc::stat>::zeroed();
+            if libc::fstat(fd, stat.as_mut_ptr()) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            stat.assume_init().st_size as usize
+        };
+
+        if len < MIN_BUFFER_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "buffer length too small",
+            ));
+        }
+
+        let buf = unsafe { map_file(fd, len)? };
+
+        Ok(SharedBuffer {
+            buf,
+            len,
+            fd,
+            owner: false,
+        })
+    }
+
+    /// File descriptor of the memory file with the buffer.
+    pub fn fd(&self) -> libc::c_int {
+        self.fd
     }
 
     /// Returns a raw pointer to the mutex in the shared buffer.
@@ -113,27 +216,83 @@ impl SharedBuffer {
     }
 
     /// Acquires a lock to the data in the shared buffer.
-    pub fn lock(&self, timeout: Duration) -> io::Result<SharedBufferGuard> {
+    ///
+    /// If the previous owner of the lock died before releasing it, the lock
+    /// is recovered. The data is still consistent in that case, because the
+    /// write cursor is updated (with [`SharedBufferGuard::advance`]) only
+    /// after a full event is written, so a partial event is discarded.
+    ///
+    /// It fails if the buffer was created by an incompatible version.
+    ///
+    /// `SIGCHLD` is blocked until the guard is dropped. The wrappers of the
+    /// wait functions, which can be called from the `SIGCHLD` handler, write
+    /// to the buffer, so the lock can't be held when the signal is handled.
+    pub fn lock(&self, timeout: Duration) -> io::Result<SharedBufferGuard<'_>> {
+        let header: &SharedBufferHeader<0> = unsafe { &*self.buf.cast() };
+        if header.magic != BUFFER_MAGIC || header.version != BUFFER_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "incompatible shared buffer",
+            ));
+        }
+
+        let sigmask = block_sigchld();
+
         let abstime = compute_abstime(timeout);
         let res = unsafe { libc::pthread_mutex_timedlock(self.mutex(), &abstime) };
 
+        let res = match res {
+            0 => 0,
+            libc::EOWNERDEAD => unsafe { pthread_mutex_consistent(self.mutex()) },
+            e => e,
+        };
+
         if res != 0 {
+            restore_sigmask(&sigmask);
             return Err(io::Error::from_raw_os_error(res));
         }
 
-        Ok(SharedBufferGuard(self))
+        Ok(SharedBufferGuard {
+            buffer: self,
+            sigmask,
+        })
     }
 }
 
 impl Drop for SharedBuffer {
     fn drop(&mut self) {
         unsafe {
-            libc::pthread_mutex_destroy(self.mutex());
+            if self.owner {
+                libc::pthread_mutex_destroy(self.mutex());
+            }
+
             libc::munmap(self.buf, self.len);
+
+            if self.owner {
+                libc::close(self.fd);
+            }
         }
     }
 }
 
+/// Map `len` bytes of a file in memory, with read and write access.
+unsafe fn map_file(fd: libc::c_int, len: usize) -> io::Result<*mut libc::c_void> {
+    let buf = libc::mmap(
+        ptr::null_mut(),
+        len,
+        libc::PROT_READ | libc::PROT_WRITE,
+        libc::MAP_SHARED,
+        fd,
+        0,
+    );
+
+    if buf == libc::MAP_FAILED {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(buf)
+}
+
 /// Compute a timeout based on the *realtime* clock.
 fn compute_abstime(timeout: Duration) -> libc::timespec {
     const NS_PER_SEC: libc::c_long = 1_000_000_000;
@@ -159,15 +318,20 @@ fn compute_abstime(timeout: Duration) -> libc::timespec {
 }
 
 /// Access to the data in the shared buffer.
-pub struct SharedBufferGuard<'a>(&'a SharedBuffer);
+pub struct SharedBufferGuard<'a> {
+    buffer: &'a SharedBuffer,
+
+    /// Signal mask before the lock was acquired.
+    sigmask: libc::sigset_t,
+}
 
 impl SharedBufferGuard<'_> {
     fn header(&self) -> &SharedBufferHeader<1> {
-        unsafe { &*self.0.buf.cast() }
+        unsafe { &*self.buffer.buf.cast() }
     }
 
     fn header_mut(&mut self) -> &mut SharedBufferHeader<1> {
-        unsafe { &mut *self.0.buf.cast() }
+        unsafe { &mut *self.buffer.buf.cast() }
     }
 
     fn data(&self) -> *const u8 {
@@ -179,12 +343,22 @@ impl SharedBufferGuard<'_> {
     }
 
     fn capacity(&self) -> usize {
-        self.0.len - mem::size_of::<SharedBufferHeader<0>>()
+        self.buffer.len - mem::size_of::<SharedBufferHeader<0>>()
     }
 
     /// Discard data in the shared buffer, and reset the write cursor to `0`.
     pub fn clear(&mut self) {
-        self.header_mut().cursor = 0;
+        let header = self.header_mut();
+        header.cursor = 0;
+        header.generation = header.generation.wrapping_add(1);
+    }
+
+    /// Returns the number of times the buffer was cleared.
+    ///
+    /// With the write cursor, it identifies a position in the data written
+    /// to the buffer.
+    pub fn generation(&self) -> u64 {
+        self.header().generation
     }
 
     /// Move the write cursor `n` bytes, usually called after updating the
@@ -220,6 +394,11 @@ impl SharedBufferGuard<'_> {
         unsafe { slice::from_raw_parts_mut(self.data_mut().add(cursor), len) }
     }
 
+    /// Returns the size of the shared buffer, including its header.
+    pub fn size(&self) -> usize {
+        self.buffer.len
+    }
+
     /// Returns the maximum number of bytes to add in a single command line.
     pub fn max_cmdline(&self) -> usize {
         self.header().max_cmdline
@@ -236,6 +415,8 @@ impl Drop for SharedBufferGuard<'_> {
         unsafe {
             libc::pthread_mutex_unlock(self.header().mutex.get());
         }
+
+        restore_sigmask(&self.sigmask);
     }
 }
 
@@ -245,8 +426,10 @@ mod tests {
     use std::ptr;
     use std::sync::{Arc, Barrier};
 
-    const EXPECTED_HEADER_SIZE: usize =
-        mem::size_of::<libc::pthread_mutex_t>() + mem::size_of::<usize>() * 2;
+    const EXPECTED_HEADER_SIZE: usize = mem::size_of::<u32>() * 2
+        + mem::size_of::<libc::pthread_mutex_t>()
+        + mem::size_of::<usize>() * 2
+        + mem::size_of::<u64>();
 
     #[test]
     fn send_data() {
@@ -311,6 +494,10 @@ mod tests {
         std::thread::spawn(move || {
             let lock: SharedBufferGuard = buffer2.lock(Duration::from_secs(1)).unwrap();
             barrier2.wait();
+
+            // Keep the thread alive, since the lock is recovered when its
+            // owner is terminated.
+            barrier2.wait();
             std::mem::forget(lock);
         });
 
@@ -320,5 +507,125 @@ mod tests {
         let lock_res = buffer.lock(Duration::from_millis(20));
         assert!((20..120).contains(&start.elapsed().as_millis()));
         assert_eq!(lock_res.err().unwrap().kind(), std::io::ErrorKind::TimedOut);
+
+        barrier.wait();
+    }
+
+    #[test]
+    fn open_from_fd() {
+        let buffer = SharedBuffer::new(MIN_BUFFER_SIZE).unwrap();
+        let other = SharedBuffer::from_fd(buffer.fd()).unwrap();
+
+        let mut lock = other.lock(Duration::from_millis(100)).unwrap();
+        lock.output()[..3].copy_from_slice(b"abc");
+        lock.advance(3);
+        drop(lock);
+        drop(other);
+
+        let lock = buffer.lock(Duration::from_millis(100)).unwrap();
+        assert_eq!(lock.input(), b"abc");
+    }
+
+    #[test]
+    fn reject_incompatible_buffer() {
+        let buffer = SharedBuffer::new(MIN_BUFFER_SIZE).unwrap();
+        assert!(buffer.lock(Duration::from_millis(100)).is_ok());
+
+        unsafe {
+            let header: &mut SharedBufferHeader<0> = &mut *buffer.buf.cast();
+            header.version += 1;
+        }
+
+        let lock_res = buffer.lock(Duration::from_millis(100));
+        assert_eq!(lock_res.err().unwrap().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn recover_lock_from_dead_process() {
+        let lock_timeout = Duration::from_secs(1);
+        let buffer = SharedBuffer::new(MIN_BUFFER_SIZE).unwrap();
+
+        let mut lock = buffer.lock(lock_timeout).unwrap();
+        lock.output()[..3].copy_from_slice(b"abc");
+        lock.advance(3);
+        drop(lock);
+
+        // The child writes a partial event, and exits without releasing
+        // the lock.
+        let pid = unsafe { libc::fork() };
+        assert!(pid >= 0);
+
+        if pid == 0 {
+            let mut lock = buffer.lock(lock_timeout).unwrap();
+            lock.output()[..3].copy_from_slice(b"XYZ");
+            std::mem::forget(lock);
+            unsafe { libc::_exit(0) };
+        }
+
+        assert_eq!(unsafe { libc::waitpid(pid, ptr::null_mut(), 0) }, pid);
+
+        let lock = buffer.lock(Duration::from_millis(100)).unwrap();
+        assert_eq!(lock.input(), b"abc");
+        drop(lock);
+
+        // The lock is usable after the recovery.
+        assert!(buffer.lock(Duration::from_millis(100)).is_ok());
+    }
+
+    #[test]
+    fn kill_writers() {
+        const RECORD_SIZE: usize = 8;
+
+        let lock_timeout = Duration::from_secs(1);
+        let buffer = SharedBuffer::new(MIN_BUFFER_SIZE * 4).unwrap();
+
+        let mut pids = [0; 8];
+        for (idx, pid) in pids.iter_mut().enumerate() {
+            *pid = unsafe { libc::fork() };
+            assert!(*pid >= 0);
+
+            if *pid == 0 {
+                // Write records with the same byte, slowly, until the buffer
+                // is filled.
+                loop {
+                    let mut lock = buffer.lock(lock_timeout).unwrap();
+                    let data = lock.output();
+
+                    if data.len() < RECORD_SIZE {
+                        drop(lock);
+                        unsafe { libc::_exit(0) };
+                    }
+
+                    for byte in &mut data[..RECORD_SIZE] {
+                        *byte = idx as u8 + b'A';
+                        std::thread::yield_now();
+                    }
+
+                    lock.advance(RECORD_SIZE);
+                    drop(lock);
+                    std::thread::yield_now();
+                }
+            }
+        }
+
+        // Kill half of the writers, likely while they hold the lock.
+        std::thread::sleep(Duration::from_millis(5));
+        for pid in &pids[..pids.len() / 2] {
+            unsafe { libc::kill(*pid, libc::SIGKILL) };
+        }
+
+        for pid in pids {
+            assert_eq!(unsafe { libc::waitpid(pid, ptr::null_mut(), 0) }, pid);
+        }
+
+        // Every record must be complete.
+        let lock = buffer.lock(lock_timeout).unwrap();
+        let data = lock.input();
+        assert!(!data.is_empty());
+        assert_eq!(data.len() % RECORD_SIZE, 0);
+
+        for record in data.chunks(RECORD_SIZE) {
+            assert!(record.iter().all(|b| *b == record[0]), "{:?}", record);
+        }
     }
 }
@@ -1,6 +1,8 @@
 //! Extensions for the JSON support.
 
 use crate::history::State;
+use crate::metrics::Metrics;
+use crate::waitstatus::{SignalName, WaitStatus};
 use serde::ser::{Serialize, SerializeMap, SerializeSeq, Serializer};
 use std::ffi::{OsStr, OsString};
 use std::os::unix::ffi::OsStrExt;
@@ -21,6 +23,17 @@ pub fn serialize_vec_os_string<S: Serializer>(
     seq.end()
 }
 
+pub fn serialize_is_some<S: Serializer, T>(value: &Option<T>, ser: S) -> Result<S::Ok, S::Error> {
+    ser.serialize_bool(value.is_some())
+}
+
+pub fn serialize_duration_secs<S: Serializer>(
+    duration: &std::time::Duration,
+    ser: S,
+) -> Result<S::Ok, S::Error> {
+    ser.serialize_f64(duration.as_secs_f64())
+}
+
 pub fn serialize_state<S: Serializer>(data: &State, ser: S) -> Result<S::Ok, S::Error> {
     let mut map = ser.serialize_map(Some(1))?;
 
@@ -29,10 +42,34 @@ pub fn serialize_state<S: Serializer>(data: &State, ser: S) -> Result<S::Ok, S::
             map.serialize_entry("running", &MonotonicTime(Timespec(start)))?;
         }
 
+        State::Stopped {
+            start,
+            since,
+            signal,
+        } => {
+            map.serialize_entry(
+                "stopped",
+                &Stopped {
+                    start,
+                    since,
+                    signal,
+                },
+            )?;
+        }
+
+        State::Continued { start } => {
+            map.serialize_entry("continued", &MonotonicTime(Timespec(start)))?;
+        }
+
+        State::Lost { start } => {
+            map.serialize_entry("lost", &MonotonicTime(Timespec(start)))?;
+        }
+
         State::Finished {
             running_time,
             status,
             rusage,
+            ..
         } => {
             map.serialize_entry(
                 "finished",
@@ -54,12 +91,22 @@ struct MonotonicTime<'a>(Timespec<'a>);
 
 struct Timespec<'a>(&'a libc::timespec);
 
+struct Stopped<'a> {
+    start: &'a libc::timespec,
+    since: &'a libc::timespec,
+    signal: &'a libc::c_int,
+}
+
 struct Finished<'a> {
     running_time: &'a Option<std::time::Duration>,
     status: &'a libc::c_int,
     rusage: &'a libc::rusage,
 }
 
+struct DecodedStatus(WaitStatus);
+
+struct DerivedMetrics<'a>(Metrics<'a>);
+
 struct Rusage<'a>(&'a libc::rusage);
 
 struct Timeval<'a>(&'a libc::timeval);
@@ -83,6 +130,17 @@ impl Serialize for Timespec<'_> {
     }
 }
 
+impl Serialize for Stopped<'_> {
+    fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+        let mut fields = ser.serialize_map(Some(4))?;
+        fields.serialize_entry("monotonic", &Timespec(self.start))?;
+        fields.serialize_entry("since", &Timespec(self.since))?;
+        fields.serialize_entry("signal", self.signal)?;
+        fields.serialize_entry("signal_name", &SignalName(*self.signal).to_string())?;
+        fields.end()
+    }
+}
+
 impl Serialize for Finished<'_> {
     fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
         let mut fields = ser.serialize_map(None)?;
@@ -90,7 +148,60 @@ impl Serialize for Finished<'_> {
             fields.serialize_entry("running_time_secs", &r.as_secs_f64())?;
         }
         fields.serialize_entry("status", self.status)?;
+        fields.serialize_entry("wait_status", &DecodedStatus(WaitStatus::new(*self.status)))?;
         fields.serialize_entry("resource_usage", &Rusage(self.rusage))?;
+        fields.serialize_entry(
+            "metrics",
+            &DerivedMetrics(Metrics::new(self.running_time.as_ref(), self.rusage)),
+        )?;
+        fields.end()
+    }
+}
+
+impl Serialize for DecodedStatus {
+    fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+        let status = &self.0;
+        let mut fields = ser.serialize_map(None)?;
+        fields.serialize_entry("type", status.kind())?;
+
+        if let WaitStatus::Exited(code) = status {
+            fields.serialize_entry("exit_code", code)?;
+        }
+
+        if let Some(signal) = status.signal() {
+            fields.serialize_entry("signal", &signal)?;
+            fields.serialize_entry("signal_name", &SignalName(signal).to_string())?;
+        }
+
+        fields.serialize_entry("core_dumped", &status.core_dumped())?;
+
+        if let Some(code) = status.shell_status() {
+            fields.serialize_entry("shell_status", &code)?;
+        }
+
+        fields.end()
+    }
+}
+
+impl Serialize for DerivedMetrics<'_> {
+    fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+        let metrics = &self.0;
+        let mut fields = ser.serialize_map(None)?;
+        fields.serialize_entry("cpu_time_secs", &metrics.cpu_time().as_secs_f64())?;
+
+        let rates = [
+            ("cores", metrics.cores()),
+            ("faults_per_sec", metrics.faults_rate()),
+            ("csw_per_sec", metrics.csw_rate()),
+            ("blocks_per_sec", metrics.blocks_rate()),
+        ];
+
+        for (key, value) in rates {
+            if let Some(value) = value {
+                fields.serialize_entry(key, &value)?;
+            }
+        }
+
         fields.end()
     }
 }
@@ -1,51 +1,173 @@
 //! timehistory bash builtin
 
-use bash_builtins::{builtin_metadata, variables, warning, Args, Builtin, BuiltinOptions};
+use bash_builtins::{builtin_metadata, error, variables, warning, Args, Builtin, BuiltinOptions};
 use bash_builtins::{Error::Usage, Result as BuiltinResult};
 
 use std::borrow::Cow;
+use std::collections::VecDeque;
 use std::io::{self, BufWriter, Write};
 
+use serde_json as json;
+
 builtin_metadata!(
     name = "timehistory",
     try_create = TimeHistory::new,
-    short_doc = "timehistory [-f FMT | -v | -j] [<n> | +<n>] | -s | -R",
+    short_doc = "timehistory [-f FMT | -v | -j | -A NAME] [-g] [-T PAT] [<n> | +<n> | -p] | -s [-j] | -R | -d SPEC | -t TAG [SPEC ...]",
     long_doc = "
         Displays information about the resources used by programs executed in
         the running shell.
 
         Options:
           -f FMT\tUse FMT as the format string for every history entry,
-                \tinstead of the default value.
+                \tinstead of the default value. @NAME uses a format
+                \tfrom the configuration file.
           -v\tUse the verbose format, similar to GNU time.
           -j\tPrint information as JSON format.
-          -s\tPrint the current configuration settings.
+          -A NAME\tWrite the fields of an entry in the associative
+                \tarray NAME, instead of printing them.
+          -g\tGroup the members of a pipeline in a single entry.
+          -T PAT\tList only the entries with a tag matching PAT.
+          -p\tList the entries finished since the previous -p.
+          -s\tPrint the current configuration settings. With -j,
+                \tthey are printed as JSON.
           -R\tRemove all entries in the history.
+          -d SPEC\tRemove the entries selected by SPEC.
+          -t TAG\tAttach TAG to the entries selected by the arguments.
 
         If <n> is given, it displays information for a specific history entry.
         The number for every entry is printed with the %n specifier in the
         format string. If the number is prefixed with a plus symbol (+<n>) it
         is the offset from the end of the list ('+1' is the last entry).
 
+        Delete entries:
+          SPEC in -d can be an entry number (<n> or +<n>), a range of
+          numbers (<n>-<m>), or a list of glob patterns separated by colons.
+          The patterns are matched against the filename of the program, its
+          first argument, and the whole command line. -d can be used multiple
+          times. The numbers of the other entries are not modified.
+
+        Tags:
+          -t attaches a tag (any text, like 'baseline' or a ticket id) to
+          the entries selected by the arguments, which use the same syntax
+          of SPEC in -d. If there are no arguments, the last entry is
+          tagged. -t can be used multiple times.
+
+          New entries are tagged with the value of TIMEHISTORY_TAG, if it
+          is not empty.
+
+          -T shows only the entries with a tag matching any of the glob
+          patterns in PAT, separated by colons. The %(tags) specifier
+          prints the tags of every entry.
+
+        Prompt:
+          -p lists only the entries finished since the previous call with
+          -p, including commands from subshells, so it can be used in
+          PROMPT_COMMAND. The default format is a summary of every command,
+          set by TIMEHISTORY_PROMPT_FORMAT, but -f, -v or -j can be used
+          too.
+
+          -p never waits if the history is busy. In that case, it prints
+          nothing, and the entries are printed in the next call.
+
+        Arrays:
+          -A writes the fields of the entry selected by <n> (or the last
+          entry, if <n> is omitted) in an associative array. The keys are
+          number, pid, pgid, job, histnum, depth, filename, args, argc,
+          shell_cmd, start_time, state, elapsed, elapsed_us, status, signal,
+          signal_name, user_time, sys_time, cpu_time, cores, maxrss, majflt,
+          minflt, inblock, oublock, nvcsw, nivcsw, stops, stopped_time, and
+          tags. The values use the same format of the equivalent specifiers
+          (see '-f help'). status is the value of $? for the command.
+
+          If the variable does not exist, it is created as a global
+          variable. Use 'local -A NAME' to create a local array in a
+          function. Other keys in the array are not modified.
+
+        Pipelines:
+          When job control is enabled, -g shows a single entry for every
+          pipeline. CPU times are added, and the elapsed time goes from the
+          first start to the last finish. The %(members) specifier prints the
+          numbers of the member entries, which can be displayed with <n>.
+          Commands executed by a subshell or a nested shell are not grouped.
+
         Format:
           Use '-f help' to get information about the formatting syntax.
 
         Settings:
           The following shell variables can be used to change the configuration:
 
-            TIMEHISTORY_FORMAT          Default format string.
+            TIMEHISTORY_FORMAT          Default format string. Invalid
+                                        values are rejected.
+            TIMEHISTORY_PROMPT_FORMAT   Default format string for -p.
             TIMEHISTORY_LIMIT           History limit.
             TIMEHISTORY_CMDLINE_LIMIT   Number of bytes to copy from the
                                         command line.
+            TIMEHISTORY_IGNORE          Programs not added to the history.
+            TIMEHISTORY_ONLY            If not empty, only these programs
+                                        are added to the history.
+            TIMEHISTORY_MIN_DURATION    Minimum time, in seconds, to keep
+                                        an entry when it is finished.
+            TIMEHISTORY_REDACT          Names of parameters with secret
+                                        values.
+            TIMEHISTORY_TAG             Tag for new entries.
+            TIMEHISTORY_NESTED          If set to a non-empty value other
+                                        than 0, non-interactive bash
+                                        instances launched from the shell
+                                        (like scripts) are tracked too.
+
+        Configuration file:
+          The settings can be defined in the file
+          $XDG_CONFIG_HOME/timehistory/config.toml (or
+          ~/.config/timehistory/config.toml), read when the builtin is
+          loaded. Its keys are the names of the variables, in lowercase and
+          without the TIMEHISTORY_ prefix, and its [formats] table defines
+          named formats for -f @NAME. Shell variables override the values
+          from the file. -s shows the source of every setting.
+
+        Last command:
+          The following read-only variables contain data from the most
+          recently finished entry. They are empty if there is no such entry.
+
+            TIMEHISTORY_LAST_ELAPSED    Elapsed time, in seconds (%e).
+            TIMEHISTORY_LAST_MAXRSS     Maximum resident set size, in
+                                        KiB (%M).
+            TIMEHISTORY_LAST_CPU        Percent of CPU (%P).
+
+        Filters:
+          TIMEHISTORY_IGNORE and TIMEHISTORY_ONLY are lists of glob patterns
+          separated by colons, like HISTIGNORE. They are matched against the
+          filename of the program and its first argument ('ls:*/git').
+
+        Redaction:
+          TIMEHISTORY_REDACT is a list of glob patterns separated by colons,
+          matched ignoring case against the names of parameters with secret
+          values, like '--password=x', '--token x', 'Authorization: x', or
+          query parameters in URLs. Passwords in URLs are always redacted.
+          Values are replaced by '<redacted>' before they are stored.
+
+        Nested shells:
+          When TIMEHISTORY_NESTED is enabled, the builtin is loaded in nested
+          bash instances through BASH_ENV, and their commands are added to
+          this history. The %(depth) specifier prints the nesting level of
+          the shell that executed every program. Only bash and scripts for
+          bash inherit the tracking, and the file descriptor of the buffer
+          with the events.
     ",
 );
 
+mod arrays;
 mod bytetables;
+mod config;
+mod filters;
 mod format;
 mod history;
 mod ipc;
 mod jsonext;
+mod metrics;
+mod pipeline;
 mod procs;
+mod redact;
+mod waitstatus;
 
 #[cfg(test)]
 mod tests;
@@ -54,15 +176,58 @@ use std::time::Duration;
 
 const DEFAULT_FORMAT: &str = "[header,table]%n\\t%(time:%X)\\t%P\\t%e\\t%C";
 
+/// Default format string for the prompt mode (`-p`).
+const DEFAULT_PROMPT_FORMAT: &str = "%n: %C (%es, %P CPU, %M KiB)";
+
 /// Shell variable to set the format string.
 const SHELL_VAR_FORMAT: &str = "TIMEHISTORY_FORMAT";
 
+/// Shell variable to set the format string of the prompt mode.
+const SHELL_VAR_PROMPT_FORMAT: &str = "TIMEHISTORY_PROMPT_FORMAT";
+
 /// Shell variable to set the history limit.
 const SHELL_VAR_LIMIT: &str = "TIMEHISTORY_LIMIT";
 
+/// Shell variable to set the patterns of the ignored programs.
+const SHELL_VAR_IGNORE: &str = "TIMEHISTORY_IGNORE";
+
+/// Shell variable to set the patterns of the tracked programs.
+const SHELL_VAR_ONLY: &str = "TIMEHISTORY_ONLY";
+
+/// Shell variable to set the minimum duration of the entries.
+const SHELL_VAR_MIN_DURATION: &str = "TIMEHISTORY_MIN_DURATION";
+
+/// Shell variable to set the patterns of secret parameters.
+const SHELL_VAR_REDACT: &str = "TIMEHISTORY_REDACT";
+
+/// Shell variable to set the tag of new entries.
+const SHELL_VAR_TAG: &str = "TIMEHISTORY_TAG";
+
+/// Read-only shell variable with the elapsed time of the last finished entry.
+const SHELL_VAR_LAST_ELAPSED: &str = "TIMEHISTORY_LAST_ELAPSED";
+
+/// Read-only shell variable with the maximum RSS of the last finished entry.
+const SHELL_VAR_LAST_MAXRSS: &str = "TIMEHISTORY_LAST_MAXRSS";
+
+/// Read-only shell variable with the CPU usage of the last finished entry.
+const SHELL_VAR_LAST_CPU: &str = "TIMEHISTORY_LAST_CPU";
+
 /// Shell variable to set the command line limit.
 const SHELL_VAR_CMDLINE_LIMIT: &str = "TIMEHISTORY_CMDLINE_LIMIT";
 
+/// Dynamic variables that can be set from the configuration file.
+const TRACKED_VARS: &[&str] = &[
+    SHELL_VAR_FORMAT,
+    SHELL_VAR_PROMPT_FORMAT,
+    SHELL_VAR_LIMIT,
+    SHELL_VAR_CMDLINE_LIMIT,
+    SHELL_VAR_IGNORE,
+    SHELL_VAR_ONLY,
+    SHELL_VAR_MIN_DURATION,
+    SHELL_VAR_REDACT,
+    SHELL_VAR_TAG,
+];
+
 struct TimeHistory;
 
 #[derive(BuiltinOptions)]
@@ -76,15 +241,37 @@ enum Opt<'a> {
     #[opt = 'j']
     Json,
 
+    #[opt = 'g']
+    Group,
+
+    #[opt = 'A']
+    Array(&'a str),
+
     #[opt = 'R']
     Reset,
 
+    #[opt = 'p']
+    Prompt,
+
+    #[opt = 'd']
+    Delete(&'a str),
+
+    #[opt = 't']
+    Tag(&'a str),
+
+    #[opt = 'T']
+    TagFilter(&'a str),
+
     #[opt = 's']
     Setting(Option<&'a str>),
 
     #[cfg(feature = "option-for-panics")]
     #[opt = 'P']
     Panic,
+
+    #[cfg(feature = "option-for-exec-tests")]
+    #[opt = 'X']
+    ExecWith(&'a str),
 }
 
 enum Output {
@@ -95,7 +282,10 @@ enum Output {
 
 enum Action {
     List,
+    Prompt,
     Reset,
+    Delete(Vec<(String, history::Selection)>),
+    Tag(Vec<String>, Vec<(String, history::Selection)>),
     ShowItem(usize),
 }
 
@@ -105,35 +295,102 @@ impl TimeHistory {
             return Err("shared buffer unavailable".into());
         }
 
-        variables::bind(SHELL_VAR_LIMIT, history::LimitVariable)?;
-        variables::bind(SHELL_VAR_CMDLINE_LIMIT, ipc::CmdLineLimitVariable)?;
+        let main_shell = procs::nested::inherited_buffer().is_none();
+
+        // bash resets the variables when they are bound, so the values
+        // defined before loading the builtin are applied after the
+        // configuration file.
+        let shell_values = if main_shell {
+            TRACKED_VARS
+                .iter()
+                .filter_map(|var| variables::find_as_string(var).map(|value| (*var, value)))
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        macro_rules! bind_tracked {
+            ($name:expr, $var:expr) => {
+                variables::bind($name, config::Tracked::new($name, $var))?
+            };
+        }
+
+        bind_tracked!(SHELL_VAR_FORMAT, format::FormatVariable::List);
+        bind_tracked!(SHELL_VAR_PROMPT_FORMAT, format::FormatVariable::Prompt);
+        bind_tracked!(SHELL_VAR_LIMIT, history::LimitVariable);
+        bind_tracked!(SHELL_VAR_CMDLINE_LIMIT, ipc::CmdLineLimitVariable);
+        bind_tracked!(SHELL_VAR_IGNORE, filters::FilterVariable::Ignore);
+        bind_tracked!(SHELL_VAR_ONLY, filters::FilterVariable::Only);
+        bind_tracked!(SHELL_VAR_MIN_DURATION, filters::FilterVariable::MinDuration);
+        bind_tracked!(SHELL_VAR_REDACT, redact::RedactVariable);
+        bind_tracked!(SHELL_VAR_TAG, history::TagVariable);
+        variables::bind(SHELL_VAR_LAST_ELAPSED, history::LastEntryVariable::Elapsed)?;
+        variables::bind(SHELL_VAR_LAST_MAXRSS, history::LastEntryVariable::MaxRss)?;
+        variables::bind(SHELL_VAR_LAST_CPU, history::LastEntryVariable::Cpu)?;
 
         procs::replace_functions()?;
 
-        unsafe {
-            history::OWNER_PID = libc::getpid();
+        // In a nested shell, the history is stored in the main shell, and
+        // all events are sent through the shared buffer.
+        if main_shell {
+            config::load(shell_values);
+
+            unsafe {
+                history::OWNER_PID = libc::getpid();
+            }
+        } else {
+            procs::nested::close_init_script();
+
+            if let Some(patterns) = procs::nested::inherited_redact_patterns() {
+                redact::set_patterns(&patterns);
+            }
         }
 
         Ok(TimeHistory)
     }
 }
 
+impl Drop for TimeHistory {
+    fn drop(&mut self) {
+        // TIMEHISTORY_FORMAT was a regular variable in previous versions, so
+        // the value assigned from the shell is kept after removing the
+        // builtin.
+        if config::source(SHELL_VAR_FORMAT) != config::Source::Shell {
+            return;
+        }
+
+        let format = match history::HISTORY.try_lock() {
+            Ok(history) => history.format.clone().unwrap_or_default(),
+            Err(_) => return,
+        };
+
+        // The dynamic variable is replaced by a regular one, so it is not
+        // removed when the shared object is unloaded.
+        if variables::unset(SHELL_VAR_FORMAT) {
+            let _ = variables::set(SHELL_VAR_FORMAT, format);
+        }
+    }
+}
+
 impl Builtin for TimeHistory {
     fn call(&mut self, args: &mut Args) -> BuiltinResult<()> {
         let mut table_writer;
         let stdout_handle = io::stdout();
         let mut output = &mut BufWriter::new(stdout_handle.lock()) as &mut dyn Write;
 
-        let mut history = match crate::ipc::events::collect_events(true) {
-            Some(history) => history,
-            None => return Err(bash_builtins::Error::ExitCode(1)),
-        };
-
         // Extract options from command-line.
 
         let mut exit_after_options = false;
+        let mut show_config = false;
+        let mut new_limit = None;
         let mut output_format = None;
         let mut action = Action::List;
+        let mut group_pipelines = false;
+        let mut tag_filter = None;
+        let mut array_name = None;
+
+        #[cfg(feature = "option-for-exec-tests")]
+        let mut exec_with = None;
 
         macro_rules! set_format {
             ($($t:tt)+) => {{
@@ -146,6 +403,21 @@ impl Builtin for TimeHistory {
             }}
         }
 
+        // Only one action can be used in every invocation.
+        macro_rules! set_action {
+            ($new:expr) => {{
+                let new = $new;
+                if !matches!(action, Action::List)
+                    && std::mem::discriminant(&action) != std::mem::discriminant(&new)
+                {
+                    bash_builtins::log::show_usage();
+                    return Err(Usage);
+                }
+
+                action = new;
+            }};
+        }
+
         for opt in args.options() {
             match opt? {
                 Opt::Format("help") => {
@@ -153,21 +425,50 @@ impl Builtin for TimeHistory {
                     exit_after_options = true;
                 }
 
-                Opt::Format(fmt) => set_format!(Format(fmt.to_owned())),
+                Opt::Format(fmt) => match fmt.strip_prefix('@') {
+                    None => set_format!(Format(fmt.to_owned())),
+
+                    Some(name) => match config::named_format(name) {
+                        Some(fmt) => set_format!(Format(fmt.to_owned())),
+                        None => {
+                            error!("{}: unknown format", fmt);
+                            return Err(Usage);
+                        }
+                    },
+                },
 
                 Opt::VerboseFormat => set_format!(Verbose),
 
                 Opt::Json => set_format!(Json),
 
-                Opt::Reset => action = Action::Reset,
+                Opt::Group => group_pipelines = true,
+
+                Opt::Array(name) => array_name = Some(name.to_owned()),
+
+                Opt::TagFilter(patterns) => {
+                    let patterns = std::ffi::CString::new(patterns).unwrap_or_default();
+                    tag_filter = Some(filters::Patterns::parse(&patterns));
+                }
+
+                Opt::Reset => set_action!(Action::Reset),
+
+                Opt::Prompt => set_action!(Action::Prompt),
+
+                Opt::Delete(spec) => {
+                    let selection = (spec.to_owned(), history::Selection::parse(spec));
+                    match &mut action {
+                        Action::Delete(selections) => selections.push(selection),
+                        _ => set_action!(Action::Delete(vec![selection])),
+                    }
+                }
+
+                Opt::Tag(tag) => match &mut action {
+                    Action::Tag(tags, _) => tags.push(tag.to_owned()),
+                    _ => set_action!(Action::Tag(vec![tag.to_owned()], Vec::new())),
+                },
 
                 Opt::Setting(None) => {
-                    self.print_config(
-                        &mut output,
-                        &history,
-                        ipc::global_shared_buffer(Duration::from_millis(100))
-                            .map(|buf| buf.max_cmdline()),
-                    )?;
+                    show_config = true;
                     exit_after_options = true;
                 }
 
@@ -177,7 +478,8 @@ impl Builtin for TimeHistory {
                     let mut parts = setting.splitn(2, '=');
                     match (parts.next(), parts.next()) {
                         (Some("limit"), Some(value)) => {
-                            history.set_size(value.parse()?);
+                            new_limit = Some(value.parse()?);
+                            config::set_source(SHELL_VAR_LIMIT, config::Source::Shell);
                         }
 
                         (Some("format"), Some(value)) => {
@@ -200,7 +502,79 @@ impl Builtin for TimeHistory {
 
                 #[cfg(feature = "option-for-panics")]
                 Opt::Panic => panic!("-P"),
+
+                #[cfg(feature = "option-for-exec-tests")]
+                Opt::ExecWith(function) => exec_with = Some(function.to_owned()),
+            }
+        }
+
+        let prompt = matches!(action, Action::Prompt);
+
+        // Values assigned with `local`, or in the prefix of the command, are
+        // regular variables, so they are not stored in the history. They
+        // have to be read before locking it, since the dynamic variable
+        // needs the lock to get its value.
+        let visible_format = match output_format {
+            None if prompt => variables::find_as_string(SHELL_VAR_PROMPT_FORMAT),
+            None => variables::find_as_string(SHELL_VAR_FORMAT),
+            Some(_) => None,
+        };
+
+        // In a nested shell, the history is stored in the main shell.
+        if procs::nested::inherited_buffer().is_some() {
+            if prompt {
+                return Ok(());
             }
+
+            error!("history is stored in the main shell");
+            return Err(bash_builtins::Error::ExitCode(1));
+        }
+
+        // The prompt mode never waits for the locks. If they are busy, the
+        // finished entries are printed in the next prompt.
+        let history = if prompt {
+            crate::ipc::events::try_collect_events()
+        } else {
+            crate::ipc::events::collect_events(true)
+        };
+
+        let mut history = match history {
+            Some(history) => history,
+            None if prompt => return Ok(()),
+            None => return Err(bash_builtins::Error::ExitCode(1)),
+        };
+
+        if let Some(limit) = new_limit {
+            history.set_size(limit);
+        }
+
+        if show_config {
+            let buffer = ipc::global_shared_buffer(Duration::from_millis(100))
+                .map(|buf| (buf.size(), buf.max_cmdline()));
+
+            match output_format {
+                None => self.print_config(&mut output, &history, buffer.map(|b| b.1))?,
+                Some(Output::Json) => self.print_config_json(&mut output, &history, buffer)?,
+                Some(_) => {
+                    bash_builtins::log::show_usage();
+                    return Err(Usage);
+                }
+            }
+        }
+
+        #[cfg(feature = "option-for-exec-tests")]
+        if let Some(function) = exec_with {
+            // The history has to be unlocked, so the wrapper for `waitpid`
+            // can update the new entry.
+            drop(history);
+
+            let args: Vec<_> = args.raw_arguments().map(|a| a.to_owned()).collect();
+            let status = procs::exec_with(&function, &args)?;
+
+            return match waitstatus::WaitStatus::new(status).shell_status() {
+                Some(0) | None => Ok(()),
+                Some(n) => Err(bash_builtins::Error::ExitCode(n)),
+            };
         }
 
         if exit_after_options {
@@ -214,17 +588,82 @@ impl Builtin for TimeHistory {
             if let Some(arg) = args.string_arguments().next() {
                 let arg = arg?;
                 let number = match arg.parse()? {
-                    n if n > 0 && arg.starts_with('+') => history.offset_number(n),
+                    n if n > 0 && arg.starts_with('+') => history.offset_number(n).unwrap_or(0),
                     n => n,
                 };
                 action = Action::ShowItem(number);
             }
         }
 
+        // Entries to tag. The default is the last one.
+        if let Action::Tag(_, selections) = &mut action {
+            for arg in args.string_arguments() {
+                let arg = arg?;
+                selections.push((arg.to_owned(), history::Selection::parse(arg)));
+            }
+
+            if selections.is_empty() {
+                selections.push(("+1".to_owned(), history::Selection::Offset(1)));
+            }
+        }
+
         args.finished()?;
 
+        // Entries finished since the previous prompt. They are discarded
+        // only if the output is written, so they are not lost if it fails.
+        let finished = if prompt {
+            Some(history.finished().to_vec())
+        } else {
+            None
+        };
+
+        let pipelines;
+        let entries = if group_pipelines {
+            pipelines = pipeline::group(&history.entries, unsafe { libc::getpgrp() });
+            &pipelines
+        } else {
+            &history.entries
+        };
+
+        // Write the fields of an entry in an array, instead of printing them.
+        if let Some(name) = array_name {
+            let number = match action {
+                Action::List if output_format.is_none() => history.offset_number(1).unwrap_or(0),
+                Action::ShowItem(number) if output_format.is_none() => number,
+                _ => {
+                    bash_builtins::log::show_usage();
+                    return Err(Usage);
+                }
+            };
+
+            let entry = match find_entry(entries, number) {
+                Some(entry) => entry,
+                None => {
+                    error!("{}: history position out of range", number);
+                    return Err(bash_builtins::Error::ExitCode(1));
+                }
+            };
+
+            return match arrays::set_entry_fields(&name, entry) {
+                Ok(()) => Ok(()),
+
+                Err(e) => {
+                    error!("{}: {}", name, e);
+                    Err(bash_builtins::Error::ExitCode(1))
+                }
+            };
+        }
+
         let format = match &output_format {
-            None => Some(Self::default_format()),
+            // Deletions and tags don't print anything.
+            _ if matches!(action, Action::Delete(_) | Action::Tag(..)) => None,
+
+            None => Some(
+                match visible_format.as_ref().and_then(|f| f.to_str().ok()) {
+                    Some(f) if !f.is_empty() => Self::named_format(f).into(),
+                    _ => Self::default_format(&history, prompt).into(),
+                },
+            ),
             Some(Output::Format(f)) => Some(Cow::Borrowed(f.as_ref())),
             Some(Output::Verbose) => Some(include_str!("format/verbose.fmt").into()),
             Some(Output::Json) => None,
@@ -245,12 +684,32 @@ impl Builtin for TimeHistory {
             }
         }
 
+        let mut listed: Vec<&history::Entry> = match &finished {
+            Some(numbers) => {
+                // With -g, multiple members can be in the same entry.
+                let mut listed: Vec<&history::Entry> = Vec::new();
+                for entry in numbers.iter().filter_map(|n| find_entry(entries, *n)) {
+                    if !listed.iter().any(|e| std::ptr::eq(*e, entry)) {
+                        listed.push(entry);
+                    }
+                }
+
+                listed
+            }
+
+            None => entries.iter().rev().collect(),
+        };
+
+        if let Some(patterns) = &tag_filter {
+            listed.retain(|entry| history::tags_match(patterns, entry));
+        }
+
         match (action, format.map(|f| f.format)) {
-            (Action::List, None) => {
+            (Action::List | Action::Prompt, None) => {
                 let mut first = true;
                 output.write_all(b"[\n")?;
 
-                for entry in history.entries.iter().rev() {
+                for entry in listed {
                     if !std::mem::replace(&mut first, false) {
                         output.write_all(b",\n")?;
                     }
@@ -261,8 +720,8 @@ impl Builtin for TimeHistory {
                 output.write_all(b"\n]\n")?;
             }
 
-            (Action::List, Some(fmt)) => {
-                for entry in history.entries.iter().rev() {
+            (Action::List | Action::Prompt, Some(fmt)) => {
+                for entry in listed {
                     format::render(entry, fmt, &mut output)?;
                     output.write_all(b"\n")?;
                 }
@@ -272,8 +731,16 @@ impl Builtin for TimeHistory {
                 history.entries.clear();
             }
 
+            (Action::Delete(selections), _) => {
+                update_entries(&selections, |selection| history.delete(selection))?;
+            }
+
+            (Action::Tag(tags, selections), _) => {
+                update_entries(&selections, |selection| history.annotate(selection, &tags))?;
+            }
+
             (Action::ShowItem(number), output_format) => {
-                if let Some(entry) = history.entries.iter().find(|e| e.number == number) {
+                if let Some(entry) = find_entry(entries, number) {
                     match output_format {
                         None => serde_json::to_writer(&mut output, entry)?,
                         Some(fmt) => format::render(entry, fmt, &mut output)?,
@@ -286,10 +753,44 @@ impl Builtin for TimeHistory {
 
         output.flush()?;
 
+        if prompt {
+            history.clear_finished();
+        }
+
         Ok(())
     }
 }
 
+/// Apply `update` to the entries of every selection. `update` returns the
+/// number of modified entries.
+///
+/// Like `history -d`, it fails if a number is not found.
+fn update_entries(
+    selections: &[(String, history::Selection)],
+    mut update: impl FnMut(&history::Selection) -> usize,
+) -> BuiltinResult<()> {
+    let mut not_found = false;
+    for (spec, selection) in selections {
+        if update(selection) == 0 && !matches!(selection, history::Selection::Patterns(_)) {
+            error!("{}: history position out of range", spec);
+            not_found = true;
+        }
+    }
+
+    if not_found {
+        return Err(bash_builtins::Error::ExitCode(1));
+    }
+
+    Ok(())
+}
+
+/// Find an entry by its number, or by the number of a member of its pipeline.
+fn find_entry(entries: &VecDeque<history::Entry>, number: usize) -> Option<&history::Entry> {
+    entries
+        .iter()
+        .find(|e| e.number == number || e.pipeline.iter().any(|m| m.number == number))
+}
+
 impl TimeHistory {
     fn print_config(
         &self,
@@ -297,26 +798,135 @@ impl TimeHistory {
         history: &history::History,
         max_cmdline: Option<usize>,
     ) -> io::Result<()> {
-        write!(
-            &mut output,
-            "\
-             TIMEHISTORY_FORMAT        = {}\n\
-             TIMEHISTORY_LIMIT         = {}\n\
-            ",
-            Self::default_format(),
-            history.size(),
+        if let Some(path) = config::get().and_then(|c| c.path.as_ref()) {
+            writeln!(&mut output, "# {}", path.display())?;
+        }
+
+        let mut setting = |name: &str, value: &dyn std::fmt::Display| {
+            let source = format!("({})", config::source(name));
+            writeln!(&mut output, "{:25} {:9} = {}", name, source, value)
+        };
+
+        let filters = &history.filters;
+
+        setting(SHELL_VAR_FORMAT, &Self::default_format(history, false))?;
+        setting(
+            SHELL_VAR_PROMPT_FORMAT,
+            &Self::default_format(history, true),
         )?;
+        setting(SHELL_VAR_LIMIT, &history.size())?;
 
         if let Some(max_cmdline) = max_cmdline {
-            writeln!(&mut output, "TIMEHISTORY_CMDLINE_LIMIT = {}", max_cmdline)?;
+            setting(SHELL_VAR_CMDLINE_LIMIT, &max_cmdline)?;
+        }
+
+        setting(SHELL_VAR_IGNORE, &filters.ignore.source().to_string_lossy())?;
+        setting(SHELL_VAR_ONLY, &filters.only.source().to_string_lossy())?;
+        setting(
+            SHELL_VAR_MIN_DURATION,
+            &filters.min_duration.map_or(0.0, |d| d.as_secs_f64()),
+        )?;
+        setting(
+            SHELL_VAR_REDACT,
+            &redact::redactor().patterns().source().to_string_lossy(),
+        )?;
+        setting(SHELL_VAR_TAG, &history.tag.as_deref().unwrap_or_default())?;
+        setting(
+            procs::nested::SHELL_VAR_NESTED,
+            &u8::from(procs::nested::enabled()),
+        )?;
+
+        if let Some(config) = config::get() {
+            for (name, format) in &config.formats {
+                writeln!(
+                    &mut output,
+                    "{:25} {:9} = {}",
+                    format!("@{}", name),
+                    "(config)",
+                    format
+                )?;
+            }
         }
 
         Ok(())
     }
 
-    fn default_format() -> Cow<'static, str> {
-        variables::find_as_string(SHELL_VAR_FORMAT)
-            .and_then(|s| s.into_string().ok().map(Cow::Owned))
-            .unwrap_or_else(|| DEFAULT_FORMAT.into())
+    /// Print the configuration settings as JSON.
+    ///
+    /// `buffer` contains the size of the shared buffer and the command line
+    /// limit, if the buffer is available.
+    fn print_config_json(
+        &self,
+        mut output: impl Write,
+        history: &history::History,
+        buffer: Option<(usize, usize)>,
+    ) -> io::Result<()> {
+        let setting = |name: &str, value: json::Value| {
+            json::json!({
+                "value": value,
+                "source": config::source(name).to_string(),
+            })
+        };
+
+        let filters = &history.filters;
+        let default_format = Self::default_format(history, false);
+        let config = config::get();
+
+        let settings = json::json!({
+            "config_file": config.and_then(|c| c.path.as_ref()),
+            "buffer_size": buffer.map(|b| b.0),
+            "format": format::FormatOptions::parse(default_format),
+            "formats": config.map(|c| &c.formats),
+            "variables": {
+                SHELL_VAR_FORMAT: setting(SHELL_VAR_FORMAT, default_format.into()),
+                SHELL_VAR_PROMPT_FORMAT: setting(
+                    SHELL_VAR_PROMPT_FORMAT,
+                    Self::default_format(history, true).into(),
+                ),
+                SHELL_VAR_LIMIT: setting(SHELL_VAR_LIMIT, history.size().into()),
+                SHELL_VAR_CMDLINE_LIMIT: setting(SHELL_VAR_CMDLINE_LIMIT, json::json!(buffer.map(|b| b.1))),
+                SHELL_VAR_IGNORE: setting(SHELL_VAR_IGNORE, filters.ignore.source().to_string_lossy().into()),
+                SHELL_VAR_ONLY: setting(SHELL_VAR_ONLY, filters.only.source().to_string_lossy().into()),
+                SHELL_VAR_MIN_DURATION: setting(
+                    SHELL_VAR_MIN_DURATION,
+                    filters.min_duration.map_or(0.0, |d| d.as_secs_f64()).into(),
+                ),
+                SHELL_VAR_REDACT: setting(
+                    SHELL_VAR_REDACT,
+                    redact::redactor().patterns().source().to_string_lossy().into(),
+                ),
+                SHELL_VAR_TAG: setting(SHELL_VAR_TAG, history.tag.as_deref().unwrap_or_default().into()),
+                procs::nested::SHELL_VAR_NESTED: setting(
+                    procs::nested::SHELL_VAR_NESTED,
+                    procs::nested::enabled().into(),
+                ),
+            },
+        });
+
+        json::to_writer(&mut output, &settings)?;
+        output.write_all(b"\n")
+    }
+
+    /// Format from `TIMEHISTORY_FORMAT`, or from `TIMEHISTORY_PROMPT_FORMAT`
+    /// if `prompt` is `true`.
+    fn default_format(history: &history::History, prompt: bool) -> &str {
+        let format = if prompt {
+            history
+                .prompt_format
+                .as_deref()
+                .unwrap_or(DEFAULT_PROMPT_FORMAT)
+        } else {
+            history.format.as_deref().unwrap_or(DEFAULT_FORMAT)
+        };
+
+        Self::named_format(format)
+    }
+
+    /// Replace `@name` with the named format, if it exists.
+    fn named_format(format: &str) -> &str {
+        match format.strip_prefix('@').and_then(config::named_format) {
+            Some(named) => named,
+            None => format,
+        }
     }
 }
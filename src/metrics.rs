@@ -0,0 +1,73 @@
+//! Metrics derived from the resources used by a finished process.
+
+use crate::history::State;
+use std::time::Duration;
+
+/// Values computed from the `rusage` and the running time of a process.
+pub struct Metrics<'a> {
+    running_time: Option<&'a Duration>,
+    rusage: &'a libc::rusage,
+}
+
+impl<'a> Metrics<'a> {
+    pub fn new(running_time: Option<&'a Duration>, rusage: &'a libc::rusage) -> Self {
+        Metrics {
+            running_time,
+            rusage,
+        }
+    }
+
+    /// Returns the metrics for a history entry, if it is finished.
+    pub fn from_state(state: &'a State) -> Option<Self> {
+        match state {
+            State::Finished {
+                running_time,
+                rusage,
+                ..
+            } => Some(Metrics::new(running_time.as_ref(), rusage)),
+
+            _ => None,
+        }
+    }
+
+    /// Total CPU time (user + system).
+    pub fn cpu_time(&self) -> Duration {
+        fn duration(tv: &libc::timeval) -> Duration {
+            Duration::new(tv.tv_sec as u64, tv.tv_usec as u32 * 1000)
+        }
+
+        duration(&self.rusage.ru_utime) + duration(&self.rusage.ru_stime)
+    }
+
+    /// Average number of CPU cores used while the process was running.
+    pub fn cores(&self) -> Option<f64> {
+        self.per_second(self.cpu_time().as_secs_f64())
+    }
+
+    /// Page faults (major and minor) per second.
+    pub fn faults_rate(&self) -> Option<f64> {
+        self.per_second((self.rusage.ru_majflt + self.rusage.ru_minflt) as f64)
+    }
+
+    /// Context switches (voluntary and involuntary) per second.
+    pub fn csw_rate(&self) -> Option<f64> {
+        self.per_second((self.rusage.ru_nvcsw + self.rusage.ru_nivcsw) as f64)
+    }
+
+    /// File system blocks (inputs and outputs) per second.
+    pub fn blocks_rate(&self) -> Option<f64> {
+        self.per_second((self.rusage.ru_inblock + self.rusage.ru_oublock) as f64)
+    }
+
+    /// Divide `value` by the running time, in seconds.
+    ///
+    /// Returns `None` if the running time is unknown or zero.
+    fn per_second(&self, value: f64) -> Option<f64> {
+        let secs = self.running_time?.as_secs_f64();
+        if secs > 0.0 {
+            Some(value / secs)
+        } else {
+            None
+        }
+    }
+}
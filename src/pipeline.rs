@@ -0,0 +1,458 @@
+//! Group the members of a pipeline in a single entry.
+
+use crate::history::{duration, Entry, JobControl, State};
+use std::collections::{HashMap, VecDeque};
+use std::ffi::OsString;
+use std::mem;
+
+/// Group entries that belong to the same pipeline.
+///
+/// When job control is enabled, bash creates a process group for every
+/// pipeline. Processes started when job control is disabled share the process
+/// group of the shell (`shell_pgid`), so they are never grouped.
+///
+/// A process group can also contain programs that are not members of the
+/// pipeline, like the commands of a subshell (`(a; b)`) or of a nested
+/// shell. They are forked by the process group leader instead of the shell
+/// that created the pipeline, so they are never grouped. Programs that
+/// replace a shell process are not grouped either.
+///
+/// The last command of a subshell is executed by the process of the subshell,
+/// so, if the leader of a pipeline forked other programs, the leader is shown
+/// as `( … )` in the command of the pipeline.
+///
+/// Entries are expected in the order used by `History::entries` (most recent
+/// first), and the returned list uses the same order.
+pub fn group<'a, I>(entries: I, shell_pgid: libc::pid_t) -> VecDeque<Entry>
+where
+    I: IntoIterator<Item = &'a Entry>,
+{
+    let mut singles = Vec::new();
+    let mut members = Vec::new();
+    let mut children = Vec::new();
+
+    for entry in entries {
+        if entry.pgid != shell_pgid && entry.ppid == entry.pgid {
+            children.push(entry);
+        }
+
+        if entry.pgid == shell_pgid || entry.ppid == entry.pgid || entry.replaced_shell {
+            singles.push(entry.clone());
+        } else {
+            members.push(entry);
+        }
+    }
+
+    // The start time of the leader is part of the key, so jobs that reuse a
+    // process group are not mixed.
+    let mut leaders: HashMap<JobKey, Vec<u64>> = HashMap::new();
+    for member in members.iter().filter(|m| m.pid == m.pgid) {
+        leaders
+            .entry(JobKey::new(member))
+            .or_default()
+            .push(member.process_start);
+    }
+
+    let mut jobs: HashMap<(JobKey, Option<u64>), Vec<&Entry>> = HashMap::new();
+    for member in members {
+        let key = JobKey::new(member);
+        let leader_start = leaders.get(&key).and_then(|starts| {
+            starts
+                .iter()
+                .copied()
+                .filter(|start| *start <= member.process_start)
+                .max()
+        });
+
+        jobs.entry((key, leader_start)).or_default().push(member);
+    }
+
+    let mut entries: Vec<Entry> = jobs
+        .into_iter()
+        .map(|((key, leader_start), members)| {
+            let subshell = leader_start.map_or(false, |start| {
+                let next = leaders[&key].iter().copied().filter(|s| *s > start).min();
+                children.iter().any(|child| {
+                    child.pgid == key.pgid
+                        && child.histnum == key.histnum
+                        && child.depth == key.depth
+                        && child.process_start >= start
+                        && next.map_or(true, |next| child.process_start < next)
+                })
+            });
+
+            combine(members, subshell)
+        })
+        .collect();
+
+    entries.append(&mut singles);
+    entries.sort_unstable_by_key(|e| std::cmp::Reverse(e.number));
+    entries.into()
+}
+
+/// Data shared by all members of a pipeline.
+#[derive(PartialEq, Eq, Hash)]
+struct JobKey {
+    pgid: libc::pid_t,
+    ppid: libc::pid_t,
+    histnum: Option<usize>,
+    depth: usize,
+}
+
+impl JobKey {
+    fn new(entry: &Entry) -> JobKey {
+        JobKey {
+            pgid: entry.pgid,
+            ppid: entry.ppid,
+            histnum: entry.histnum,
+            depth: entry.depth,
+        }
+    }
+}
+
+/// Build a single entry from the members of a pipeline.
+///
+/// If `subshell` is `true`, the first member is a subshell.
+fn combine(mut members: Vec<&Entry>, subshell: bool) -> Entry {
+    if members.len() == 1 {
+        return members[0].clone();
+    }
+
+    // bash forks the members of a pipeline from left to right, and the
+    // process group is the pid of the first one.
+    members.sort_by_key(|e| (e.process_start, e.pid.wrapping_sub(e.pgid) as u32));
+
+    let first = members[0];
+
+    let shell_command =
+        members
+            .iter()
+            .enumerate()
+            .fold(OsString::new(), |mut cmd, (idx, member)| {
+                if idx > 0 {
+                    cmd.push(" | ");
+                }
+
+                if idx == 0 && subshell {
+                    cmd.push("( … )");
+                } else {
+                    cmd.push(&member.shell_command);
+                }
+
+                cmd
+            });
+
+    let job_control = JobControl {
+        stops: members.iter().map(|m| m.job_control.stops).sum(),
+        stopped_time: members
+            .iter()
+            .map(|m| m.job_control.stopped_time)
+            .max()
+            .unwrap_or_default(),
+    };
+
+    Entry {
+        number: members
+            .iter()
+            .map(|m| m.number)
+            .min()
+            .unwrap_or(first.number),
+        pid: first.pgid,
+        ppid: first.ppid,
+        pgid: first.pgid,
+        job: members.iter().find_map(|m| m.job),
+        process_start: first.process_start,
+        start_time: members
+            .iter()
+            .map(|m| m.start_time)
+            .min()
+            .unwrap_or(first.start_time),
+        histnum: first.histnum,
+        depth: first.depth,
+        replaced_shell: first.replaced_shell,
+        subshell: false,
+        shell_command,
+        filename: first.filename.clone(),
+        args: first.args.clone(),
+        argc: first.argc,
+        omitted_bytes: first.omitted_bytes,
+        redacted: members.iter().any(|m| m.redacted),
+        tags: combine_tags(&members),
+        state: combine_states(&members),
+        job_control,
+        pipeline: members
+            .into_iter()
+            .enumerate()
+            .map(|(idx, member)| Entry {
+                subshell: idx == 0 && subshell,
+                ..member.clone()
+            })
+            .collect(),
+    }
+}
+
+/// Tags of all members, without duplicates.
+fn combine_tags(members: &[&Entry]) -> Vec<String> {
+    let mut tags: Vec<String> = Vec::new();
+    for tag in members.iter().flat_map(|m| &m.tags) {
+        if !tags.contains(tag) {
+            tags.push(tag.clone());
+        }
+    }
+
+    tags
+}
+
+/// Compute the state of a pipeline.
+///
+/// If any member is not finished, its state is used for the whole pipeline.
+///
+/// When all members are finished, CPU times and counters are added, the
+/// maximum resident set size is the maximum of all members, and the running
+/// time goes from the first start to the last finish, both from the
+/// monotonic clock. The status is the one from the last member, like bash
+/// does without `pipefail`.
+fn combine_states(members: &[&Entry]) -> State {
+    let mut rusage: libc::rusage = unsafe { mem::zeroed() };
+    let mut last_status = 0;
+    let mut start: Option<libc::timespec> = None;
+    let mut end = None;
+
+    for member in members {
+        let (member_start, running_time, status, member_rusage) = match &member.state {
+            State::Finished {
+                start,
+                running_time,
+                status,
+                rusage,
+            } => (start, running_time, status, rusage),
+
+            state => return state.clone(),
+        };
+
+        add_rusage(&mut rusage, member_rusage);
+        last_status = *status;
+
+        if start.map_or(true, |s| duration(member_start) < duration(&s)) {
+            start = Some(*member_start);
+        }
+
+        if let Some(running_time) = running_time {
+            end = end.max(Some(duration(member_start) + *running_time));
+        }
+    }
+
+    let start = start.unwrap_or(libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    });
+
+    State::Finished {
+        start,
+        running_time: end.and_then(|end| end.checked_sub(duration(&start))),
+        status: last_status,
+        rusage,
+    }
+}
+
+/// Add the resources from `other` to `total`.
+fn add_rusage(total: &mut libc::rusage, other: &libc::rusage) {
+    fn add_timeval(total: &mut libc::timeval, other: &libc::timeval) {
+        total.tv_sec += other.tv_sec;
+        total.tv_usec += other.tv_usec;
+        if total.tv_usec >= 1_000_000 {
+            total.tv_sec += 1;
+            total.tv_usec -= 1_000_000;
+        }
+    }
+
+    add_timeval(&mut total.ru_utime, &other.ru_utime);
+    add_timeval(&mut total.ru_stime, &other.ru_stime);
+
+    total.ru_maxrss = total.ru_maxrss.max(other.ru_maxrss);
+
+    total.ru_ixrss += other.ru_ixrss;
+    total.ru_idrss += other.ru_idrss;
+    total.ru_isrss += other.ru_isrss;
+    total.ru_minflt += other.ru_minflt;
+    total.ru_majflt += other.ru_majflt;
+    total.ru_nswap += other.ru_nswap;
+    total.ru_inblock += other.ru_inblock;
+    total.ru_oublock += other.ru_oublock;
+    total.ru_msgsnd += other.ru_msgsnd;
+    total.ru_msgrcv += other.ru_msgrcv;
+    total.ru_nsignals += other.ru_nsignals;
+    total.ru_nvcsw += other.ru_nvcsw;
+    total.ru_nivcsw += other.ru_nivcsw;
+}
+
+#[test]
+fn combine_members() {
+    use chrono::{Local, TimeZone};
+    use std::time::Duration;
+
+    let member = |number, pid, pgid, start, secs, maxrss| {
+        let mut rusage: libc::rusage = unsafe { mem::zeroed() };
+        rusage.ru_utime.tv_usec = 600_000;
+        rusage.ru_maxrss = maxrss;
+        rusage.ru_nvcsw = 10;
+        rusage.ru_nsignals = 1;
+        rusage.ru_msgrcv = 2;
+
+        Entry {
+            number,
+            pid,
+            ppid: 1,
+            pgid,
+            job: None,
+            process_start: 0,
+            start_time: Local.timestamp(start, 0),
+            histnum: None,
+            depth: 0,
+            replaced_shell: false,
+            subshell: false,
+            shell_command: OsString::from(format!("cmd{}", number)),
+            filename: OsString::from("/bin/cmd"),
+            args: vec![OsString::from(format!("cmd{}", number))],
+            argc: 1,
+            omitted_bytes: None,
+            redacted: false,
+            tags: Vec::new(),
+            state: State::Finished {
+                start: libc::timespec {
+                    tv_sec: start,
+                    tv_nsec: 0,
+                },
+                running_time: Some(Duration::from_secs(secs)),
+                status: number as libc::c_int,
+                rusage,
+            },
+            job_control: JobControl::default(),
+            pipeline: Vec::new(),
+        }
+    };
+
+    // Entries in history order (most recent first).
+    let mut entries = [
+        member(4, 200, 1, 100, 1, 1000),
+        member(3, 102, 100, 100, 5, 2000),
+        member(2, 100, 100, 101, 2, 3000),
+        member(1, 50, 1, 90, 1, 1000),
+    ];
+
+    // The running time uses the monotonic clock, even if the wall clock
+    // was changed after the pipeline was started.
+    entries[1].start_time = Local.timestamp(50, 0);
+
+    entries[1].tags = vec!["a".into(), "b".into()];
+    entries[2].tags = vec!["b".into(), "c".into()];
+
+    let groups = group(&entries, 1);
+    let numbers: Vec<_> = groups.iter().map(|e| e.number).collect();
+    assert_eq!(numbers, [4, 2, 1]);
+
+    let pipeline = &groups[1];
+    assert_eq!(pipeline.pid, 100);
+    assert_eq!(pipeline.shell_command, OsString::from("cmd2 | cmd3"));
+    assert_eq!(pipeline.pipeline.len(), 2);
+    assert_eq!(pipeline.tags, ["b", "c", "a"]);
+
+    match &pipeline.state {
+        State::Finished {
+            running_time,
+            status,
+            rusage,
+            ..
+        } => {
+            assert_eq!(*running_time, Some(Duration::from_secs(5)));
+            assert_eq!(*status, 3);
+            assert_eq!(
+                (rusage.ru_utime.tv_sec, rusage.ru_utime.tv_usec),
+                (1, 200_000)
+            );
+            assert_eq!(rusage.ru_maxrss, 3000);
+            assert_eq!(rusage.ru_nvcsw, 20);
+            assert_eq!(rusage.ru_nsignals, 2);
+            assert_eq!(rusage.ru_msgrcv, 4);
+        }
+
+        _ => panic!("pipeline should be finished"),
+    }
+}
+
+#[test]
+fn group_jobs() {
+    let member = |number, pid, ppid, pgid, process_start, histnum| Entry {
+        number,
+        pid,
+        ppid,
+        pgid,
+        job: None,
+        process_start,
+        start_time: chrono::Local::now(),
+        histnum: Some(histnum),
+        depth: 0,
+        replaced_shell: false,
+        subshell: false,
+        shell_command: OsString::from(format!("cmd{}", number)),
+        filename: OsString::from("/bin/cmd"),
+        args: Vec::new(),
+        argc: 1,
+        omitted_bytes: None,
+        redacted: false,
+        tags: Vec::new(),
+        state: State::Running {
+            start: libc::timespec {
+                tv_sec: 0,
+                tv_nsec: 0,
+            },
+        },
+        job_control: JobControl::default(),
+        pipeline: Vec::new(),
+    };
+
+    let entries = [
+        // Subshell in a pipeline, with the process group 300.
+        member(10, 301, 1, 300, 7000, 4),
+        member(9, 302, 300, 300, 7001, 4),
+        member(8, 300, 1, 300, 7000, 4),
+        // Process group 100 reused by a different job.
+        member(7, 101, 1, 100, 5000, 3),
+        member(6, 100, 1, 100, 5000, 3),
+        // Commands of a subshell in the process group 100.
+        member(5, 100, 1, 100, 2000, 2),
+        member(4, 102, 100, 100, 2001, 2),
+        member(3, 101, 100, 100, 2001, 2),
+        // Pipeline with the process group 100.
+        member(2, 101, 1, 100, 1000, 1),
+        member(1, 100, 1, 100, 1000, 1),
+    ];
+
+    let groups = group(&entries, 1);
+    let jobs: Vec<_> = groups
+        .iter()
+        .map(|e| {
+            let members: Vec<_> = e.pipeline.iter().map(|m| m.number).collect();
+            (e.number, members)
+        })
+        .collect();
+
+    assert_eq!(
+        jobs,
+        [
+            (9, vec![]),
+            (8, vec![8, 10]),
+            (6, vec![6, 7]),
+            (5, vec![]),
+            (4, vec![]),
+            (3, vec![]),
+            (1, vec![1, 2]),
+        ]
+    );
+
+    let commands: Vec<_> = groups.iter().map(|e| e.shell_command.clone()).collect();
+    assert_eq!(commands[1], OsString::from("( … ) | cmd10"));
+    assert_eq!(commands[2], OsString::from("cmd6 | cmd7"));
+    assert!(groups[1].pipeline[0].subshell);
+    assert!(!groups[2].pipeline[0].subshell);
+}
@@ -1,63 +1,356 @@
-//! Wrapper for `execve()`
+//! Wrappers for `execve()` and the other functions of the exec family.
 
+use std::convert::TryFrom;
+use std::ffi::{CStr, CString, OsString};
 use std::io::{self, stderr, Write};
 use std::mem::MaybeUninit;
 use std::os::raw::{c_char, c_int};
+use std::os::unix::ffi::OsStringExt;
 use std::time::Duration;
+use std::{iter, ptr};
 
-use crate::ipc::events::ExecEvent;
+use bash_builtins::variables;
+
+use super::nested::{self, Environment};
+use crate::history;
+use crate::ipc::events::{collect_events, ExecEvent, ProcessInfo};
 
 /// Timeout to send execve to the shared buffer.
 const EVENT_TIMEOUT: Duration = Duration::from_millis(250);
 
+/// Shell variable with the command being executed.
+const SHELL_VAR_COMMAND: &str = "BASH_COMMAND";
+
+/// Shell variable with the history number of the current command.
+const SHELL_VAR_HISTCMD: &str = "HISTCMD";
+
 /// Function to replace execve().
 pub(super) unsafe extern "C" fn execve_wrapper(
     filename: *const c_char,
     argv: *const *const c_char,
     envp: *const *const c_char,
 ) -> c_int {
-    let execve_fn: super::ExecveFn = match &super::EXECVE_FN {
-        Some(f) => *f,
-        None => {
-            *libc::__errno_location() = libc::EFAULT;
-            return -1;
-        }
+    let execve_fn = original_fn!(EXECVE_FN, libc::execve);
+    let execution = register_current_process("execve", filename, argv);
+
+    let env = Environment::new(filename, envp, false);
+    let ret = (execve_fn)(filename, argv, env.as_ref().map_or(envp, Environment::envp));
+    execution.failed();
+    ret
+}
+
+/// Function to replace execv().
+pub(super) unsafe extern "C" fn execv_wrapper(
+    filename: *const c_char,
+    argv: *const *const c_char,
+) -> c_int {
+    let execv_fn = original_fn!(EXECV_FN, libc::execv);
+    let execution = register_current_process("execv", filename, argv);
+
+    // If the tracking is propagated, the new environment is set in
+    // `environ` for the original function.
+    let ret = match Environment::new(filename, nested::current_environ(), false) {
+        Some(env) => nested::with_environ(env.envp(), || (execv_fn)(filename, argv)),
+        None => (execv_fn)(filename, argv),
     };
 
-    // Register this call if there is a shared buffer.
-    if let Some(shared_buffer) = crate::ipc::global_shared_buffer(EVENT_TIMEOUT) {
-        if let Err(e) = write_event(shared_buffer, filename, argv) {
-            let _ = writeln!(stderr(), "timehistory: execve: {}", e);
+    execution.failed();
+    ret
+}
+
+/// Function to replace execvp().
+pub(super) unsafe extern "C" fn execvp_wrapper(
+    file: *const c_char,
+    argv: *const *const c_char,
+) -> c_int {
+    let execvp_fn = original_fn!(EXECVP_FN, libc::execvp);
+    let execution = register_current_process("execvp", file, argv);
+
+    // If the tracking is propagated, the new environment is set in
+    // `environ` for the original function.
+    let ret = match Environment::new(file, nested::current_environ(), true) {
+        Some(env) => nested::with_environ(env.envp(), || (execvp_fn)(file, argv)),
+        None => (execvp_fn)(file, argv),
+    };
+
+    execution.failed();
+    ret
+}
+
+/// Function to replace execvpe().
+pub(super) unsafe extern "C" fn execvpe_wrapper(
+    file: *const c_char,
+    argv: *const *const c_char,
+    envp: *const *const c_char,
+) -> c_int {
+    let execvpe_fn = original_fn!(EXECVPE_FN, libc::execvpe);
+    let execution = register_current_process("execvpe", file, argv);
+
+    let env = Environment::new(file, envp, true);
+    let ret = (execvpe_fn)(file, argv, env.as_ref().map_or(envp, Environment::envp));
+    execution.failed();
+    ret
+}
+
+/// Function to replace fexecve().
+///
+/// The filename is taken from the `/proc/self/fd` link, which is read in a
+/// buffer on the stack.
+pub(super) unsafe extern "C" fn fexecve_wrapper(
+    fd: c_int,
+    argv: *const *const c_char,
+    envp: *const *const c_char,
+) -> c_int {
+    let fexecve_fn = original_fn!(FEXECVE_FN, libc::fexecve);
+
+    let mut link = [0_u8; 32];
+    let _ = write!(&mut link[..], "/proc/self/fd/{}\0", fd);
+
+    let mut filename = [0_u8; nested::PATH_LEN + 1];
+    let len = libc::readlink(
+        link.as_ptr().cast(),
+        filename.as_mut_ptr().cast(),
+        nested::PATH_LEN,
+    );
+    filename[usize::try_from(len).unwrap_or(0)] = 0;
+
+    let filename = filename.as_ptr().cast();
+    let execution = register_current_process("fexecve", filename, argv);
+
+    let env = Environment::new(filename, envp, false);
+    let ret = (fexecve_fn)(fd, argv, env.as_ref().map_or(envp, Environment::envp));
+    execution.failed();
+    ret
+}
+
+/// Register a program executed in the current process.
+unsafe fn register_current_process(
+    function: &str,
+    filename: *const c_char,
+    argv: *const *const c_char,
+) -> Execution {
+    let start = StartTime::now();
+    let pid = libc::getpid();
+
+    // If the shell calls the exec function without a fork (like in `exec
+    // cmd`), the new program replaces the shell process.
+    let replaces_shell = pid == super::SHELL_PID;
+
+    register(
+        function,
+        pid,
+        libc::getppid(),
+        libc::getpgrp(),
+        &start,
+        replaces_shell,
+        filename,
+        argv,
+    );
+
+    Execution {
+        pid,
+        replaces_shell,
+    }
+}
+
+/// Program executed in the current process.
+struct Execution {
+    pid: libc::pid_t,
+    replaces_shell: bool,
+}
+
+impl Execution {
+    /// Update the history when the exec function fails.
+    ///
+    /// It only affects programs executed without a fork in the main shell,
+    /// which can survive a failed exec (if it is interactive, or with
+    /// `shopt -s execfail`). A nested shell usually exits after a failed
+    /// exec, and its entries are updated when it is reaped.
+    unsafe fn failed(self) {
+        if !self.replaces_shell || self.pid != history::OWNER_PID {
+            return;
+        }
+
+        let errno = *libc::__errno_location();
+
+        // Use the same exit codes as bash.
+        let status = if errno == libc::ENOENT { 127 } else { 126 } << 8;
+
+        if let Some(mut history) = collect_events(false) {
+            history.update_entry(
+                self.pid,
+                super::procstat::start_ticks(self.pid).unwrap_or(0),
+                status,
+                StartTime::now().monotonic,
+                std::mem::zeroed(),
+            );
         }
+
+        *libc::__errno_location() = errno;
     }
+}
+
+/// Time when a program is started.
+pub(super) struct StartTime {
+    monotonic: libc::timespec,
+    realtime: libc::timespec,
+}
+
+impl StartTime {
+    pub(super) fn now() -> StartTime {
+        let mut monotonic = MaybeUninit::zeroed();
+        let mut realtime = MaybeUninit::zeroed();
+
+        unsafe {
+            libc::clock_gettime(libc::CLOCK_MONOTONIC, monotonic.as_mut_ptr());
+            libc::clock_gettime(libc::CLOCK_REALTIME, realtime.as_mut_ptr());
+
+            StartTime {
+                monotonic: monotonic.assume_init(),
+                realtime: realtime.assume_init(),
+            }
+        }
+    }
+}
+
+/// Send an `ExecEvent` to the shared buffer, if it is available.
+///
+/// `function` is used only for error messages.
+#[allow(clippy::too_many_arguments)]
+pub(super) unsafe fn register(
+    function: &str,
+    pid: libc::pid_t,
+    ppid: libc::pid_t,
+    pgid: libc::pid_t,
+    start: &StartTime,
+    replaces_shell: bool,
+    filename: *const c_char,
+    argv: *const *const c_char,
+) {
+    if let Some(shared_buffer) = crate::ipc::global_shared_buffer(EVENT_TIMEOUT) {
+        let result = write_event(
+            shared_buffer,
+            pid,
+            ppid,
+            pgid,
+            start,
+            replaces_shell,
+            filename,
+            argv,
+        );
 
-    (execve_fn)(filename, argv, envp)
+        if let Err(e) = result {
+            let _ = writeln!(stderr(), "timehistory: {}: {}", function, e);
+        }
+    }
 }
 
 /// Send `execve` data to the shared buffer.
+#[allow(clippy::too_many_arguments)]
 unsafe fn write_event(
     mut buffer: crate::ipc::SharedBufferGuard,
+    pid: libc::pid_t,
+    ppid: libc::pid_t,
+    pgid: libc::pid_t,
+    start: &StartTime,
+    replaces_shell: bool,
     filename: *const c_char,
     argv: *const *const c_char,
 ) -> io::Result<()> {
-    let mut monotonic_time = MaybeUninit::zeroed();
-    let mut start_time = MaybeUninit::zeroed();
-    let max_cmdline = buffer.max_cmdline();
+    let mut max_cmdline = buffer.max_cmdline();
 
-    let pid = libc::getpid();
-    libc::clock_gettime(libc::CLOCK_MONOTONIC, monotonic_time.as_mut_ptr());
-    libc::clock_gettime(libc::CLOCK_REALTIME, start_time.as_mut_ptr());
+    // This process is either bash or a process forked from it, so shell
+    // variables are still available.
+    let mut shell_command = variables::find_as_string(SHELL_VAR_COMMAND);
+    let histnum = variables::find_as_string(SHELL_VAR_HISTCMD)
+        .and_then(|n| n.to_str().ok()?.parse().ok())
+        .unwrap_or(0);
 
-    let written = ExecEvent::serialize(
-        io::Cursor::new(buffer.output()),
+    // Secrets are removed before writing the event, so they are never
+    // visible to other processes with access to the shared buffer.
+    let redacted_args = redact(argv, &mut shell_command);
+    let redacted_argv: Option<Vec<*const c_char>> = redacted_args.as_ref().map(|args| {
+        args.iter()
+            .map(|arg| arg.as_ptr())
+            .chain(iter::once(ptr::null()))
+            .collect()
+    });
+
+    let argv = redacted_argv.as_ref().map_or(argv, |argv| argv.as_ptr());
+
+    let process = ProcessInfo {
         pid,
-        monotonic_time.assume_init(),
-        start_time.assume_init(),
-        filename,
-        argv,
-        max_cmdline,
-    )?;
+        ppid,
+        pgid,
+        process_start: super::procstat::start_ticks(pid).unwrap_or(0),
+        histnum,
+        depth: nested::depth(),
+        replaces_shell,
+        redacted: redacted_args.is_some(),
+        shell_command: shell_command.as_deref(),
+    };
+
+    loop {
+        let result = ExecEvent::serialize(
+            io::Cursor::new(buffer.output()),
+            &process,
+            start.monotonic,
+            start.realtime,
+            filename,
+            argv,
+            max_cmdline,
+        );
+
+        match result {
+            Ok(written) => {
+                buffer.advance(written);
+                return Ok(());
+            }
+
+            // If the event does not fit in the free space of the buffer, try
+            // again with a shorter command line. The event records how many
+            // bytes were discarded.
+            Err(e) if e.kind() == io::ErrorKind::WriteZero && max_cmdline > 0 => {
+                max_cmdline = max_cmdline.min(buffer.output().len()) / 2;
+            }
+
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Redact secrets in `argv` and in `shell_command`, with the patterns from
+/// `TIMEHISTORY_REDACT`.
+///
+/// Returns a copy of `argv` with the redacted values, or `None` if there
+/// was nothing to redact.
+unsafe fn redact(
+    argv: *const *const c_char,
+    shell_command: &mut Option<CString>,
+) -> Option<Vec<CString>> {
+    let mut args = Vec::new();
+    let mut arg = argv;
+    while !(*arg).is_null() {
+        args.push(OsString::from_vec(CStr::from_ptr(*arg).to_bytes().to_vec()));
+        arg = arg.add(1);
+    }
+
+    let mut command = shell_command
+        .as_ref()
+        .map(|cmd| OsString::from_vec(cmd.as_bytes().to_vec()))
+        .unwrap_or_default();
+
+    if !crate::redact::redactor().redact(&mut args, &mut command) {
+        return None;
+    }
+
+    if shell_command.is_some() {
+        *shell_command = CString::new(command.into_vec()).ok();
+    }
 
-    buffer.advance(written);
-    Ok(())
+    Some(
+        args.into_iter()
+            .filter_map(|arg| CString::new(arg.into_vec()).ok())
+            .collect(),
+    )
 }
@@ -0,0 +1,23 @@
+//! Wrapper for `fork()`.
+//!
+//! The wrappers of the wait functions can't update the history in the main
+//! shell (see `waitpid::record`). Before creating a new process, the main
+//! shell moves the pending events to the history, so they don't wait until
+//! the next time the builtin is invoked.
+//!
+//! The data to propagate the tracking to nested instances is also prepared
+//! before the fork, so the exec wrappers don't need to allocate memory in
+//! the new process.
+
+use crate::ipc::events::{flush_pending_events, pending};
+
+/// Function to replace fork().
+pub(super) unsafe extern "C" fn fork_wrapper() -> libc::pid_t {
+    if !pending::is_empty() {
+        flush_pending_events();
+    }
+
+    super::nested::prepare();
+
+    libc::fork()
+}
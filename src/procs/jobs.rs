@@ -0,0 +1,94 @@
+//! Job numbers assigned by bash.
+//!
+//! bash adds a job to its table after the processes of the pipeline are
+//! started, so the job number is not available when `execve` is invoked.
+//! Instead, it is read from the job table of the main shell while the
+//! process is running, or when it is reaped.
+//!
+//! The table is read with `get_job_by_pid`. This function is not available
+//! if bash is built without job control, and its signature changed in bash
+//! 5, so it is resolved with `dlsym` when the builtin is loaded.
+
+use std::ffi::CStr;
+use std::mem;
+use std::os::raw::{c_char, c_int, c_void};
+use std::ptr;
+
+/// `get_job_by_pid` in bash 4.
+type GetJobByPid4 = unsafe extern "C" fn(libc::pid_t, c_int) -> c_int;
+
+/// `get_job_by_pid` in bash 5, with the `procp` argument.
+type GetJobByPid5 = unsafe extern "C" fn(libc::pid_t, c_int, *mut *mut c_void) -> c_int;
+
+#[derive(Clone, Copy)]
+enum GetJobByPid {
+    Bash4(GetJobByPid4),
+    Bash5(GetJobByPid5),
+}
+
+/// Address of `get_job_by_pid`, if it is available.
+static mut GET_JOB_BY_PID: Option<GetJobByPid> = None;
+
+/// Find the `get_job_by_pid` function in the shell.
+///
+/// It is not used if the version of bash is unknown.
+pub(super) fn resolve() {
+    unsafe {
+        let function = libc::dlsym(libc::RTLD_DEFAULT, b"get_job_by_pid\0".as_ptr().cast());
+        let version = libc::dlsym(libc::RTLD_DEFAULT, b"dist_version\0".as_ptr().cast());
+
+        if function.is_null() || version.is_null() {
+            return;
+        }
+
+        // `dist_version` is a `const char *`, like "5.1".
+        let version = *version.cast::<*const c_char>();
+        if version.is_null() {
+            return;
+        }
+
+        let major = CStr::from_ptr(version)
+            .to_bytes()
+            .split(|b| *b == b'.')
+            .next()
+            .and_then(|m| std::str::from_utf8(m).ok())
+            .and_then(|m| m.parse::<u32>().ok());
+
+        GET_JOB_BY_PID = match major {
+            Some(4) => {
+                let function = mem::transmute::<*mut c_void, GetJobByPid4>(function);
+                Some(GetJobByPid::Bash4(function))
+            }
+
+            Some(m) if m >= 5 => {
+                let function = mem::transmute::<*mut c_void, GetJobByPid5>(function);
+                Some(GetJobByPid::Bash5(function))
+            }
+
+            _ => None,
+        };
+    }
+}
+
+/// Returns the job number (like `%1`) of a process in the main shell.
+///
+/// This function does not allocate memory, so it can be called from the
+/// wrappers of the wait functions. When they are invoked from the `SIGCHLD`
+/// handler, bash is already reading the job table in `waitchld`, and the
+/// table is not modified until the handler returns.
+pub(crate) fn job_number(pid: libc::pid_t) -> Option<usize> {
+    if unsafe { libc::getpid() != crate::history::OWNER_PID } {
+        return None;
+    }
+
+    let index = match unsafe { *ptr::addr_of!(GET_JOB_BY_PID) }? {
+        GetJobByPid::Bash4(f) => unsafe { f(pid, 0) },
+        GetJobByPid::Bash5(f) => unsafe { f(pid, 0, ptr::null_mut()) },
+    };
+
+    if index < 0 {
+        None
+    } else {
+        Some(index as usize + 1)
+    }
+}
@@ -0,0 +1,97 @@
+//! Check if a process is still running.
+
+use std::mem::MaybeUninit;
+use std::time::Duration;
+
+use super::procstat;
+
+/// Margin to compare start times, since the start time in `/proc` has a
+/// resolution of clock ticks, and the exec event is sent after the fork.
+const START_TIME_MARGIN: Duration = Duration::from_millis(500);
+
+/// Returns `true` if the process `pid` exists, and it is the same process
+/// that was registered in the history.
+///
+/// If `process_start` (see `procstat::start_ticks`) is not `0`, it must be
+/// equal to the start time of the running process. Otherwise, the process
+/// must be started before `start` (a `CLOCK_MONOTONIC` time).
+pub(crate) fn is_alive(pid: libc::pid_t, process_start: u64, start: &libc::timespec) -> bool {
+    let exists = unsafe { libc::kill(pid, 0) } == 0
+        || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM);
+
+    if !exists {
+        return false;
+    }
+
+    let current_start = match procstat::start_ticks(pid) {
+        Some(t) => t,
+        None => return true,
+    };
+
+    if process_start != 0 {
+        return current_start == process_start;
+    }
+
+    // Compare the age of the process with the age of the entry. The process
+    // must be older, since it was forked before the exec event.
+    //
+    // The age of the process is computed with `CLOCK_BOOTTIME`, which is
+    // the clock used by the kernel for the start time in `/proc`, and the
+    // age of the entry with `CLOCK_MONOTONIC`. The difference between both
+    // clocks (time in suspend) can only make the process older.
+    let process_start = match procstat::ticks_per_second() {
+        Some(tps) => Duration::from_micros(current_start * 1_000_000 / tps),
+        None => return true,
+    };
+
+    let process_age = clock(libc::CLOCK_BOOTTIME).saturating_sub(process_start);
+    let entry_age = clock(libc::CLOCK_MONOTONIC).saturating_sub(duration(start));
+
+    process_age + START_TIME_MARGIN >= entry_age
+}
+
+/// Current value of a clock.
+fn clock(clock_id: libc::clockid_t) -> Duration {
+    let mut ts = MaybeUninit::zeroed();
+    unsafe {
+        libc::clock_gettime(clock_id, ts.as_mut_ptr());
+        duration(&ts.assume_init())
+    }
+}
+
+fn duration(ts: &libc::timespec) -> Duration {
+    Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32)
+}
+
+#[test]
+fn check_processes() {
+    let now = unsafe {
+        let mut ts = MaybeUninit::zeroed();
+        libc::clock_gettime(libc::CLOCK_MONOTONIC, ts.as_mut_ptr());
+        ts.assume_init()
+    };
+
+    let pid = unsafe { libc::getpid() };
+    let ticks = procstat::start_ticks(pid).unwrap();
+    assert!(is_alive(pid, 0, &now));
+    assert!(is_alive(pid, ticks, &now));
+    assert!(!is_alive(pid, ticks + 1, &now));
+
+    // An entry older than the process means that the pid was reused.
+    let boot = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+
+    let process_start =
+        Duration::from_micros(ticks * 1_000_000 / procstat::ticks_per_second().unwrap());
+    let process_age = clock(libc::CLOCK_BOOTTIME).saturating_sub(process_start);
+    if clock(libc::CLOCK_MONOTONIC) > process_age + Duration::from_secs(5) {
+        assert!(!is_alive(pid, 0, &boot));
+    }
+
+    // Reaped process.
+    let mut child = std::process::Command::new("true").spawn().unwrap();
+    child.wait().unwrap();
+    assert!(!is_alive(child.id() as libc::pid_t, 0, &now));
+}
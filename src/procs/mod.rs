@@ -1,22 +1,97 @@
 //! Wrappers for libc functions.
 
-use plthook::{ObjectFile, Replacement};
+use plthook::{ErrorKind, ObjectFile, Replacement};
 use std::mem;
-use std::os::raw::{c_char, c_int};
+use std::os::raw::{c_char, c_int, c_void};
+use std::ptr;
+
+/// Returns the address of the original function, or the one from libc if the
+/// function was not replaced.
+macro_rules! original_fn {
+    ($original:ident, $libc_fn:path) => {
+        (*std::ptr::addr_of!(super::$original)).unwrap_or($libc_fn)
+    };
+}
 
 mod execve;
+mod fork;
+mod jobs;
+mod liveness;
+pub(crate) mod nested;
+mod procstat;
+mod spawn;
 mod waitpid;
 
-/// Function signature for execve().
-type ExecveFn = extern "C" fn(*const c_char, *const *const c_char, *const *const c_char) -> c_int;
+pub(crate) use jobs::job_number;
+pub(crate) use liveness::is_alive;
+
+#[cfg(feature = "option-for-exec-tests")]
+mod testing;
+
+#[cfg(feature = "option-for-exec-tests")]
+pub(crate) use testing::exec_with;
+
+/// Function signature for execve() and execvpe().
+type ExecveFn =
+    unsafe extern "C" fn(*const c_char, *const *const c_char, *const *const c_char) -> c_int;
+
+/// Function signature for execv() and execvp().
+type ExecvFn = unsafe extern "C" fn(*const c_char, *const *const c_char) -> c_int;
+
+/// Function signature for fexecve().
+type FexecveFn = unsafe extern "C" fn(c_int, *const *const c_char, *const *const c_char) -> c_int;
+
+/// Function signature for posix_spawn() and posix_spawnp().
+type PosixSpawnFn = unsafe extern "C" fn(
+    *mut libc::pid_t,
+    *const c_char,
+    *const libc::posix_spawn_file_actions_t,
+    *const libc::posix_spawnattr_t,
+    *const *mut c_char,
+    *const *mut c_char,
+) -> c_int;
 
 /// Address of the original `execve`.
 static mut EXECVE_FN: Option<ExecveFn> = None;
 
+/// Address of the original `execv`.
+static mut EXECV_FN: Option<ExecvFn> = None;
+
+/// Address of the original `execvp`.
+static mut EXECVP_FN: Option<ExecvFn> = None;
+
+/// Address of the original `execvpe`.
+static mut EXECVPE_FN: Option<ExecveFn> = None;
+
+/// Address of the original `fexecve`.
+static mut FEXECVE_FN: Option<FexecveFn> = None;
+
+/// Address of the original `posix_spawn`.
+static mut POSIX_SPAWN_FN: Option<PosixSpawnFn> = None;
+
+/// Address of the original `posix_spawnp`.
+static mut POSIX_SPAWNP_FN: Option<PosixSpawnFn> = None;
+
+/// Process identifier of the shell where the functions were replaced.
+///
+/// A program executed in this process replaces the shell, without a fork.
+static mut SHELL_PID: libc::pid_t = 0;
+
 /// Replacements of the original libc functions.
-static mut REPLACEMENTS: Option<[Replacement; 2]> = None;
+static mut REPLACEMENTS: Option<Vec<Replacement>> = None;
 
-/// Replace `waitpid` and `execve` functions.
+/// Replace the functions to execute and wait for programs.
+///
+/// `waitpid` and `execve` are required. The other functions (`execv`,
+/// `execvp`, `execvpe`, `fexecve`, `posix_spawn`, `posix_spawnp`, `wait3`,
+/// `wait4`, `waitid` and `fork`) are replaced only if they are used by the
+/// main program. Variadic functions, like `execl`, can't be replaced.
+///
+/// `vfork` is not replaced, and programs executed in a child created by
+/// `vfork` are not tracked: bash never uses it, and the wrappers of the exec
+/// family allocate memory (for example, to read shell variables), which is
+/// not safe in that child. For the same reason, the programs executed by
+/// `posix_spawn` are registered by the parent process.
 pub(crate) fn replace_functions() -> Result<(), Box<dyn std::error::Error>> {
     let main_program = ObjectFile::open_main_program()?;
 
@@ -27,13 +102,73 @@ pub(crate) fn replace_functions() -> Result<(), Box<dyn std::error::Error>> {
             return Err(std::io::Error::last_os_error().into());
         }
 
+        SHELL_PID = libc::getpid();
+
+        jobs::resolve();
+
         // Replace waitpid and execve PLT entries.
         let waitpid_fn = main_program.replace("waitpid", waitpid::waitpid_wrapper as *const _)?;
         let execve_fn = main_program.replace("execve", execve::execve_wrapper as *const _)?;
 
-        EXECVE_FN = Some(mem::transmute(execve_fn.original_address()));
+        EXECVE_FN = Some(mem::transmute::<*const c_void, ExecveFn>(
+            execve_fn.original_address(),
+        ));
+
+        let mut replacements = vec![waitpid_fn, execve_fn];
+
+        // Optional functions.
+        macro_rules! replace_optional {
+            ($object:ident, $name:literal, $wrapper:path $(, $original:ident: $type:ty)?) => {
+                match $object.replace($name, $wrapper as *const _) {
+                    Ok(replacement) => {
+                        $(
+                            $original = Some(mem::transmute::<*const c_void, $type>(
+                                replacement.original_address(),
+                            ));
+                        )?
+
+                        replacements.push(replacement);
+                    }
+
+                    Err(e) if matches!(e.kind(), ErrorKind::FunctionNotFound) => (),
+
+                    Err(e) => return Err(e.into()),
+                }
+            };
+        }
+
+        replace_optional!(main_program, "execv", execve::execv_wrapper, EXECV_FN: ExecvFn);
+        replace_optional!(main_program, "execvp", execve::execvp_wrapper, EXECVP_FN: ExecvFn);
+        replace_optional!(main_program, "execvpe", execve::execvpe_wrapper, EXECVPE_FN: ExecveFn);
+        replace_optional!(main_program, "fexecve", execve::fexecve_wrapper, FEXECVE_FN: FexecveFn);
+        replace_optional!(main_program, "posix_spawn", spawn::posix_spawn_wrapper, POSIX_SPAWN_FN: PosixSpawnFn);
+        replace_optional!(main_program, "posix_spawnp", spawn::posix_spawnp_wrapper, POSIX_SPAWNP_FN: PosixSpawnFn);
+
+        // The wait functions use wait4 (or the waitid syscall) directly, so
+        // the original addresses are not needed.
+        replace_optional!(main_program, "wait3", waitpid::wait3_wrapper);
+        replace_optional!(main_program, "wait4", waitpid::wait4_wrapper);
+        replace_optional!(main_program, "waitid", waitpid::waitid_wrapper);
+
+        // The main shell collects the events from the wait functions before
+        // a fork.
+        replace_optional!(main_program, "fork", fork::fork_wrapper);
+
+        // The `-X` option executes programs with the functions imported by
+        // this shared object, so they are replaced like the ones in the
+        // main program.
+        #[cfg(feature = "option-for-exec-tests")]
+        {
+            let builtin = testing::builtin_object()?;
+            replace_optional!(builtin, "execv", execve::execv_wrapper, EXECV_FN: ExecvFn);
+            replace_optional!(builtin, "execvp", execve::execvp_wrapper, EXECVP_FN: ExecvFn);
+            replace_optional!(builtin, "execvpe", execve::execvpe_wrapper, EXECVPE_FN: ExecveFn);
+            replace_optional!(builtin, "fexecve", execve::fexecve_wrapper, FEXECVE_FN: FexecveFn);
+            replace_optional!(builtin, "posix_spawn", spawn::posix_spawn_wrapper, POSIX_SPAWN_FN: PosixSpawnFn);
+            replace_optional!(builtin, "posix_spawnp", spawn::posix_spawnp_wrapper, POSIX_SPAWNP_FN: PosixSpawnFn);
+        }
 
-        REPLACEMENTS = Some([waitpid_fn, execve_fn]);
+        REPLACEMENTS = Some(replacements);
     }
 
     Ok(())
@@ -42,6 +177,6 @@ pub(crate) fn replace_functions() -> Result<(), Box<dyn std::error::Error>> {
 /// Restore original libc functions.
 extern "C" fn restore_functions() {
     unsafe {
-        drop(REPLACEMENTS.take());
+        drop((*ptr::addr_of_mut!(REPLACEMENTS)).take());
     }
 }
@@ -0,0 +1,656 @@
+//! Propagate the tracking to nested bash instances.
+//!
+//! When `TIMEHISTORY_NESTED` is set, the environment of the executed programs
+//! includes the file descriptor of the shared buffer, the redaction patterns,
+//! and a `BASH_ENV` script to load the builtin in non-interactive bash
+//! instances (like `bash -c` or shell scripts).
+//!
+//! Nested instances send their events to the inherited buffer, so their
+//! commands appear in the history of the main shell. Programs executed from
+//! a nested instance always propagate the tracking.
+//!
+//! Any process with the file descriptor of the shared buffer can read and
+//! write the events of the history. To limit the exposure, the descriptors
+//! are only inherited when the executed program is the same executable as
+//! the tracked shell, or a script with a `#!` line for it. Nested instances
+//! don't pass them to other programs.
+//!
+//! The exec functions are usually invoked in a forked process, so the data
+//! for the environment is prepared by the wrapper of `fork`, before the new
+//! process is created. The exec wrappers only have to check the executed
+//! program, and they don't allocate memory for it.
+
+use std::convert::TryFrom;
+use std::ffi::{CStr, CString, OsStr};
+use std::mem::MaybeUninit;
+use std::os::raw::{c_char, c_int};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::ptr;
+
+use bash_builtins::variables;
+
+/// Shell variable to enable the propagation.
+pub(crate) const SHELL_VAR_NESTED: &str = "TIMEHISTORY_NESTED";
+
+/// Environment variable with the file descriptor of the shared buffer.
+const ENV_SHARED_FD: &str = "TIMEHISTORY_SHARED_FD";
+
+/// Environment variable with the nesting depth of the shell.
+const ENV_DEPTH: &str = "TIMEHISTORY_DEPTH";
+
+/// Environment variable with the path of the library, used by the init
+/// script.
+const ENV_LIBRARY: &str = "TIMEHISTORY_LIBRARY";
+
+/// Environment variable to keep the original value of `BASH_ENV`.
+const ENV_BASH_ENV: &str = "TIMEHISTORY_BASH_ENV";
+
+/// Environment variable with the redaction patterns of the main shell.
+const ENV_REDACT: &str = "TIMEHISTORY_REDACT_PATTERNS";
+
+/// Environment variable for the startup file of non-interactive shells.
+const ENV_STARTUP_FILE: &str = "BASH_ENV";
+
+/// Prefix for the path of the init script in `BASH_ENV`. It is followed by
+/// the file descriptor.
+const INIT_SCRIPT_PATH: &str = "/proc/self/fd/";
+
+/// Prefix of the link in `/proc/self/fd` for the init script.
+const INIT_SCRIPT_LINK: &[u8] = b"/memfd:timehistory-init";
+
+/// Maximum number of bytes read from a script to find its `#!` line.
+const SHEBANG_MAX_LEN: usize = 256;
+
+/// Size of the buffers for paths.
+pub(super) const PATH_LEN: usize = libc::PATH_MAX as usize;
+
+/// Number of entries reserved in the new environment, in addition to the
+/// ones in the environment of the shell.
+const ENVP_EXTRA_ENTRIES: usize = 64;
+
+/// Script loaded by nested bash instances. The original `BASH_ENV` file, if
+/// any, is loaded after the builtin.
+const INIT_SCRIPT: &[u8] = br#"enable -f "$TIMEHISTORY_LIBRARY" timehistory
+if [ -n "${TIMEHISTORY_BASH_ENV:-}" ]; then
+  . "$TIMEHISTORY_BASH_ENV"
+fi
+"#;
+
+extern "C" {
+    static mut environ: *const *const c_char;
+}
+
+/// Data to build the environment, prepared before a fork.
+static mut PREPARED: Option<Prepared> = None;
+
+/// Descriptor of the memory file with the init script, or `-1` if it is not
+/// created yet.
+static mut INIT_SCRIPT_FD: c_int = -1;
+
+/// File descriptor of the shared buffer inherited from a tracked shell, if
+/// this shell is a nested instance.
+pub(crate) fn inherited_buffer() -> Option<c_int> {
+    std::env::var(ENV_SHARED_FD).ok()?.parse().ok()
+}
+
+/// Redaction patterns inherited from the main shell, if this shell is a
+/// nested instance.
+pub(crate) fn inherited_redact_patterns() -> Option<CString> {
+    inherited_buffer()?;
+    CString::new(std::env::var_os(ENV_REDACT)?.into_vec()).ok()
+}
+
+/// Close the file descriptor of the init script, so it is not inherited
+/// by the programs executed from a nested instance.
+///
+/// bash reads the whole script before executing it, so the descriptor is
+/// not needed when the builtin is loaded. `BASH_ENV` can be modified by the
+/// user, so the descriptor is closed only if it is the memory file created
+/// by the tracked shell.
+pub(crate) fn close_init_script() {
+    let fd: c_int = match std::env::var(ENV_STARTUP_FILE)
+        .ok()
+        .and_then(|path| path.strip_prefix(INIT_SCRIPT_PATH)?.parse().ok())
+    {
+        Some(fd) => fd,
+        None => return,
+    };
+
+    let is_init_script = std::fs::read_link(format!("{}{}", INIT_SCRIPT_PATH, fd))
+        .map_or(false, |link| {
+            link.as_os_str().as_bytes().starts_with(INIT_SCRIPT_LINK)
+        });
+
+    if is_init_script {
+        unsafe {
+            libc::close(fd);
+        }
+    }
+}
+
+/// Nesting depth of this shell. `0` is the main shell.
+pub(crate) fn depth() -> usize {
+    if inherited_buffer().is_none() {
+        return 0;
+    }
+
+    std::env::var(ENV_DEPTH)
+        .ok()
+        .and_then(|d| d.parse().ok())
+        .unwrap_or(1)
+}
+
+/// Current environment of the process.
+pub(super) unsafe fn current_environ() -> *const *const c_char {
+    environ
+}
+
+/// Invoke `f` with `envp` as the environment of the process, for the exec
+/// functions without an `envp` argument.
+pub(super) unsafe fn with_environ<T>(envp: *const *const c_char, f: impl FnOnce() -> T) -> T {
+    let previous = environ;
+    environ = envp;
+    let ret = f();
+    environ = previous;
+    ret
+}
+
+/// Returns `true` if the executed programs have to inherit the tracking.
+pub(crate) fn enabled() -> bool {
+    if inherited_buffer().is_some() {
+        return true;
+    }
+
+    let value = variables::find_as_string(SHELL_VAR_NESTED);
+    let value = match &value {
+        Some(value) => value.as_bytes(),
+        None => match crate::config::value(SHELL_VAR_NESTED) {
+            Some(value) => value.as_bytes(),
+            None => return false,
+        },
+    };
+
+    !matches!(value, b"" | b"0")
+}
+
+/// Data to build the environment of the executed programs.
+///
+/// It is updated by the wrapper of `fork`, so the forked process does not
+/// need to allocate memory or to read files to propagate the tracking.
+struct Prepared {
+    /// Executable of the shell.
+    shell: FileId,
+
+    /// `true` if this shell is a nested instance.
+    nested: bool,
+
+    /// Descriptors of the shared buffer and the init script.
+    fds: (c_int, c_int),
+
+    /// Variables added to the environment of nested instances.
+    variables: Vec<CString>,
+
+    /// Buffer for the `TIMEHISTORY_BASH_ENV` entry, which is copied from
+    /// the environment of the program.
+    bash_env: Vec<u8>,
+
+    /// Variables for other programs executed from a nested instance.
+    restored: Vec<CString>,
+
+    /// Buffer for the new `envp`.
+    envp: Vec<*const c_char>,
+}
+
+impl Prepared {
+    fn new() -> Option<Prepared> {
+        let shell = file_id(b"/proc/self/exe\0".as_ptr().cast())?;
+        let buffer_fd = crate::ipc::global_shared_buffer_fd()?;
+        let script_fd = unsafe { init_script()? };
+        let library = library_path()?;
+        let redact_patterns = crate::redact::redactor().patterns().source().to_owned();
+
+        let variables = [
+            env_var(ENV_SHARED_FD, buffer_fd.to_string().as_bytes()),
+            env_var(ENV_DEPTH, (depth() + 1).to_string().as_bytes()),
+            env_var(ENV_LIBRARY, &library),
+            env_var(ENV_REDACT, redact_patterns.to_bytes()),
+            env_var(
+                ENV_STARTUP_FILE,
+                format!("{}{}", INIT_SCRIPT_PATH, script_fd).as_bytes(),
+            ),
+        ];
+
+        let restored = match std::env::var_os(ENV_BASH_ENV) {
+            Some(file) if !file.is_empty() => vec![env_var(ENV_STARTUP_FILE, file.as_bytes())?],
+            _ => Vec::new(),
+        };
+
+        let mut envp_len = 0;
+        unsafe {
+            while !(*environ.add(envp_len)).is_null() {
+                envp_len += 1;
+            }
+        }
+
+        Some(Prepared {
+            shell,
+            nested: inherited_buffer().is_some(),
+            fds: (buffer_fd, script_fd),
+            variables: variables.iter().cloned().collect::<Option<_>>()?,
+            bash_env: Vec::with_capacity(ENV_BASH_ENV.len() + PATH_LEN + 2),
+            restored,
+            envp: Vec::with_capacity(envp_len + ENVP_EXTRA_ENTRIES),
+        })
+    }
+}
+
+/// Prepare the data for the environment of the programs executed from the
+/// next process created by this shell.
+///
+/// It has to be called before a fork.
+pub(super) fn prepare() {
+    let prepared = if enabled() { Prepared::new() } else { None };
+    unsafe {
+        *ptr::addr_of_mut!(PREPARED) = prepared;
+    }
+}
+
+/// Environment for a program that inherits the tracking.
+///
+/// The file descriptors for the program are not inherited anymore when the
+/// value is dropped, so it must be kept until the program is executed.
+pub(super) struct Environment {
+    /// Null-terminated array for `envp`.
+    envp: *const *const c_char,
+
+    /// Descriptors of the shared buffer and the init script, if they are
+    /// inherited by the program.
+    inherited_fds: Option<(c_int, c_int)>,
+}
+
+impl Environment {
+    /// Build an environment from `envp`, if the propagation is enabled.
+    ///
+    /// If `program` is not executed by the same shell, the tracking is not
+    /// propagated. In a nested instance, the variables inherited from the
+    /// main shell are removed, since the descriptors in them are not
+    /// available to the program. `search_path` is `true` if the program is
+    /// searched in `$PATH`, like in `execvp`.
+    ///
+    /// Except when it is called in the shell process, like in `exec cmd` or
+    /// in `posix_spawn`, the data is prepared before the fork. This function
+    /// does not allocate memory, unless `envp` has many more entries than
+    /// the environment of the shell.
+    pub(super) unsafe fn new(
+        program: *const c_char,
+        envp: *const *const c_char,
+        search_path: bool,
+    ) -> Option<Environment> {
+        if envp.is_null() || program.is_null() {
+            return None;
+        }
+
+        if libc::getpid() == super::SHELL_PID {
+            prepare();
+        }
+
+        let prepared = (*ptr::addr_of_mut!(PREPARED)).as_mut()?;
+
+        if !runs_shell(
+            CStr::from_ptr(program).to_bytes(),
+            search_path,
+            prepared.shell,
+        ) {
+            return if prepared.nested {
+                Some(Environment::without_tracking(prepared, envp))
+            } else {
+                None
+            };
+        }
+
+        // Keep the original BASH_ENV. In nested shells, BASH_ENV is the init
+        // script, and the original value is inherited from the main shell.
+        let mut bash_env = None;
+        if !prepared.nested {
+            let entry = &mut prepared.bash_env;
+            entry.clear();
+            entry.extend_from_slice(ENV_BASH_ENV.as_bytes());
+            entry.push(b'=');
+            entry.extend_from_slice(find_var(envp, ENV_STARTUP_FILE).unwrap_or_default());
+            entry.push(0);
+
+            bash_env = CStr::from_bytes_with_nul(entry).ok();
+        }
+
+        let variables = prepared.variables.iter().map(|v| v.as_c_str());
+        copy_envp(&mut prepared.envp, envp, &[], variables.chain(bash_env));
+
+        let (buffer_fd, script_fd) = prepared.fds;
+        libc::fcntl(buffer_fd, libc::F_SETFD, 0);
+        libc::fcntl(script_fd, libc::F_SETFD, 0);
+
+        Some(Environment {
+            envp: prepared.envp.as_ptr(),
+            inherited_fds: Some(prepared.fds),
+        })
+    }
+
+    /// Build an environment without the variables to propagate the
+    /// tracking. The original `BASH_ENV` is restored.
+    unsafe fn without_tracking(prepared: &mut Prepared, envp: *const *const c_char) -> Environment {
+        let removed = [
+            ENV_SHARED_FD,
+            ENV_DEPTH,
+            ENV_LIBRARY,
+            ENV_BASH_ENV,
+            ENV_REDACT,
+            ENV_STARTUP_FILE,
+        ];
+
+        let variables = prepared.restored.iter().map(|v| v.as_c_str());
+        copy_envp(&mut prepared.envp, envp, &removed, variables);
+
+        Environment {
+            envp: prepared.envp.as_ptr(),
+            inherited_fds: None,
+        }
+    }
+
+    /// Pointer for the `envp` argument of the exec functions.
+    pub(super) fn envp(&self) -> *const *const c_char {
+        self.envp
+    }
+}
+
+impl Drop for Environment {
+    fn drop(&mut self) {
+        if let Some((buffer_fd, script_fd)) = self.inherited_fds {
+            unsafe {
+                libc::fcntl(buffer_fd, libc::F_SETFD, libc::FD_CLOEXEC);
+                libc::fcntl(script_fd, libc::F_SETFD, libc::FD_CLOEXEC);
+            }
+        }
+    }
+}
+
+/// Copy the variables in `envp` to `new_envp`, except the ones in `removed`
+/// and the ones replaced by `variables`, and append `variables`.
+unsafe fn copy_envp<'a>(
+    new_envp: &mut Vec<*const c_char>,
+    envp: *const *const c_char,
+    removed: &[&str],
+    variables: impl Iterator<Item = &'a CStr> + Clone,
+) {
+    new_envp.clear();
+
+    let mut var = envp;
+    while !(*var).is_null() {
+        let entry = CStr::from_ptr(*var).to_bytes();
+
+        let is_removed = removed.iter().any(|name| {
+            entry
+                .strip_prefix(name.as_bytes())
+                .map_or(false, |rest| rest.starts_with(b"="))
+        });
+
+        if !is_removed && !variables.clone().any(|v| same_name(v.to_bytes(), entry)) {
+            new_envp.push(*var);
+        }
+
+        var = var.add(1);
+    }
+
+    new_envp.extend(variables.map(|v| v.as_ptr()));
+    new_envp.push(ptr::null());
+}
+
+/// Build a `NAME=value` entry for the environment.
+fn env_var(name: &str, value: &[u8]) -> Option<CString> {
+    let mut entry = Vec::with_capacity(name.len() + value.len() + 1);
+    entry.extend_from_slice(name.as_bytes());
+    entry.push(b'=');
+    entry.extend_from_slice(value);
+    CString::new(entry).ok()
+}
+
+/// Returns `true` if both `NAME=value` entries have the same name.
+fn same_name(a: &[u8], b: &[u8]) -> bool {
+    fn name(entry: &[u8]) -> Option<&[u8]> {
+        entry.iter().position(|c| *c == b'=').map(|n| &entry[..=n])
+    }
+
+    matches!((name(a), name(b)), (Some(a), Some(b)) if a == b)
+}
+
+/// Find the value of a variable in `envp`.
+unsafe fn find_var<'a>(envp: *const *const c_char, name: &str) -> Option<&'a [u8]> {
+    let mut var = envp;
+    while !(*var).is_null() {
+        let entry = CStr::from_ptr(*var).to_bytes();
+        if let Some(value) = entry.strip_prefix(name.as_bytes()) {
+            if let Some(value) = value.strip_prefix(b"=") {
+                return Some(value);
+            }
+        }
+
+        var = var.add(1);
+    }
+
+    None
+}
+
+/// Absolute path of the library with the builtin.
+fn library_path() -> Option<Vec<u8>> {
+    let mut info = MaybeUninit::<libc::Dl_info>::zeroed();
+
+    let filename = unsafe {
+        if libc::dladdr(library_path as *const libc::c_void, info.as_mut_ptr()) == 0 {
+            return None;
+        }
+
+        let filename = info.assume_init().dli_fname;
+        if filename.is_null() {
+            return None;
+        }
+
+        CStr::from_ptr(filename)
+    };
+
+    let path = std::fs::canonicalize(OsStr::from_bytes(filename.to_bytes())).ok()?;
+    Some(path.into_os_string().into_vec())
+}
+
+/// Device and inode of a file.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct FileId(libc::dev_t, libc::ino_t);
+
+/// Returns the identifier of a regular file.
+fn file_id(path: *const c_char) -> Option<FileId> {
+    let mut stat = MaybeUninit::<libc::stat>::zeroed();
+    unsafe {
+        if libc::stat(path, stat.as_mut_ptr()) != 0 {
+            return None;
+        }
+
+        let stat = stat.assume_init();
+        if stat.st_mode & libc::S_IFMT != libc::S_IFREG {
+            return None;
+        }
+
+        Some(FileId(stat.st_dev, stat.st_ino))
+    }
+}
+
+/// Returns `true` if `program` is the executable of `shell`, or a script
+/// with a `#!` line for it.
+///
+/// This function does not allocate memory.
+fn runs_shell(program: &[u8], search_path: bool, shell: FileId) -> bool {
+    let mut path = [0; PATH_LEN];
+    match find_program(program, search_path, &mut path) {
+        Some(id) if id == shell => return true,
+        Some(_) => (),
+        None => return false,
+    }
+
+    let mut head = [0; SHEBANG_MAX_LEN];
+    let len = unsafe {
+        let fd = libc::open(path.as_ptr().cast(), libc::O_RDONLY | libc::O_CLOEXEC);
+        if fd == -1 {
+            return false;
+        }
+
+        let len = libc::read(fd, head.as_mut_ptr().cast(), head.len());
+        libc::close(fd);
+
+        match usize::try_from(len) {
+            Ok(len) => len,
+            Err(_) => return false,
+        }
+    };
+
+    let line = match head[..len].strip_prefix(b"#!") {
+        Some(line) => line.split(|c| *c == b'\n').next().unwrap_or_default(),
+        None => return false,
+    };
+
+    let mut words = line
+        .split(|c| c.is_ascii_whitespace())
+        .filter(|w| !w.is_empty());
+
+    // `env` searches the program in `$PATH`.
+    let interpreter = match words.next() {
+        Some(env) if env.ends_with(b"/env") => words.next(),
+        interpreter => interpreter,
+    };
+
+    interpreter.and_then(|i| find_program(i, true, &mut path)) == Some(shell)
+}
+
+/// Find a regular file for `program`, like `execvp` if `search_path` is
+/// `true`, and write its path (terminated by a nul byte) in `path`.
+///
+/// This function does not allocate memory.
+fn find_program(program: &[u8], search_path: bool, path: &mut [u8; PATH_LEN]) -> Option<FileId> {
+    /// Concatenate `parts` in `path`.
+    fn join(parts: &[&[u8]], path: &mut [u8; PATH_LEN]) -> Option<FileId> {
+        let mut len = 0;
+        for part in parts {
+            let end = len + part.len();
+            path.get_mut(len..end)?.copy_from_slice(part);
+            len = end;
+        }
+
+        *path.get_mut(len)? = 0;
+        file_id(path.as_ptr().cast())
+    }
+
+    if program.is_empty() || program.contains(&0) {
+        return None;
+    }
+
+    if !search_path || program.contains(&b'/') {
+        return join(&[program], path);
+    }
+
+    let paths = unsafe { libc::getenv(b"PATH\0".as_ptr().cast()) };
+    let paths = if paths.is_null() {
+        &b"/usr/bin:/bin"[..]
+    } else {
+        unsafe { CStr::from_ptr(paths).to_bytes() }
+    };
+
+    paths.split(|c| *c == b':').find_map(|dir| {
+        let dir = if dir.is_empty() { &b"."[..] } else { dir };
+        join(&[dir, b"/", program], path)
+    })
+}
+
+/// Returns the descriptor of a memory file with the init script. The file
+/// is created the first time, with `MFD_CLOEXEC`, and the flag is removed
+/// only when the environment for a nested instance is ready.
+unsafe fn init_script() -> Option<c_int> {
+    if INIT_SCRIPT_FD != -1 {
+        return Some(INIT_SCRIPT_FD);
+    }
+
+    let fd = libc::syscall(
+        libc::SYS_memfd_create,
+        b"timehistory-init\0".as_ptr(),
+        libc::MFD_CLOEXEC,
+    ) as c_int;
+    if fd == -1 {
+        return None;
+    }
+
+    let written = libc::write(fd, INIT_SCRIPT.as_ptr().cast(), INIT_SCRIPT.len());
+    if written != INIT_SCRIPT.len() as isize {
+        libc::close(fd);
+        return None;
+    }
+
+    INIT_SCRIPT_FD = fd;
+    Some(fd)
+}
+
+#[test]
+fn replace_variables() {
+    assert!(same_name(b"A=1", b"A=2"));
+    assert!(same_name(b"A=", b"A=2"));
+    assert!(!same_name(b"A=1", b"AB=1"));
+    assert!(!same_name(b"A", b"A"));
+
+    let envp = [
+        b"BASH_ENV=/etc/env\0".as_ptr().cast(),
+        b"X=1\0".as_ptr().cast(),
+        ptr::null(),
+    ];
+
+    unsafe {
+        assert_eq!(find_var(envp.as_ptr(), "BASH_ENV"), Some(&b"/etc/env"[..]));
+        assert_eq!(find_var(envp.as_ptr(), "BASH"), None);
+        assert_eq!(find_var(envp.as_ptr(), "X"), Some(&b"1"[..]));
+    }
+}
+
+#[test]
+fn detect_shell_scripts() {
+    let mut bash = [0; PATH_LEN];
+    let shell = match find_program(b"bash", true, &mut bash) {
+        Some(shell) => shell,
+        None => return,
+    };
+
+    let bash = CStr::from_bytes_with_nul(&bash[..=bash.iter().position(|c| *c == 0).unwrap()])
+        .unwrap()
+        .to_str()
+        .unwrap();
+
+    let dir = std::env::temp_dir().join(format!("timehistory-nested-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let scripts = [
+        ("direct", format!("#!{} -e\ntrue\n", bash), true),
+        ("env", "#! /usr/bin/env bash\ntrue\n".to_owned(), true),
+        ("other", "#!/bin/true\ntrue\n".to_owned(), false),
+        ("plain", "true\n".to_owned(), false),
+    ];
+
+    for (name, content, expected) in &scripts {
+        let path = dir.join(name);
+        std::fs::write(&path, content).unwrap();
+        let path = path.as_os_str().as_bytes();
+        assert_eq!(runs_shell(path, false, shell), *expected, "{}", name);
+    }
+
+    // Relative names are searched in $PATH only if requested.
+    assert!(runs_shell(b"bash", true, shell));
+    assert!(!runs_shell(b"bash", false, shell));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert!(runs_shell(bash.as_bytes(), false, shell));
+    assert!(!runs_shell(b"/no/such/bash", false, shell));
+    assert!(!runs_shell(b"/bin/true", false, shell));
+    assert!(!runs_shell(b"", true, shell));
+}
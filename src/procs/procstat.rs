@@ -0,0 +1,65 @@
+//! Read process data from `/proc/<pid>/stat`.
+
+use std::convert::TryFrom;
+use std::io::Write;
+
+/// Size of the buffer to read `/proc/<pid>/stat`. The command name is
+/// limited to 16 bytes, so the file is usually smaller than 400 bytes.
+const STAT_BUFFER_SIZE: usize = 1024;
+
+/// Start time of a process, in clock ticks since boot.
+///
+/// Together with the pid, this value identifies a process, even if the pid is
+/// reused later.
+///
+/// This function is called from the wrappers of the wait functions, which
+/// bash can invoke from its `SIGCHLD` handler, so it must not allocate
+/// memory.
+pub(crate) fn start_ticks(pid: libc::pid_t) -> Option<u64> {
+    let mut path = [0; 32];
+    let mut path_writer = &mut path[..];
+    write!(path_writer, "/proc/{}/stat\0", pid).ok()?;
+
+    let mut stat = [0; STAT_BUFFER_SIZE];
+    let stat = unsafe {
+        let fd = libc::open(path.as_ptr().cast(), libc::O_RDONLY | libc::O_CLOEXEC);
+        if fd < 0 {
+            return None;
+        }
+
+        let n = libc::read(fd, stat.as_mut_ptr().cast(), stat.len());
+        libc::close(fd);
+
+        stat.get(..usize::try_from(n).ok()?)?
+    };
+
+    // The second field (the command name) can contain spaces, so fields are
+    // counted after the last parenthesis.
+    let fields_start = memchr::memrchr(b')', stat)? + 1;
+    std::str::from_utf8(&stat[fields_start..])
+        .ok()?
+        .split_ascii_whitespace()
+        .nth(19)?
+        .parse()
+        .ok()
+}
+
+/// Number of clock ticks per second, used by `start_ticks`.
+pub(crate) fn ticks_per_second() -> Option<u64> {
+    match unsafe { libc::sysconf(libc::_SC_CLK_TCK) } {
+        n if n > 0 => Some(n as u64),
+        _ => None,
+    }
+}
+
+#[test]
+fn read_start_ticks() {
+    let pid = unsafe { libc::getpid() };
+    let ticks = start_ticks(pid).unwrap();
+    assert!(ticks > 0);
+    assert_eq!(start_ticks(pid), Some(ticks));
+
+    let mut child = std::process::Command::new("true").spawn().unwrap();
+    assert!(start_ticks(child.id() as libc::pid_t).unwrap() >= ticks);
+    child.wait().unwrap();
+}
@@ -0,0 +1,101 @@
+//! Wrappers for `posix_spawn()` and `posix_spawnp()`.
+//!
+//! Unlike the exec family, these functions are executed in the parent
+//! process, so the event is sent after the new process is created, when its
+//! pid is known.
+
+use libc::{pid_t, posix_spawn_file_actions_t, posix_spawnattr_t};
+use std::os::raw::{c_char, c_int};
+
+use super::execve::{register, StartTime};
+use super::nested::Environment;
+
+/// Function to replace posix_spawn().
+pub(super) unsafe extern "C" fn posix_spawn_wrapper(
+    pid: *mut pid_t,
+    path: *const c_char,
+    file_actions: *const posix_spawn_file_actions_t,
+    attrp: *const posix_spawnattr_t,
+    argv: *const *mut c_char,
+    envp: *const *mut c_char,
+) -> c_int {
+    let spawn_fn = original_fn!(POSIX_SPAWN_FN, libc::posix_spawn);
+    spawn(
+        "posix_spawn",
+        spawn_fn,
+        false,
+        pid,
+        path,
+        file_actions,
+        attrp,
+        argv,
+        envp,
+    )
+}
+
+/// Function to replace posix_spawnp().
+pub(super) unsafe extern "C" fn posix_spawnp_wrapper(
+    pid: *mut pid_t,
+    file: *const c_char,
+    file_actions: *const posix_spawn_file_actions_t,
+    attrp: *const posix_spawnattr_t,
+    argv: *const *mut c_char,
+    envp: *const *mut c_char,
+) -> c_int {
+    let spawn_fn = original_fn!(POSIX_SPAWNP_FN, libc::posix_spawnp);
+    spawn(
+        "posix_spawnp",
+        spawn_fn,
+        true,
+        pid,
+        file,
+        file_actions,
+        attrp,
+        argv,
+        envp,
+    )
+}
+
+/// Invoke the original function, and register the new process if it
+/// succeeds.
+///
+/// `search_path` is `true` if the function searches the program in `$PATH`.
+#[allow(clippy::too_many_arguments)]
+unsafe fn spawn(
+    function: &str,
+    spawn_fn: super::PosixSpawnFn,
+    search_path: bool,
+    pid: *mut pid_t,
+    path: *const c_char,
+    file_actions: *const posix_spawn_file_actions_t,
+    attrp: *const posix_spawnattr_t,
+    argv: *const *mut c_char,
+    envp: *const *mut c_char,
+) -> c_int {
+    // The caller may not need the pid, but we need it to track the process.
+    let mut child_pid = 0;
+    let pid_ptr = if pid.is_null() { &mut child_pid } else { pid };
+
+    let env = Environment::new(path, envp.cast(), search_path);
+    let envp = env.as_ref().map_or(envp, |env| env.envp().cast());
+
+    let start = StartTime::now();
+    let ret = (spawn_fn)(pid_ptr, path, file_actions, attrp, argv, envp);
+    drop(env);
+
+    if ret == 0 {
+        let child_pid = *pid_ptr;
+        register(
+            function,
+            child_pid,
+            libc::getpid(),
+            libc::getpgid(child_pid),
+            &start,
+            false,
+            path,
+            argv.cast(),
+        );
+    }
+
+    ret
+}
@@ -0,0 +1,185 @@
+//! Helpers to test the wrappers for functions not used by bash.
+//!
+//! The functions are invoked from this shared object, and its imports are
+//! replaced like the ones in bash (see `super::replace_functions`), so the
+//! programs are executed through the same hooks.
+//!
+//! This module is available only with the `option-for-exec-tests` feature.
+
+use plthook::ObjectFile;
+use std::error::Error;
+use std::ffi::{CStr, CString, OsStr};
+use std::io;
+use std::mem::MaybeUninit;
+use std::os::raw::{c_char, c_int, c_void};
+use std::os::unix::ffi::OsStrExt;
+use std::ptr;
+
+extern "C" {
+    static environ: *const *const c_char;
+}
+
+/// Open the shared object containing this function.
+pub(super) fn builtin_object() -> Result<ObjectFile, Box<dyn Error>> {
+    let mut info = MaybeUninit::<libc::Dl_info>::zeroed();
+    let address = exec_with as *const c_void;
+
+    let info = unsafe {
+        if libc::dladdr(address, info.as_mut_ptr()) == 0 {
+            return Err("dladdr: shared object not found".into());
+        }
+
+        info.assume_init()
+    };
+
+    let path = unsafe { CStr::from_ptr(info.dli_fname) };
+    Ok(ObjectFile::open_file(OsStr::from_bytes(path.to_bytes()))?)
+}
+
+/// Execute a program with one of the replaced functions, and wait until it
+/// is finished.
+///
+/// `functions` is the name of the function to execute the program,
+/// optionally followed by a slash and the name of the function to wait for
+/// it (like `execv/wait3`). `waitpid` is used if it is omitted, and
+/// `unhooked` reaps the process without updating the history.
+///
+/// `args[0]` is used as the path (or file, for the functions that search in
+/// `$PATH`) and as the first item of `argv`. `execl` ignores the other
+/// arguments.
+///
+/// Returns the status from the wait function.
+pub(crate) fn exec_with(functions: &str, args: &[CString]) -> io::Result<c_int> {
+    // Block SIGCHLD, so the handler in bash does not reap the new process
+    // before the wait function.
+    unsafe {
+        let mut set = MaybeUninit::uninit();
+        let mut old_set = MaybeUninit::uninit();
+        libc::sigemptyset(set.as_mut_ptr());
+        libc::sigaddset(set.as_mut_ptr(), libc::SIGCHLD);
+        libc::sigprocmask(libc::SIG_BLOCK, set.as_ptr(), old_set.as_mut_ptr());
+
+        let result = run(functions, args);
+
+        libc::sigprocmask(libc::SIG_SETMASK, old_set.as_ptr(), ptr::null_mut());
+        result
+    }
+}
+
+/// Execute and wait for the program. See `exec_with`.
+fn run(functions: &str, args: &[CString]) -> io::Result<c_int> {
+    let mut functions = functions.splitn(2, '/');
+    let function = functions.next().unwrap_or_default();
+    let wait_function = functions.next().unwrap_or("waitpid");
+
+    let path = match args.first() {
+        Some(path) => path,
+        None => return Err(io::ErrorKind::InvalidInput.into()),
+    };
+
+    let mut argv: Vec<*const c_char> = args.iter().map(|a| a.as_ptr()).collect();
+    argv.push(ptr::null());
+
+    let pid = unsafe {
+        match function {
+            "posix_spawn" | "posix_spawnp" => {
+                let spawn_fn = match function {
+                    "posix_spawn" => libc::posix_spawn,
+                    _ => libc::posix_spawnp,
+                };
+
+                let mut pid = 0;
+                let ret = spawn_fn(
+                    &mut pid,
+                    path.as_ptr(),
+                    ptr::null(),
+                    ptr::null(),
+                    argv.as_ptr().cast(),
+                    environ.cast(),
+                );
+
+                if ret != 0 {
+                    return Err(io::Error::from_raw_os_error(ret));
+                }
+
+                pid
+            }
+
+            "execl" | "execv" | "execvp" | "execvpe" | "fexecve" => {
+                let fd = if function == "fexecve" {
+                    match libc::open(path.as_ptr(), libc::O_RDONLY) {
+                        -1 => return Err(io::Error::last_os_error()),
+                        fd => fd,
+                    }
+                } else {
+                    -1
+                };
+
+                match libc::fork() {
+                    -1 => return Err(io::Error::last_os_error()),
+
+                    0 => {
+                        let path = path.as_ptr();
+                        let argv = argv.as_ptr();
+                        match function {
+                            // Variadic functions can't be replaced, so the
+                            // program is not added to the history.
+                            "execl" => libc::execl(path, path, ptr::null::<c_char>()),
+                            "execv" => libc::execv(path, argv),
+                            "execvp" => libc::execvp(path, argv),
+                            "execvpe" => libc::execvpe(path, argv, environ),
+                            _ => libc::fexecve(fd, argv, environ),
+                        };
+
+                        libc::_exit(127);
+                    }
+
+                    pid => {
+                        if fd != -1 {
+                            libc::close(fd);
+                        }
+
+                        pid
+                    }
+                }
+            }
+
+            _ => return Err(io::ErrorKind::InvalidInput.into()),
+        }
+    };
+
+    unsafe { wait_with(wait_function, pid) }
+}
+
+/// Wait for a process with one of the replaced functions.
+unsafe fn wait_with(function: &str, pid: libc::pid_t) -> io::Result<c_int> {
+    use super::waitpid::*;
+
+    let mut status = 0;
+    let ret = match function {
+        "waitpid" => waitpid_wrapper(pid, &mut status, 0),
+        "wait3" => wait3_wrapper(&mut status, 0, ptr::null_mut()),
+        "wait4" => wait4_wrapper(pid, &mut status, 0, ptr::null_mut()),
+
+        // Reap the process without updating the history.
+        "unhooked" => libc::waitpid(pid, &mut status, 0),
+
+        "waitid" => {
+            let mut info = std::mem::zeroed::<libc::siginfo_t>();
+            let ret = waitid_wrapper(libc::P_PID, pid as libc::id_t, &mut info, libc::WEXITED);
+            if ret == 0 {
+                // Build a status like the one from wait4.
+                status = (info.si_status() & 0xff) << 8;
+            }
+            ret
+        }
+
+        _ => return Err(io::ErrorKind::InvalidInput.into()),
+    };
+
+    if ret == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(status)
+}
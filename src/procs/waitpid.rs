@@ -1,12 +1,23 @@
-//! Wrapper for `waitpid()`
+//! Wrappers for `waitpid()` and the other functions of the wait family.
+//!
+//! All wrappers use `wait4` (or the `waitid` syscall) to get the resources
+//! used by the process, and then send them to the history with the same
+//! logic.
+//!
+//! Before reaping a process, its start time is read from `/proc/<pid>/stat`,
+//! so the event can be matched with the right history entry even if the pid
+//! is reused. When the wrapper can wait for any child (like `waitpid(-1,
+//! ...)`), `waitid` with `WNOWAIT` is used to know which child will be
+//! reaped.
 
-use libc::{c_int, pid_t};
-use std::io::{stderr, Write};
+use libc::{c_int, id_t, idtype_t, pid_t, rusage, siginfo_t};
 use std::mem::MaybeUninit;
+use std::ptr;
 use std::time::Duration;
 
+use super::procstat;
 use crate::history;
-use crate::ipc::events::{collect_events, WaitEvent};
+use crate::ipc::events::{pending, WaitEvent};
 
 /// Timeout to send results for `wait4` to the shared buffer.
 const EVENT_TIMEOUT: Duration = Duration::from_millis(250);
@@ -17,67 +28,290 @@ pub(super) unsafe extern "C" fn waitpid_wrapper(
     wstatus: *mut c_int,
     options: c_int,
 ) -> pid_t {
-    let mut rusage = MaybeUninit::zeroed();
-    let ret = libc::wait4(pid, wstatus, options, rusage.as_mut_ptr());
+    wait4_wrapper(pid, wstatus, options, std::ptr::null_mut())
+}
 
-    // Get current time before doing anything else.
-    let finish_time = {
-        let mut ts = MaybeUninit::zeroed();
-        libc::clock_gettime(libc::CLOCK_MONOTONIC, ts.as_mut_ptr());
-        ts.assume_init()
+/// Function to replace wait3().
+pub(super) unsafe extern "C" fn wait3_wrapper(
+    wstatus: *mut c_int,
+    options: c_int,
+    rusage: *mut rusage,
+) -> pid_t {
+    wait4_wrapper(-1, wstatus, options, rusage)
+}
+
+/// Function to replace wait4().
+pub(super) unsafe extern "C" fn wait4_wrapper(
+    pid: pid_t,
+    wstatus: *mut c_int,
+    options: c_int,
+    rusage: *mut rusage,
+) -> pid_t {
+    // The status is needed to update the history, even if the caller
+    // does not request it.
+    let mut local_status = 0;
+    let wstatus = if wstatus.is_null() {
+        &mut local_status
+    } else {
+        wstatus
     };
 
-    if ret <= 0 {
-        return ret;
+    let mut local_rusage = MaybeUninit::zeroed();
+    let rusage = if rusage.is_null() {
+        local_rusage.as_mut_ptr()
+    } else {
+        rusage
+    };
+
+    // Find the process that will be reaped.
+    let (target, process_start) = if pid > 0 {
+        (pid, procstat::start_ticks(pid))
+    } else {
+        let (idtype, id) = match pid {
+            -1 => (libc::P_ALL, 0),
+            0 => (libc::P_PGID, libc::getpgrp() as id_t),
+            _ => (libc::P_PGID, -pid as id_t),
+        };
+
+        match peek(idtype, id, options) {
+            Peek::Process(child, start) => (child, start),
+            Peek::None => (pid, None),
+            Peek::Error => return -1,
+        }
+    };
+
+    let ret = libc::wait4(target, wstatus, options, rusage);
+
+    // Get current time before doing anything else.
+    let finish_time = now();
+
+    if ret > 0 {
+        let process_start = process_start.filter(|_| ret == target).unwrap_or(0);
+        record("wait4", ret, process_start, *wstatus, finish_time, *rusage);
+    }
+
+    ret
+}
+
+/// Function to replace waitid().
+///
+/// The `waitid` syscall in Linux accepts an extra argument to get the
+/// resources used by the process, which is not available in the libc
+/// function.
+pub(super) unsafe extern "C" fn waitid_wrapper(
+    idtype: idtype_t,
+    id: id_t,
+    infop: *mut siginfo_t,
+    options: c_int,
+) -> c_int {
+    // With WNOWAIT the process is still waitable, so it will be recorded
+    // by a later call.
+    if options & libc::WNOWAIT != 0 {
+        return waitid_syscall(idtype, id, infop, options, ptr::null_mut());
     }
 
-    let status = if wstatus.is_null() { -1 } else { *wstatus };
+    // Find the process that will be reaped.
+    let (idtype, id, process_start) = match peek(idtype, id, options) {
+        Peek::Process(child, start) => (libc::P_PID, child as id_t, start),
+        Peek::None => (idtype, id, None),
+        Peek::Error => return -1,
+    };
 
-    if libc::getpid() == history::OWNER_PID {
-        // We are running in the main bash process, so we can update the data
-        // in the global `HISTORY` state.
+    let mut rusage = MaybeUninit::<rusage>::zeroed();
+    let ret = waitid_syscall(idtype, id, infop, options, rusage.as_mut_ptr());
 
-        collect_events(false);
+    let finish_time = now();
 
-        if let Ok(mut history) = history::HISTORY.try_lock() {
-            history.update_entry(ret, status, finish_time, rusage.assume_init());
-        }
-    } else {
-        // This process is a subshell, so we don't have access to the `HISTORY` state.
-        //
-        // The results from `wait4` are sent through the shared buffer.
-        if let Some(shared_buffer) = crate::ipc::global_shared_buffer(EVENT_TIMEOUT) {
-            if let Err(e) = write_event(
-                shared_buffer,
-                ret,
+    if ret != 0 || infop.is_null() {
+        return ret;
+    }
+
+    let info = &*infop;
+    let pid = info.si_pid();
+
+    // With WNOHANG, si_pid is zero if no child was in a waitable state.
+    if pid > 0 {
+        if let Some(status) = status_from_siginfo(info.si_code, info.si_status()) {
+            let process_start = process_start.filter(|_| idtype == libc::P_PID).unwrap_or(0);
+            record(
+                "waitid",
+                pid,
+                process_start,
                 status,
                 finish_time,
                 rusage.assume_init(),
-            ) {
-                let _ = writeln!(stderr(), "timehistory: waitpid: {}", e);
-            }
+            );
         }
     }
 
     ret
 }
 
-unsafe fn write_event(
-    mut buffer: crate::ipc::SharedBufferGuard,
-    pid: libc::pid_t,
-    status: libc::c_int,
+/// Invoke the `waitid` syscall, which accepts a `rusage` argument.
+unsafe fn waitid_syscall(
+    idtype: idtype_t,
+    id: id_t,
+    infop: *mut siginfo_t,
+    options: c_int,
+    rusage: *mut rusage,
+) -> c_int {
+    libc::syscall(libc::SYS_waitid, idtype, id, infop, options, rusage) as c_int
+}
+
+/// Result of `peek`.
+enum Peek {
+    /// A process that can be reaped, and its start time.
+    Process(pid_t, Option<u64>),
+
+    /// No process is available, or `waitid` failed with an unexpected error.
+    /// The caller should use its original arguments.
+    None,
+
+    /// `waitid` failed with an error (like `EINTR` or `ECHILD`) that has to
+    /// be returned to the caller.
+    Error,
+}
+
+/// Find a waitable process without reaping it.
+///
+/// `options` are the flags for `wait4` or `waitid`. `WUNTRACED` and
+/// `WSTOPPED` have the same value.
+unsafe fn peek(idtype: idtype_t, id: id_t, options: c_int) -> Peek {
+    let mut info = MaybeUninit::<siginfo_t>::zeroed();
+    let options = options | libc::WEXITED | libc::WNOWAIT;
+
+    if waitid_syscall(idtype, id, info.as_mut_ptr(), options, ptr::null_mut()) != 0 {
+        return match std::io::Error::last_os_error().raw_os_error() {
+            Some(libc::EINTR) | Some(libc::ECHILD) => Peek::Error,
+            _ => Peek::None,
+        };
+    }
+
+    // With WNOHANG, si_pid is zero if no child was in a waitable state.
+    let info = info.assume_init();
+    match info.si_pid() {
+        0 => Peek::None,
+        pid => Peek::Process(pid, procstat::start_ticks(pid)),
+    }
+}
+
+/// Current time from the monotonic clock.
+fn now() -> libc::timespec {
+    let mut ts = MaybeUninit::zeroed();
+    unsafe {
+        libc::clock_gettime(libc::CLOCK_MONOTONIC, ts.as_mut_ptr());
+        ts.assume_init()
+    }
+}
+
+/// Build a status value, like the one returned by `wait4`, from the fields
+/// of a `siginfo_t` filled by `waitid`.
+fn status_from_siginfo(code: c_int, status: c_int) -> Option<c_int> {
+    let status = match code {
+        libc::CLD_EXITED => (status & 0xff) << 8,
+        libc::CLD_KILLED => status & 0x7f,
+        libc::CLD_DUMPED => (status & 0x7f) | 0x80,
+        libc::CLD_STOPPED | libc::CLD_TRAPPED => ((status & 0xff) << 8) | 0x7f,
+        libc::CLD_CONTINUED => 0xffff,
+        _ => return None,
+    };
+
+    Some(status)
+}
+
+/// Send the results of a wait function to the history.
+///
+/// This function can be called from the `SIGCHLD` handler of bash, so it
+/// can't allocate memory.
+///
+/// In the main shell, the results are stored in the `pending` queue, and
+/// they are added to the history in the next call to `collect_events`. In
+/// a subshell, or if the queue is full, they are sent through the shared
+/// buffer.
+///
+/// `function` is used only for error messages.
+unsafe fn record(
+    function: &str,
+    pid: pid_t,
+    process_start: u64,
+    status: c_int,
     finish_time: libc::timespec,
-    rusage: libc::rusage,
-) -> std::io::Result<()> {
-    let written = WaitEvent::serialize(
-        std::io::Cursor::new(buffer.output()),
+    rusage: rusage,
+) {
+    let event = WaitEvent {
         pid,
+        process_start,
         status,
         finish_time,
         rusage,
+        job: super::job_number(pid),
+    };
+
+    if libc::getpid() == history::OWNER_PID && pending::push(event) {
+        return;
+    }
+
+    // If the shared buffer is unavailable, the event is discarded without
+    // an error, since the same failure would be reported on every wait.
+    if let Some(shared_buffer) = crate::ipc::global_shared_buffer(EVENT_TIMEOUT) {
+        if write_event(shared_buffer, event).is_err() {
+            report_error(function, "shared buffer is full");
+        }
+    }
+}
+
+/// Write an error message to stderr, without allocating memory.
+unsafe fn report_error(function: &str, message: &str) {
+    for part in ["timehistory: ", function, ": ", message, "\n"] {
+        libc::write(libc::STDERR_FILENO, part.as_ptr().cast(), part.len());
+    }
+}
+
+unsafe fn write_event(
+    mut buffer: crate::ipc::SharedBufferGuard,
+    event: WaitEvent,
+) -> std::io::Result<()> {
+    let written = WaitEvent::serialize(
+        std::io::Cursor::new(buffer.output()),
+        event.pid,
+        event.process_start,
+        event.status,
+        event.finish_time,
+        event.rusage,
     )?;
 
     buffer.advance(written);
 
     Ok(())
 }
+
+#[test]
+fn siginfo_status() {
+    use crate::waitstatus::WaitStatus;
+
+    let decode = |code, status| WaitStatus::new(status_from_siginfo(code, status).unwrap());
+
+    assert!(matches!(decode(libc::CLD_EXITED, 3), WaitStatus::Exited(3)));
+
+    assert!(matches!(
+        decode(libc::CLD_KILLED, libc::SIGTERM),
+        WaitStatus::Signalled { signal, core_dumped: false } if signal == libc::SIGTERM
+    ));
+
+    assert!(matches!(
+        decode(libc::CLD_DUMPED, libc::SIGSEGV),
+        WaitStatus::Signalled { signal, core_dumped: true } if signal == libc::SIGSEGV
+    ));
+
+    assert!(matches!(
+        decode(libc::CLD_STOPPED, libc::SIGTSTP),
+        WaitStatus::Stopped(signal) if signal == libc::SIGTSTP
+    ));
+
+    assert!(matches!(
+        decode(libc::CLD_CONTINUED, libc::SIGCONT),
+        WaitStatus::Continued
+    ));
+
+    assert_eq!(status_from_siginfo(0, 0), None);
+}
@@ -0,0 +1,464 @@
+//! Redaction of secrets in command lines.
+//!
+//! `TIMEHISTORY_REDACT` is a list of glob patterns, separated by colons, for
+//! the names of parameters with secret values. Names are matched ignoring
+//! case. A secret value is replaced by `<redacted>` when it appears:
+//!
+//! * In an argument like `NAME=value` (`--password=x`, `TOKEN=x`).
+//! * In the argument after `NAME`, if the name starts with `-`
+//!   (`--password x`). Names ending with a word like `-file` or `-path`
+//!   (`--token-file x`) are skipped, since their values refer to the secret.
+//! * In an argument like `NAME: value` (`Authorization: Bearer x`).
+//! * In a query parameter of a URL (`https://host/?token=x`).
+//!
+//! Passwords in the user information of a URL (`https://user:x@host`) are
+//! always redacted.
+//!
+//! Secrets found in the arguments are also removed from the command line
+//! from the shell (`$BASH_COMMAND`), when they are not part of a bigger word.
+//! If a secret is not found there (for example, because it was quoted or
+//! escaped), the whole command line is replaced by `<redacted>`.
+//!
+//! Values are redacted in the process that executes the program, before the
+//! event is written to the shared buffer. Nested shells receive the patterns
+//! of the main shell in their environment.
+
+use std::ffi::{CStr, CString, OsString};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use crate::config::Setting;
+use memchr::{memchr, memmem, memrchr};
+use once_cell::sync::Lazy;
+
+use crate::filters::Patterns;
+
+/// Text to replace secret values.
+pub const MARKER: &[u8] = b"<redacted>";
+
+/// Default value for `TIMEHISTORY_REDACT`.
+const DEFAULT_PATTERNS: &[u8] =
+    b"*passw*:*token*:*secret*:*api?key*:*authorization*:*credential*\0";
+
+/// Last words of option names with values that refer to a secret, instead of
+/// containing it, like `--token-file path`.
+const REFERENCE_WORDS: &[&[u8]] = &[b"file", b"path", b"dir", b"env", b"name", b"id"];
+
+/// Patterns used by the wrappers of the exec functions.
+///
+/// They are not stored in the history, since its lock can be held when a
+/// program is executed. This lock is only held to read or replace the
+/// patterns.
+static REDACTOR: Lazy<Mutex<Redactor>> = Lazy::new(Default::default);
+
+/// Access the global patterns.
+pub fn redactor() -> MutexGuard<'static, Redactor> {
+    REDACTOR.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Replace the global patterns.
+pub fn set_patterns(source: &CStr) {
+    redactor().patterns = Patterns::parse(source);
+}
+
+/// Patterns to detect secrets.
+pub struct Redactor {
+    patterns: Patterns,
+}
+
+impl Default for Redactor {
+    fn default() -> Self {
+        let source = CStr::from_bytes_with_nul(DEFAULT_PATTERNS).unwrap();
+        Redactor {
+            patterns: Patterns::parse(source),
+        }
+    }
+}
+
+impl Redactor {
+    pub fn patterns(&self) -> &Patterns {
+        &self.patterns
+    }
+
+    /// Redact secrets in the arguments of a program, and in the command line
+    /// from the shell.
+    ///
+    /// Returns `true` if any value was redacted.
+    pub fn redact(&self, args: &mut [OsString], shell_command: &mut OsString) -> bool {
+        let mut secrets = Vec::new();
+        let mut redact_next = false;
+
+        for arg in args.iter_mut() {
+            let bytes = arg.as_bytes();
+
+            if std::mem::take(&mut redact_next) {
+                secrets.push(bytes.to_vec());
+                *arg = OsString::from_vec(MARKER.to_vec());
+                continue;
+            }
+
+            if let Some(redacted) = self.redact_word(bytes, &mut secrets) {
+                *arg = OsString::from_vec(redacted);
+                continue;
+            }
+
+            // Options like `--password x`.
+            redact_next = bytes.starts_with(b"-")
+                && !bytes.contains(&b'=')
+                && !is_reference(bytes)
+                && self.is_secret(bytes);
+        }
+
+        if secrets.is_empty() {
+            return false;
+        }
+
+        // Replace the longest values first, in case a secret contains
+        // another one.
+        secrets.sort_by_key(|s| std::cmp::Reverse(s.len()));
+
+        let mut command = shell_command.as_bytes().to_vec();
+        for (idx, secret) in secrets.iter().enumerate() {
+            if secret.is_empty() || command.is_empty() {
+                continue;
+            }
+
+            match replace_words(&command, secret, MARKER) {
+                Some(redacted) => command = redacted,
+
+                // Already replaced as part of a longer secret.
+                None if secrets[..idx]
+                    .iter()
+                    .any(|s| memmem::find(s, secret).is_some()) => {}
+
+                // The secret is written differently in the shell, so it can't
+                // be removed without parsing the command line.
+                None => {
+                    command = MARKER.to_vec();
+                    break;
+                }
+            }
+        }
+
+        *shell_command = OsString::from_vec(command);
+        true
+    }
+
+    /// Returns `true` if `name` matches any of the patterns.
+    fn is_secret(&self, name: &[u8]) -> bool {
+        !name.is_empty() && self.patterns.matches_ignore_case(name)
+    }
+
+    /// Redact a single argument. Returns `None` if the argument does not
+    /// contain any secret.
+    fn redact_word(&self, arg: &[u8], secrets: &mut Vec<Vec<u8>>) -> Option<Vec<u8>> {
+        if memmem::find(arg, b"://").is_some() {
+            return self.redact_url(arg, secrets);
+        }
+
+        // Find the end of the name in `NAME=value` or `NAME: value`.
+        let separator = arg.iter().position(|c| *c == b'=' || *c == b':')?;
+        let name = &arg[..separator];
+
+        if name.iter().any(|c| *c == b'/' || c.is_ascii_whitespace()) || !self.is_secret(name) {
+            return None;
+        }
+
+        let mut value_start = separator + 1;
+        while arg.get(value_start).map_or(false, u8::is_ascii_whitespace) {
+            value_start += 1;
+        }
+
+        if value_start == arg.len() {
+            return None;
+        }
+
+        secrets.push(arg[value_start..].to_vec());
+
+        let mut redacted = arg[..value_start].to_vec();
+        redacted.extend_from_slice(MARKER);
+        Some(redacted)
+    }
+
+    /// Redact the password in the user information of a URL, and the values
+    /// of its query parameters.
+    fn redact_url(&self, arg: &[u8], secrets: &mut Vec<Vec<u8>>) -> Option<Vec<u8>> {
+        let start = memmem::find(arg, b"://")? + 3;
+        let mut output = arg[..start].to_vec();
+        let mut changed = false;
+
+        let mut rest = &arg[start..];
+
+        // User information, before the host.
+        let authority_len = rest
+            .iter()
+            .position(|c| matches!(c, b'/' | b'?' | b'#'))
+            .unwrap_or(rest.len());
+
+        if let Some(at) = memrchr(b'@', &rest[..authority_len]) {
+            let userinfo = &rest[..at];
+            if let Some(colon) = memchr(b':', userinfo) {
+                output.extend_from_slice(&userinfo[..=colon]);
+                output.extend_from_slice(MARKER);
+                secrets.push(userinfo[colon + 1..].to_vec());
+                changed = true;
+                rest = &rest[at..];
+            }
+        }
+
+        // Query parameters.
+        if let Some(question) = memchr(b'?', rest) {
+            output.extend_from_slice(&rest[..=question]);
+            rest = &rest[question + 1..];
+
+            let query_len = memchr(b'#', rest).unwrap_or(rest.len());
+            for (idx, param) in rest[..query_len].split(|c| *c == b'&').enumerate() {
+                if idx > 0 {
+                    output.push(b'&');
+                }
+
+                match memchr(b'=', param) {
+                    Some(eq) if self.is_secret(&param[..eq]) && eq + 1 < param.len() => {
+                        output.extend_from_slice(&param[..=eq]);
+                        output.extend_from_slice(MARKER);
+                        secrets.push(param[eq + 1..].to_vec());
+                        changed = true;
+                    }
+
+                    _ => output.extend_from_slice(param),
+                }
+            }
+
+            rest = &rest[query_len..];
+        }
+
+        output.extend_from_slice(rest);
+
+        if changed {
+            Some(output)
+        } else {
+            None
+        }
+    }
+}
+
+/// Returns `true` if the option name ends with a word from `REFERENCE_WORDS`.
+fn is_reference(name: &[u8]) -> bool {
+    let last_word = match name.iter().rposition(|c| *c == b'-' || *c == b'_') {
+        Some(pos) => &name[pos + 1..],
+        None => return false,
+    };
+
+    REFERENCE_WORDS
+        .iter()
+        .any(|word| word.eq_ignore_ascii_case(last_word))
+}
+
+/// Replace the occurrences of `needle` in `haystack` that are not part of a
+/// bigger word.
+///
+/// Without this check, a short secret (like `--password e`) would destroy
+/// the rest of the command line.
+///
+/// Returns `None` if there is no occurrence to replace.
+fn replace_words(haystack: &[u8], needle: &[u8], replacement: &[u8]) -> Option<Vec<u8>> {
+    let is_word = |c: Option<&u8>| c.map_or(false, |c| c.is_ascii_alphanumeric() || *c == b'_');
+
+    let mut output = Vec::with_capacity(haystack.len());
+    let mut last = 0;
+
+    for pos in memmem::find_iter(haystack, needle) {
+        let end = pos + needle.len();
+
+        // Skip overlapping matches, and matches inside a word.
+        if pos < last
+            || (is_word(needle.first())
+                && is_word(pos.checked_sub(1).and_then(|p| haystack.get(p))))
+            || (is_word(needle.last()) && is_word(haystack.get(end)))
+        {
+            continue;
+        }
+
+        output.extend_from_slice(&haystack[last..pos]);
+        output.extend_from_slice(replacement);
+        last = end;
+    }
+
+    if last == 0 {
+        return None;
+    }
+
+    output.extend_from_slice(&haystack[last..]);
+    Some(output)
+}
+
+/// Dynamic variable to control the redaction patterns.
+pub struct RedactVariable;
+
+impl Setting for RedactVariable {
+    fn get(&mut self) -> std::option::Option<CString> {
+        Some(redactor().patterns.source().to_owned())
+    }
+
+    fn set(&mut self, value: &CStr) -> bool {
+        set_patterns(value);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn redact(args: &[&str], shell_command: &str) -> (Vec<String>, String, bool) {
+        let mut args: Vec<OsString> = args.iter().map(OsString::from).collect();
+        let mut shell_command = OsString::from(shell_command);
+
+        let redacted = Redactor::default().redact(&mut args, &mut shell_command);
+
+        let args = args
+            .iter()
+            .map(|a| a.to_string_lossy().into_owned())
+            .collect();
+        (args, shell_command.to_string_lossy().into_owned(), redacted)
+    }
+
+    #[test]
+    fn credential_options() {
+        assert_eq!(
+            redact(
+                &[
+                    "mysql",
+                    "--password=s3cr3t",
+                    "--user",
+                    "root",
+                    "--api-key",
+                    "K1"
+                ],
+                "mysql --password=s3cr3t --user root --api-key K1",
+            ),
+            (
+                vec![
+                    "mysql".into(),
+                    "--password=<redacted>".into(),
+                    "--user".into(),
+                    "root".into(),
+                    "--api-key".into(),
+                    "<redacted>".into(),
+                ],
+                "mysql --password=<redacted> --user root --api-key <redacted>".into(),
+                true
+            )
+        );
+    }
+
+    #[test]
+    fn headers_and_urls() {
+        let (args, shell_command, redacted) = redact(
+            &[
+                "curl",
+                "-H",
+                "Authorization: Bearer abc",
+                "https://user:pw@example.com/path?q=1&access_token=xyz#top",
+            ],
+            "curl -H 'Authorization: Bearer abc' 'https://user:pw@example.com/path?q=1&access_token=xyz#top'",
+        );
+
+        assert!(redacted);
+        assert_eq!(
+            args[2..],
+            [
+                "Authorization: <redacted>",
+                "https://user:<redacted>@example.com/path?q=1&access_token=<redacted>#top"
+            ]
+        );
+
+        assert_eq!(
+            shell_command,
+            "curl -H 'Authorization: <redacted>' 'https://user:<redacted>@example.com/path?q=1&access_token=<redacted>#top'"
+        );
+    }
+
+    #[test]
+    fn short_secrets() {
+        assert_eq!(
+            redact(
+                &["/bin/echo", "--password", "e", "hello", "there"],
+                "/bin/echo --password e hello there",
+            ),
+            (
+                vec![
+                    "/bin/echo".into(),
+                    "--password".into(),
+                    "<redacted>".into(),
+                    "hello".into(),
+                    "there".into(),
+                ],
+                "/bin/echo --password <redacted> hello there".into(),
+                true
+            )
+        );
+
+        let (_, shell_command, _) =
+            redact(&["tool", "--token", "ab"], "tool --token 'ab' abc xab_");
+        assert_eq!(shell_command, "tool --token '<redacted>' abc xab_");
+    }
+
+    #[test]
+    fn reference_options() {
+        let (args, _, redacted) = redact(
+            &["tool", "--token-file", "/run/token", "--PASSWORD_PATH", "p"],
+            "tool --token-file /run/token --PASSWORD_PATH p",
+        );
+
+        assert_eq!(args[2], "/run/token");
+        assert_eq!(args[4], "p");
+        assert!(!redacted);
+
+        let (args, _, _) = redact(&["tool", "--secret-key", "k"], "tool --secret-key k");
+        assert_eq!(args[2], "<redacted>");
+    }
+
+    #[test]
+    fn quoted_secrets() {
+        let (args, shell_command, _) = redact(
+            &["/bin/true", "--password=it's"],
+            r"/bin/true --password='it'\''s'",
+        );
+
+        assert_eq!(args[1], "--password=<redacted>");
+        assert_eq!(shell_command, "<redacted>");
+
+        let (_, shell_command, _) = redact(&["tool", "--token", "a$b"], r#"tool --token "a\$b""#);
+        assert_eq!(shell_command, "<redacted>");
+
+        // A secret inside a longer secret.
+        let (_, shell_command, _) = redact(
+            &["tool", "--token", "abc", "--password=x abc"],
+            "tool --token abc --password='x abc'",
+        );
+        assert_eq!(
+            shell_command,
+            "tool --token <redacted> --password='<redacted>'"
+        );
+    }
+
+    #[test]
+    fn no_secrets() {
+        let args = [
+            "ls",
+            "-l",
+            "/tmp/token",
+            "https://example.com/?page=2",
+            "a=b",
+        ];
+        assert_eq!(
+            redact(&args, "ls"),
+            (
+                args.iter().map(|a| a.to_string()).collect(),
+                "ls".into(),
+                false
+            )
+        );
+    }
+}
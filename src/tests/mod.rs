@@ -20,8 +20,8 @@ fn build() -> PathBuf {
     let build = Command::new(env::var("CARGO").unwrap())
         .arg("build")
         .arg("--quiet")
-        .args(&["--features", "option-for-panics"])
-        .args(&["--message-format", "json"])
+        .args(["--features", "option-for-panics,option-for-exec-tests"])
+        .args(["--message-format", "json"])
         .output()
         .unwrap();
 
@@ -33,7 +33,10 @@ fn build() -> PathBuf {
         if let Ok(msg) = json::from_slice::<json::Value>(line) {
             if msg["reason"] == "compiler-artifact" {
                 let target = &msg["target"];
-                if target["kind"][0] == "cdylib" && target["name"] == env!("CARGO_PKG_NAME") {
+                if target["kind"][0] == "cdylib"
+                    && target["name"].as_str().map(|n| n.replace('-', "_"))
+                        == Some(env!("CARGO_PKG_NAME").replace('-', "_"))
+                {
                     if let Some(file) = msg["filenames"][0].as_str() {
                         return PathBuf::from(file);
                     }
@@ -99,6 +102,11 @@ fn run_shell_tests() {
 
     let test_runner = create_runner_file(&target);
 
+    // Empty directory for XDG_CONFIG_HOME, so the tests don't read the
+    // configuration file of the user.
+    let config_home = target.join("config");
+    std::fs::create_dir_all(&config_home).unwrap();
+
     let mut failed = 0;
     for source in fs::read_dir("src/tests/shell").unwrap() {
         let path = source.unwrap().path();
@@ -114,6 +122,7 @@ fn run_shell_tests() {
 
         let bash = Command::new("bash")
             .env("LC_ALL", "C")
+            .env("XDG_CONFIG_HOME", &config_home)
             .env(TEST_FILE_VAR, &path)
             .stdin(Stdio::null())
             .stdout(File::create(&output_path).unwrap())
@@ -0,0 +1,58 @@
+# Test to write entries in associative arrays.
+
+load_builtin
+
+TIMEHISTORY_TAG=first
+/bin/sh -c 'exit 3' || :
+
+TIMEHISTORY_TAG=
+/bin/echo a 'b c' > /dev/null
+
+timehistory -A info
+
+ASSERT_OUTPUT \
+  'echo "${info[number]} ${info[status]} ${info[state]} ${info[argc]} ${info[args]}"' \
+  "2 0 finished 3 /bin/echo a 'b c'"
+
+[[ ${info[elapsed]} =~ ^[0-9]+\.[0-9]+$ ]]
+[[ ${info[maxrss]} -gt 0 ]]
+
+# Fields from the previous entry are replaced. Other keys are kept.
+info[extra]=1
+timehistory -A info +2
+
+ASSERT_OUTPUT \
+  'echo "${info[number]} ${info[status]} ${info[tags]} ${info[extra]:-none}"' \
+  '1 3 first 1'
+
+# Local arrays in functions.
+get_status() {
+  local -A entry
+  timehistory -A entry "$1"
+  echo "${entry[filename]} ${entry[status]}"
+}
+
+ASSERT_OUTPUT 'get_status 1' '/bin/sh 3'
+[[ ! -v entry ]]
+
+# Tags added with -t.
+timehistory -t last
+timehistory -A info
+
+ASSERT_OUTPUT \
+  'echo "${info[number]} ${info[tags]}"' \
+  '2 last'
+
+# Errors.
+declare -a indexed
+ASSERT_OUTPUT \
+  "timehistory -A indexed 2> /dev/null || echo status=\$?" \
+  "status=1"
+
+ASSERT_OUTPUT \
+  "timehistory -A info 100 2> /dev/null || echo status=\$?" \
+  "status=1"
+
+ASSERT_OUTPUT \
+  "timehistory -A info -j 2> /dev/null || echo status=\$?" \
+  "status=2"
@@ -6,15 +6,29 @@ load_builtin
 TIMEHISTORY_LIMIT=5000
 TIMEHISTORY_FORMAT='%n\t%P\t%C'
 TIMEHISTORY_CMDLINE_LIMIT=1000
+TIMEHISTORY_IGNORE='ls:*/git'
+TIMEHISTORY_MIN_DURATION=0.25
+TIMEHISTORY_REDACT='*pass*:*pin'
+TIMEHISTORY_TAG='JIRA-1234'
 
 ASSERT_OUTPUT \
   "timehistory -s" \
   <<-'ITEMS'
-	TIMEHISTORY_FORMAT        = %n\t%P\t%C
-	TIMEHISTORY_LIMIT         = 5000
-	TIMEHISTORY_CMDLINE_LIMIT = 1000
+	TIMEHISTORY_FORMAT        (shell)   = %n\t%P\t%C
+	TIMEHISTORY_PROMPT_FORMAT (default) = %n: %C (%es, %P CPU, %M KiB)
+	TIMEHISTORY_LIMIT         (shell)   = 5000
+	TIMEHISTORY_CMDLINE_LIMIT (shell)   = 1000
+	TIMEHISTORY_IGNORE        (shell)   = ls:*/git
+	TIMEHISTORY_ONLY          (default) = 
+	TIMEHISTORY_MIN_DURATION  (shell)   = 0.25
+	TIMEHISTORY_REDACT        (shell)   = *pass*:*pin
+	TIMEHISTORY_TAG           (shell)   = JIRA-1234
+	TIMEHISTORY_NESTED        (default) = 0
 ITEMS
 
+TIMEHISTORY_IGNORE=
+TIMEHISTORY_MIN_DURATION=0
+TIMEHISTORY_TAG=
 timehistory -s format='> %C'
 
 command expr 1 + 2
@@ -30,7 +44,72 @@ ASSERT_OUTPUT \
   '%N\t%P 123'
 
 
-# Check variables after deleting the builtin.
+# Invalid formats are rejected on assignment.
+ASSERT_OUTPUT \
+  "TIMEHISTORY_FORMAT='[heder]%n'" \
+  "timehistory: 'heder': invalid format option"
+
+ASSERT_OUTPUT \
+  "TIMEHISTORY_FORMAT='@unknown'" \
+  "timehistory: @unknown: unknown format"
+
+ASSERT_OUTPUT \
+  "TIMEHISTORY_FORMAT='%(elapsd) %Q'" \
+  "timehistory: '%(elapsd)': unknown format specifier"
+
+ASSERT_OUTPUT \
+  "TIMEHISTORY_FORMAT='%n %Q'" \
+  "timehistory: '%Q': unknown format specifier"
+
+ASSERT_OUTPUT 'echo "$TIMEHISTORY_FORMAT"' '%N\t%P'
+
+ASSERT_OUTPUT \
+  "TIMEHISTORY_PROMPT_FORMAT='%n %Q'" \
+  "timehistory: '%Q': unknown format specifier"
+
+ASSERT_OUTPUT \
+  'echo "$TIMEHISTORY_PROMPT_FORMAT"' \
+  '%n: %C (%es, %P CPU, %M KiB)'
+
+# An empty value restores the default format.
+TIMEHISTORY_FORMAT=
+ASSERT_OUTPUT \
+  'echo "$TIMEHISTORY_FORMAT"' \
+  '[header,table]%n\t%(time:%X)\t%P\t%e\t%C'
+
+# Settings as JSON.
+TIMEHISTORY_FORMAT='[table]%n %C'
+TIMEHISTORY_CMDLINE_LIMIT=512
+TIMEHISTORY_TAG=
+TIMEHISTORY_REDACT='*pin'
+TIMEHISTORY_PROMPT_FORMAT='%n'
+
+ASSERT_OUTPUT \
+  "timehistory -s -j" \
+  <<-'ITEMS'
+	{"buffer_size":1048576,"config_file":null,"format":{"format":"%n %C","header":false,"table":true},"formats":{},"variables":{"TIMEHISTORY_CMDLINE_LIMIT":{"source":"shell","value":512},"TIMEHISTORY_FORMAT":{"source":"shell","value":"[table]%n %C"},"TIMEHISTORY_IGNORE":{"source":"shell","value":""},"TIMEHISTORY_LIMIT":{"source":"shell","value":123},"TIMEHISTORY_MIN_DURATION":{"source":"shell","value":0.0},"TIMEHISTORY_NESTED":{"source":"default","value":false},"TIMEHISTORY_ONLY":{"source":"default","value":""},"TIMEHISTORY_PROMPT_FORMAT":{"source":"shell","value":"%n"},"TIMEHISTORY_REDACT":{"source":"shell","value":"*pin"},"TIMEHISTORY_TAG":{"source":"shell","value":""}}}
+ITEMS
+
+ASSERT_FAILS "timehistory -s -v 2> /dev/null"
+
+# Values assigned in the prefix of the command, or with local, are used
+# instead of the global value.
+/bin/true prefix
+
+ASSERT_OUTPUT \
+  "TIMEHISTORY_FORMAT='PREFIX %C' timehistory +1" \
+  "PREFIX /bin/true prefix"
+
+ASSERT_OUTPUT \
+  "f() { local TIMEHISTORY_FORMAT='LOCAL %C'; timehistory +1; }; f" \
+  "LOCAL /bin/true prefix"
+
+ASSERT_OUTPUT 'echo "$TIMEHISTORY_FORMAT"' '[table]%n %C'
+
+
+# Variables are removed after deleting the builtin, except the format
+# assigned from the shell.
+TIMEHISTORY_FORMAT='%N\t%P'
 enable -d timehistory
 ASSERT_OUTPUT \
   'echo "${TIMEHISTORY_FORMAT:-NA} ${TIMEHISTORY_LIMIT:-NA}"' \
@@ -0,0 +1,82 @@
+# Test for the completion script.
+
+load_builtin
+
+source completion/timehistory.bash
+
+for n in {1..11}
+do
+  /bin/true "$n"
+done
+
+# Options.
+_timehistory timehistory -R timehistory
+ASSERT_OUTPUT 'echo "${COMPREPLY[@]}"' '-R'
+
+# Entry numbers, with a preview if there are multiple candidates.
+_timehistory timehistory 1 timehistory
+
+ASSERT_OUTPUT \
+  'printf "%s\n" "${COMPREPLY[@]}"' \
+  <<-ITEMS
+	1  /bin/true 1
+	10  /bin/true 10
+	11  /bin/true 11
+ITEMS
+
+_timehistory timehistory 9 -d
+ASSERT_OUTPUT 'echo "${COMPREPLY[@]}"' '9'
+
+# Offsets from the end of the list.
+_timehistory timehistory +1 timehistory
+
+ASSERT_OUTPUT \
+  'printf "%s\n" "${COMPREPLY[@]}"' \
+  <<-ITEMS
+	+1  /bin/true 11
+	+10  /bin/true 2
+	+11  /bin/true 1
+ITEMS
+
+_timehistory timehistory +3 -d
+ASSERT_OUTPUT 'echo "${COMPREPLY[@]}"' '+3'
+
+# Ranges in -d.
+_timehistory timehistory 9-1 -d
+
+ASSERT_OUTPUT \
+  'printf "%s\n" "${COMPREPLY[@]}"' \
+  <<-ITEMS
+	9-10  /bin/true 10
+	9-11  /bin/true 11
+ITEMS
+
+_timehistory timehistory 9-11 -d
+ASSERT_OUTPUT 'echo "${COMPREPLY[@]}"' '9-11'
+
+# Ranges are not valid to select an entry.
+_timehistory timehistory 9- timehistory
+ASSERT_OUTPUT 'echo "${#COMPREPLY[@]}"' '0'
+
+# Specifiers in a format string.
+_timehistory timehistory '%n %(ti' -f
+
+ASSERT_OUTPUT \
+  'printf "%s\n" "${COMPREPLY[@]}"' \
+  <<-ITEMS
+	%n %(time:
+ITEMS
+
+_timehistory timehistory '%(st' -f
+
+ASSERT_OUTPUT \
+  'printf "%s\n" "${COMPREPLY[@]}"' \
+  <<-ITEMS
+	%(state)
+	%(status)
+	%(stopped_time)
+	%(stops)
+ITEMS
+
+_timehistory timehistory 'he' -f
+ASSERT_OUTPUT 'echo "${COMPREPLY[@]}"' 'help'
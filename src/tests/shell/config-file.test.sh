@@ -0,0 +1,98 @@
+# Test to read settings from the configuration file.
+
+XDG_CONFIG_HOME=$(mktemp -d)
+CONFIG_FILE="$XDG_CONFIG_HOME/timehistory/config.toml"
+
+mkdir "$XDG_CONFIG_HOME/timehistory"
+cat > "$CONFIG_FILE" <<'TOML'
+# Settings for the team.
+limit = 500
+format = "@short"
+prompt_format = "%n: %C"
+ignore = ["ls", "*/git"]
+min_duration = 0
+tag = "team"
+sizes = 1
+
+[formats]
+short = '%n %C'
+tagged = "%n [%(tags)]"
+TOML
+
+# Shell variables defined before loading the builtin have priority.
+TIMEHISTORY_LIMIT=20
+
+# Unknown keys are reported when the builtin is loaded.
+load_builtin 2> "$XDG_CONFIG_HOME/errors"
+ERRORS=$(< "$XDG_CONFIG_HOME/errors")
+
+ASSERT_OUTPUT \
+  'echo "${ERRORS#*warning: }"' \
+  "$CONFIG_FILE: unknown key 'sizes'"
+
+ASSERT_OUTPUT \
+  "timehistory -s" \
+  <<-ITEMS
+	# $CONFIG_FILE
+	TIMEHISTORY_FORMAT        (config)  = %n %C
+	TIMEHISTORY_PROMPT_FORMAT (config)  = %n: %C
+	TIMEHISTORY_LIMIT         (shell)   = 20
+	TIMEHISTORY_CMDLINE_LIMIT (default) = 512
+	TIMEHISTORY_IGNORE        (config)  = ls:*/git
+	TIMEHISTORY_ONLY          (default) = 
+	TIMEHISTORY_MIN_DURATION  (config)  = 0
+	TIMEHISTORY_REDACT        (default) = *passw*:*token*:*secret*:*api?key*:*authorization*:*credential*
+	TIMEHISTORY_TAG           (config)  = team
+	TIMEHISTORY_NESTED        (default) = 0
+	@short                    (config)  = %n %C
+	@tagged                   (config)  = %n [%(tags)]
+ITEMS
+
+command ls -d / > /dev/null
+command expr 1 + 2 > /dev/null
+
+ASSERT_OUTPUT \
+  "timehistory" \
+  "1 expr 1 '+' 2"
+
+ASSERT_OUTPUT \
+  "timehistory -f @tagged" \
+  "1 [team]"
+
+ASSERT_OUTPUT \
+  "timehistory -p" \
+  "1: expr 1 '+' 2"
+
+ASSERT_FAILS "timehistory -f @missing 2> /dev/null"
+
+# Shell variables override the file after loading.
+TIMEHISTORY_TAG=
+TIMEHISTORY_FORMAT='> %C'
+
+ASSERT_OUTPUT \
+  "timehistory -s | grep -E 'FORMAT|TAG'" \
+  <<-'ITEMS'
+	TIMEHISTORY_FORMAT        (shell)   = > %C
+	TIMEHISTORY_PROMPT_FORMAT (config)  = %n: %C
+	TIMEHISTORY_TAG           (shell)   = 
+ITEMS
+
+# Rejected values keep the previous source. Accepted values are from the
+# shell, even if they don't change the current value.
+{ TIMEHISTORY_CMDLINE_LIMIT=-1; } 2> /dev/null
+TIMEHISTORY_MIN_DURATION=0.0
+
+ASSERT_OUTPUT \
+  "timehistory -s | grep -E 'CMDLINE_LIMIT|MIN_DURATION'" \
+  <<-'ITEMS'
+	TIMEHISTORY_CMDLINE_LIMIT (default) = 512
+	TIMEHISTORY_MIN_DURATION  (shell)   = 0
+ITEMS
+
+# Complete named formats.
+source completion/timehistory.bash
+
+_timehistory timehistory '@' -f
+ASSERT_OUTPUT 'echo "${COMPREPLY[@]}"' '@short @tagged'
+
+rm -r "$XDG_CONFIG_HOME"
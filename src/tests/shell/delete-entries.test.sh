@@ -0,0 +1,47 @@
+# Test to delete entries from the history.
+
+load_builtin
+
+for n in {1..8}
+do
+  /bin/true "$n"
+done
+
+/bin/echo --password=secret > /dev/null
+
+timehistory -d 2 -d 4-6 -d +2
+timehistory -d '*password*'
+
+ASSERT_OUTPUT \
+  "timehistory -f '%n %C'" \
+  <<-ITEMS
+	1 /bin/true 1
+	3 /bin/true 3
+	7 /bin/true 7
+ITEMS
+
+# Patterns can match the program.
+timehistory -d '*/true:nothing'
+test -z "$(timehistory -f '%n')"
+
+# Numbers are not reused.
+/bin/true 10
+
+ASSERT_OUTPUT \
+  "timehistory -f '%n %C'" \
+  "10 /bin/true 10"
+
+# Fail if a number is not found.
+ASSERT_OUTPUT \
+  "timehistory -d 3 2> /dev/null || echo status=\$?" \
+  "status=1"
+
+# Offsets are relative to the entries in the history, after deletions.
+timehistory -R
+/bin/true 11
+/bin/true 12
+timehistory -d +1
+
+ASSERT_OUTPUT \
+  "timehistory -f '%n %C' +1" \
+  "11 /bin/true 11"
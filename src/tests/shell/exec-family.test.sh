@@ -0,0 +1,50 @@
+# Test the wrappers for the functions to execute programs that are not used
+# by bash.
+#
+# The -X option calls the functions from the builtin, and they are replaced
+# with the same hooks used for bash.
+
+load_builtin
+
+timehistory -X execv /bin/true 1
+timehistory -X execvp true 2
+timehistory -X execvpe true 3
+timehistory -X fexecve /bin/true 4
+timehistory -X posix_spawn /bin/true 5
+timehistory -X posix_spawnp false 6 || :
+
+ASSERT_OUTPUT \
+  "timehistory -f '%C %(shell_status)'" \
+  <<-ITEMS
+	/bin/true 1 0
+	true 2 0
+	true 3 0
+	/bin/true 4 0
+	/bin/true 5 0
+	false 6 1
+ITEMS
+
+# Variadic functions can't be replaced, so their programs are not tracked.
+
+timehistory -R > /dev/null
+
+timehistory -X execl /bin/true
+timehistory -X execvp true 7
+
+ASSERT_OUTPUT "timehistory -f '%C'" "true 7"
+
+# Wait functions.
+
+timehistory -R > /dev/null
+
+timehistory -X posix_spawn/wait3 /bin/true 1
+timehistory -X posix_spawn/wait4 /bin/true 2
+timehistory -X posix_spawn/waitid /bin/false 3 || :
+
+ASSERT_OUTPUT \
+  "timehistory -f '%C %(shell_status) %(state)'" \
+  <<-ITEMS
+	/bin/true 1 0 finished
+	/bin/true 2 0 finished
+	/bin/false 3 1 finished
+ITEMS
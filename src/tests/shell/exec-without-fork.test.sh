@@ -0,0 +1,29 @@
+# Test to track programs executed without a fork.
+
+load_builtin
+
+# A failed exec in the main shell. bash exits if it fails with `set -e`,
+# even if execfail is enabled.
+shopt -s execfail
+set +e
+exec /nonexistent/program 2> /dev/null
+set -e
+
+# Nested shells replaced by a program.
+TIMEHISTORY_NESTED=1
+
+bash -c 'exec /bin/true 1'
+bash -c '/bin/true 2; exec /bin/false 3' || :
+
+ASSERT_OUTPUT \
+  "timehistory -f '%(depth) %Tx %C'" \
+  <<-'ITEMS'
+	0 127 /nonexistent/program
+	0 0 bash -c 'exec /bin/true 1'
+	1 0 /bin/true 1
+	0 1 bash -c '/bin/true 2; exec /bin/false 3'
+	1 0 /bin/true 2
+	1 1 /bin/false 3
+ITEMS
+
+timehistory -j +1 | grep -q '"replaced_shell":true'
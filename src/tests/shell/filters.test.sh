@@ -0,0 +1,83 @@
+# Test to filter the programs added to the history.
+
+load_builtin
+
+TIMEHISTORY_IGNORE='true:*/echo'
+
+/bin/true 1
+/bin/echo 2 > /dev/null
+/bin/false 3 || :
+(exec -a true /bin/true 4)
+
+ASSERT_OUTPUT \
+  "timehistory -f '%n %C'" \
+  <<-ITEMS
+	1 /bin/true 1
+	2 /bin/false 3
+ITEMS
+
+# Only programs matching the patterns.
+
+timehistory -R
+TIMEHISTORY_IGNORE=
+TIMEHISTORY_ONLY='*/sleep:true'
+
+(exec -a true /bin/true 1)
+/bin/false 2 || :
+/bin/sleep 0.01
+
+ASSERT_OUTPUT \
+  "timehistory -f '%C'" \
+  <<-ITEMS
+	true 1
+	/bin/sleep 0.01
+ITEMS
+
+# Remove short entries.
+
+timehistory -R
+TIMEHISTORY_ONLY=
+TIMEHISTORY_MIN_DURATION=0.2
+
+/bin/true 1
+/bin/sleep 0.3
+
+ASSERT_OUTPUT \
+  "timehistory -f '%C'" \
+  <<-ITEMS
+	/bin/sleep 0.3
+ITEMS
+
+ASSERT_OUTPUT "echo \$TIMEHISTORY_MIN_DURATION" "0.2"
+
+# Changes in the filters don't affect the previous commands.
+
+timehistory -R
+TIMEHISTORY_MIN_DURATION=0
+
+/bin/true 1
+TIMEHISTORY_ONLY='*/sleep'
+
+ASSERT_OUTPUT "timehistory -f '%C'" "/bin/true 1"
+
+# Offsets skip the entries removed by the minimum duration.
+
+timehistory -R
+TIMEHISTORY_ONLY=
+TIMEHISTORY_MIN_DURATION=0.2
+
+/bin/sleep 0.3
+/bin/true 1
+
+ASSERT_OUTPUT "timehistory -f '%C' +1" "/bin/sleep 0.3"
+
+# Durations that don't fit in the history are rejected, and the previous
+# value is kept.
+
+ASSERT_OUTPUT \
+  "TIMEHISTORY_MIN_DURATION=1e20; echo \$TIMEHISTORY_MIN_DURATION; timehistory -f '%C' +1" \
+  <<-ITEMS
+	timehistory: invalid duration
+	0.2
+	/bin/sleep 0.3
+ITEMS
@@ -0,0 +1,48 @@
+# Test to track stopped and continued jobs.
+
+load_builtin
+
+set -m
+
+STATE_FILE=$(mktemp)
+
+# Wait until the last entry is in the state given in $1, and fail if it
+# is not reached in 10 seconds.
+#
+# Command substitution is not used because the builtin would run in a
+# subshell. $SECONDS is used instead of an external program, since it
+# would be the last entry in the history.
+wait_state() {
+  local deadline=$((SECONDS + 10))
+
+  while
+    timehistory -f '%(state)' +1 > "$STATE_FILE"
+    read -r < "$STATE_FILE"
+    [ "$REPLY" != "$1" ]
+  do
+    if [ "$SECONDS" -gt "$deadline" ]
+    then
+      printf 'timeout: expected state %s, found %s\n' "$1" "$REPLY"
+      exit 1
+    fi
+  done
+}
+
+/bin/sleep 30 &
+PID=$!
+wait_state running
+
+kill -STOP $PID
+wait_state stopped
+
+kill -CONT $PID
+wait_state continued
+
+kill -TERM $PID
+wait $PID || :
+
+ASSERT_OUTPUT \
+  "timehistory -f '%(state) %(stops) %(signal_name) %C' +1" \
+  "finished 1 SIGTERM /bin/sleep 30"
+
+rm -f "$STATE_FILE"
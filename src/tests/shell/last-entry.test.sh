@@ -0,0 +1,50 @@
+# Test for the variables with data from the last finished entry.
+
+load_builtin
+
+ASSERT_OUTPUT \
+  'echo "[$TIMEHISTORY_LAST_ELAPSED] [$TIMEHISTORY_LAST_MAXRSS] [$TIMEHISTORY_LAST_CPU]"' \
+  '[] [] []'
+
+/bin/sleep 0.2
+
+[[ $TIMEHISTORY_LAST_ELAPSED =~ ^[0-9]+\.[0-9]+$ ]]
+[[ $TIMEHISTORY_LAST_MAXRSS -gt 0 ]]
+[[ $TIMEHISTORY_LAST_CPU =~ ^[0-9.]+%$ ]]
+
+# Same values as the specifiers.
+ASSERT_OUTPUT \
+  'echo "$TIMEHISTORY_LAST_ELAPSED $TIMEHISTORY_LAST_MAXRSS $TIMEHISTORY_LAST_CPU"' \
+  "$(timehistory -f '%e %M %P' +1)"
+
+# Variables are read-only.
+{ TIMEHISTORY_LAST_ELAPSED=1; } 2> /dev/null
+[[ $TIMEHISTORY_LAST_ELAPSED != 1 ]]
+
+# A background job finished after the last command.
+/bin/sleep 60 &
+SLEEP_PID=$!
+
+# Wait until the entry for the job is in the shared buffer, so it is
+# added before the next command.
+LIMIT=$((SECONDS + 10))
+until [[ $(timehistory -f '%C' +1) == "/bin/sleep 60" ]]; do
+  [ "$SECONDS" -le "$LIMIT" ] || exit 1
+done
+
+/bin/true
+
+ASSERT_OUTPUT \
+  'echo "$TIMEHISTORY_LAST_ELAPSED"' \
+  "$(timehistory -f '%e' +1)"
+
+kill "$SLEEP_PID"
+wait "$SLEEP_PID" || :
+
+ASSERT_OUTPUT \
+  'echo "$TIMEHISTORY_LAST_ELAPSED $TIMEHISTORY_LAST_CPU"' \
+  "$(timehistory -f '%e %P' +2)"
+
+# Empty after removing the entry.
+timehistory -R > /dev/null
+ASSERT_OUTPUT 'echo "[$TIMEHISTORY_LAST_ELAPSED]"' '[]'
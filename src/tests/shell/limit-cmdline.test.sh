@@ -11,5 +11,34 @@ ASSERT_OUTPUT \
   "timehistory -f '%n %C'" \
   <<-ITEMS
 	1 /bin/true 1 2 3 4 5 6 7 8 9 10
-	2 /bin/true 1000 1001 1002 1003 1004 1005 1006 1007 1008 1009 1
+	2 /bin/true 1000 1001 1002 1003 1004 1005 1006 1007 1008 1009 1…(+363 bytes)
+ITEMS
+
+# Command lines bigger than 64 KiB.
+
+timehistory -R
+TIMEHISTORY_CMDLINE_LIMIT=200000
+
+/bin/true {100000..120000}
+
+ASSERT_OUTPUT \
+  "timehistory -f '%n %C' | wc -c" \
+  <<-ITEMS
+	140019
+ITEMS
+
+# Truncation in JSON output.
+
+timehistory -R
+TIMEHISTORY_CMDLINE_LIMIT=10
+
+/bin/true 1 2 3 4 5
+
+# Fields are extracted separately, so their order in the output is not
+# relevant.
+ASSERT_OUTPUT \
+  "timehistory -j +1 | grep -oE '\"(truncated|argc)\":[a-z0-9]+' | sort" \
+  <<-'ITEMS'
+	"argc":6
+	"truncated":true
 ITEMS
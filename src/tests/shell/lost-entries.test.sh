@@ -0,0 +1,29 @@
+# Test to detect processes that were reaped without updating the history.
+
+load_builtin
+
+timehistory -X posix_spawn/unhooked /bin/true 1
+timehistory -X posix_spawn /bin/true 2
+
+ASSERT_OUTPUT \
+  "timehistory -f '%C %(state) %Tt'" \
+  <<-ITEMS
+	/bin/true 1 lost lost
+	/bin/true 2 finished normal
+ITEMS
+
+timehistory -j | grep -q '"lost"'
+
+# Lost entries are visible to the prompt mode and to the variables for the
+# last finished entry.
+OUTPUT_FILE=$(mktemp)
+
+timehistory -p > "$OUTPUT_FILE"
+timehistory -X posix_spawn/unhooked /bin/true 3
+
+ASSERT_OUTPUT 'echo "$TIMEHISTORY_LAST_ELAPSED"' "lost"
+
+timehistory -p -f '%C %(state)' > "$OUTPUT_FILE"
+ASSERT_OUTPUT 'echo "$(< "$OUTPUT_FILE")"' "/bin/true 3 lost"
+
+rm -f "$OUTPUT_FILE"
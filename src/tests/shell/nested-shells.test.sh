@@ -0,0 +1,54 @@
+# Test to track commands in nested bash instances.
+
+load_builtin
+
+# Nested shells are not tracked by default.
+bash -c '/bin/true 0; :'
+
+TIMEHISTORY_NESTED=1
+
+bash -c '/bin/true 1; :'
+bash -c 'bash -c "/bin/true 2; :"; /bin/true 3'
+
+ASSERT_OUTPUT \
+  "timehistory -f '%(depth) %C'" \
+  <<-'ITEMS'
+	0 bash -c '/bin/true 0; :'
+	0 bash -c '/bin/true 1; :'
+	1 /bin/true 1
+	0 bash -c 'bash -c \"/bin/true 2; :\"; /bin/true 3'
+	1 bash -c '/bin/true 2; :'
+	2 /bin/true 2
+	1 /bin/true 3
+ITEMS
+
+# The builtin is not usable in nested shells.
+ASSERT_OUTPUT \
+  "bash -c 'timehistory' 2>&1 || echo \$?" \
+  <<-'ITEMS'
+	bash: line 1: timehistory: history is stored in the main shell
+	1
+ITEMS
+
+# Scripts for bash are tracked.
+SCRIPT_FILE=$(mktemp)
+printf '#!/usr/bin/env bash\n/bin/true 4\n' > "$SCRIPT_FILE"
+chmod +x "$SCRIPT_FILE"
+
+timehistory -R
+"$SCRIPT_FILE"
+
+ASSERT_OUTPUT \
+  "timehistory -f '%(depth) %C' +1" \
+  "1 /bin/true 4"
+
+rm -f "$SCRIPT_FILE"
+
+# Other programs don't inherit the shared buffer, even from a nested shell.
+SHOW_FDS='echo "${TIMEHISTORY_SHARED_FD:-none}"; ls -l /proc/$$/fd'
+
+OUTPUT=$(/bin/sh -c "$SHOW_FDS")
+[[ $OUTPUT == none* && $OUTPUT != *memfd:* ]]
+
+OUTPUT=$(SHOW_FDS="$SHOW_FDS" bash -c '/bin/sh -c "$SHOW_FDS"')
+[[ $OUTPUT == none* && $OUTPUT != *memfd:* ]]
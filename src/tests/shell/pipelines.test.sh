@@ -0,0 +1,76 @@
+# Test to group the members of a pipeline.
+
+load_builtin
+
+set -m
+
+/bin/echo abc | /bin/cat | /bin/wc -c > /dev/null
+/bin/true 1
+
+set +m
+
+/bin/true 2
+/bin/true 3
+
+ASSERT_OUTPUT \
+  "timehistory -g -f '%n %C'" \
+  <<-ITEMS
+	1 /bin/echo abc | /bin/cat | /bin/wc -c
+	4 /bin/true 1
+	5 /bin/true 2
+	6 /bin/true 3
+ITEMS
+
+ASSERT_OUTPUT \
+  "timehistory -g -f '%(members)' 2 | tr , '\n' | sort" \
+  <<-ITEMS
+	1
+	2
+	3
+ITEMS
+
+ASSERT_OUTPUT \
+  "timehistory -g -f '%n %C' 3" \
+  "1 /bin/echo abc | /bin/cat | /bin/wc -c"
+
+# Commands of a subshell or a nested shell are not members of a pipeline,
+# even if they are in the same process group.
+
+timehistory -R
+
+set -m
+
+( /bin/true a; /bin/true b; /bin/true c )
+
+TIMEHISTORY_NESTED=1
+bash -c '/bin/true d; /bin/true e'
+TIMEHISTORY_NESTED=0
+
+set +m
+
+ASSERT_OUTPUT \
+  "timehistory -g -f '%C'" \
+  <<-'ITEMS'
+	/bin/true a
+	/bin/true b
+	/bin/true c
+	bash -c '/bin/true d; /bin/true e'
+	/bin/true d
+	/bin/true e
+ITEMS
+
+# The last command of a subshell in a pipeline is executed by the process of
+# the first member, which is shown as '( … )'.
+
+timehistory -R
+
+set -m
+( /bin/true f; /bin/true g ) | /bin/cat
+set +m
+
+ASSERT_OUTPUT \
+  "timehistory -g -f '%C' | sort" \
+  <<-'ITEMS'
+	( … ) | /bin/cat
+	/bin/true f
+ITEMS
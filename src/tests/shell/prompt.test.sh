@@ -0,0 +1,65 @@
+# Test for the prompt mode.
+
+load_builtin
+
+# Command substitution is not used because the builtin would run in a
+# subshell, and the finished entries would not be removed in this shell.
+OUTPUT_FILE=$(mktemp)
+
+timehistory -p > "$OUTPUT_FILE"
+
+# -p can't be combined with other actions.
+ASSERT_OUTPUT \
+  "timehistory -p -R 2> /dev/null || echo status=\$?" \
+  "status=2"
+
+ASSERT_OUTPUT \
+  "timehistory -d 1 -p 2> /dev/null || echo status=\$?" \
+  "status=2"
+
+# Commands from this shell and from subshells.
+/bin/true 1
+( /bin/true 2 )
+( /bin/true 3 & wait )
+
+timehistory -p -f '%C' > "$OUTPUT_FILE"
+
+ASSERT_OUTPUT \
+  'echo "$(< "$OUTPUT_FILE")"' \
+  <<-ITEMS
+	/bin/true 1
+	/bin/true 2
+	/bin/true 3
+ITEMS
+
+# Nothing finished since the last prompt.
+timehistory -p > "$OUTPUT_FILE"
+ASSERT_OUTPUT 'echo "$(< "$OUTPUT_FILE")"' ''
+
+# Default format.
+/bin/true 4
+timehistory -p > "$OUTPUT_FILE"
+
+[[ $(< "$OUTPUT_FILE") =~ ^[0-9]+:\ /bin/true\ 4\ \([0-9.]+s,\ [0-9.]+%\ CPU,\ [0-9]+\ KiB\)$ ]]
+
+# Running commands are printed when they are finished.
+/bin/sleep 0.5 &
+timehistory -p > "$OUTPUT_FILE"
+ASSERT_OUTPUT 'echo "$(< "$OUTPUT_FILE")"' ''
+
+wait
+timehistory -p -f '%C' > "$OUTPUT_FILE"
+ASSERT_OUTPUT 'echo "$(< "$OUTPUT_FILE")"' '/bin/sleep 0.5'
+
+# Entries are kept if the output can't be written.
+/bin/true 5
+timehistory -p -f '%C' > /dev/full 2> /dev/null || :
+timehistory -p -f '%C' > "$OUTPUT_FILE"
+ASSERT_OUTPUT 'echo "$(< "$OUTPUT_FILE")"' '/bin/true 5'
+
+# Format assigned in the prefix of the command.
+/bin/true 6
+TIMEHISTORY_PROMPT_FORMAT='P %C' timehistory -p > "$OUTPUT_FILE"
+ASSERT_OUTPUT 'echo "$(< "$OUTPUT_FILE")"' 'P /bin/true 6'
+
+rm -f "$OUTPUT_FILE"
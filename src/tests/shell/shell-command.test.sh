@@ -0,0 +1,53 @@
+# Test to record the command line seen by the shell.
+
+load_builtin
+
+f() {
+  /bin/true "$@" > /dev/null
+}
+
+f 1 2
+N=3
+/bin/echo "$N" > /dev/null
+
+ASSERT_OUTPUT \
+  "timehistory -f '%(shell_cmd) | %C'" \
+  <<-'ITEMS'
+	/bin/true "$@" > /dev/null | /bin/true 1 2
+	/bin/echo "$N" > /dev/null | /bin/echo 3
+ITEMS
+
+# The history number is the value of HISTCMD when the command was executed.
+
+timehistory -R > /dev/null
+
+set -o history
+/bin/true a; FIRST=$HISTCMD
+:
+/bin/true b; SECOND=$HISTCMD
+set +o history
+
+ASSERT_OUTPUT \
+  "timehistory -f '%C %(histnum)'" \
+  <<-ITEMS
+	/bin/true a $FIRST
+	/bin/true b $SECOND
+ITEMS
+
+[ "$FIRST" -lt "$SECOND" ]
+
+# Job numbers are read from the job table of bash.
+
+timehistory -R > /dev/null
+
+/bin/sleep 0.1 &
+/bin/true c | /bin/cat
+wait
+
+ASSERT_OUTPUT \
+  "timehistory -f '%C %(job)'" \
+  <<-ITEMS
+	/bin/sleep 0.1 1
+	/bin/true c 2
+	/bin/cat 2
+ITEMS
@@ -0,0 +1,13 @@
+# Test to decode the status of processes terminated by a signal.
+
+load_builtin
+
+/bin/sh -c 'exit 3' || :
+/bin/sh -c 'kill -KILL $$' || :
+
+ASSERT_OUTPUT \
+  "timehistory -f '%Tt %(signal_name) %(shell_status)'" \
+  <<-ITEMS
+	normal  3
+	signalled SIGKILL 137
+ITEMS
@@ -0,0 +1,61 @@
+# Test to tag history entries.
+
+load_builtin
+
+/bin/true 1
+
+TIMEHISTORY_TAG=baseline
+/bin/true 2
+/bin/true 3
+
+TIMEHISTORY_TAG='after patch'
+/bin/true 4
+
+TIMEHISTORY_TAG=
+/bin/true 5
+
+# Tag the last entry, and entries selected by number, range or pattern.
+timehistory -t JIRA-12
+timehistory -t review -t JIRA-12 1 3-4
+
+ASSERT_OUTPUT \
+  "timehistory -f '%n %C [%(tags)]'" \
+  <<-ITEMS
+	1 /bin/true 1 [review,JIRA-12]
+	2 /bin/true 2 [baseline]
+	3 /bin/true 3 [baseline,review,JIRA-12]
+	4 /bin/true 4 [after patch,review,JIRA-12]
+	5 /bin/true 5 [JIRA-12]
+ITEMS
+
+# Filter by tags.
+ASSERT_OUTPUT \
+  "timehistory -T 'baseline:after*' -f '%n %(tags)'" \
+  <<-ITEMS
+	2 baseline
+	3 baseline,review,JIRA-12
+	4 after patch,review,JIRA-12
+ITEMS
+
+[[ $(timehistory -T baseline -j) == *'"tags":["baseline"]'* ]]
+
+# Fail if a number is not found.
+ASSERT_OUTPUT \
+  "timehistory -t x 100 2> /dev/null || echo status=\$?" \
+  "status=1"
+
+# Only one action can be used in every invocation.
+ASSERT_OUTPUT \
+  "timehistory -d 1 -t x 2> /dev/null || echo status=\$?" \
+  "status=2"
+
+ASSERT_OUTPUT \
+  "timehistory -t x -R 2> /dev/null || echo status=\$?" \
+  "status=2"
+
+ASSERT_OUTPUT \
+  "timehistory -f '%n [%(tags)]' 1; timehistory -f '%n [%(tags)]' 5" \
+  <<-ITEMS
+	1 [review,JIRA-12]
+	5 [JIRA-12]
+ITEMS
@@ -16,3 +16,50 @@ ASSERT_OUTPUT \
 	0,/bin/echo 2
 	1,/bin/false
 ITEMS
+
+# Reading the history in a subshell does not discard the events for the
+# main shell. The program is executed after the subshell is started, so
+# its event is still in the shared buffer.
+SYNC_DIR=$(mktemp -d)
+timehistory -R
+
+# Wait until a file exists, for up to 10 seconds.
+wait_file() {
+  local limit=$((SECONDS + 10))
+  while [ ! -e "$1" ]; do
+    if [ "$SECONDS" -gt "$limit" ]; then
+      printf 'timeout waiting for %s\n' "$1"
+      exit 1
+    fi
+  done
+}
+
+{
+  wait_file "$SYNC_DIR/reader"
+  /bin/true 1
+  : > "$SYNC_DIR/writer"
+} &
+
+OUTPUT=$(
+  : > "$SYNC_DIR/reader"
+  wait_file "$SYNC_DIR/writer"
+  timehistory -f '%C'
+)
+
+wait
+
+ASSERT_OUTPUT 'echo "$OUTPUT"' "/bin/true 1"
+ASSERT_OUTPUT "timehistory -f '%C'" "/bin/true 1"
+
+rm -r "$SYNC_DIR"
+
+# Events read by a subshell are not added again in the same subshell.
+timehistory -R > /dev/null
+
+ASSERT_OUTPUT \
+  "( /bin/true A; timehistory -f '%C'; echo ---; timehistory -f '%C' )" \
+  <<-ITEMS
+	/bin/true A
+	---
+	/bin/true A
+ITEMS
@@ -0,0 +1,155 @@
+//! Decode the status returned by `wait4`.
+
+use std::fmt;
+
+/// Decoded value of a `wstatus` from the `wait` family.
+#[cfg_attr(test, derive(PartialEq, Debug))]
+pub enum WaitStatus {
+    Exited(libc::c_int),
+
+    Signalled {
+        signal: libc::c_int,
+        core_dumped: bool,
+    },
+
+    Stopped(libc::c_int),
+
+    Continued,
+}
+
+impl WaitStatus {
+    pub fn new(status: libc::c_int) -> WaitStatus {
+        if libc::WIFEXITED(status) {
+            WaitStatus::Exited(libc::WEXITSTATUS(status))
+        } else if libc::WIFSIGNALED(status) {
+            WaitStatus::Signalled {
+                signal: libc::WTERMSIG(status),
+                core_dumped: libc::WCOREDUMP(status),
+            }
+        } else if libc::WIFCONTINUED(status) {
+            WaitStatus::Continued
+        } else {
+            WaitStatus::Stopped(libc::WSTOPSIG(status))
+        }
+    }
+
+    /// Termination type, as printed by the `%Tt` specifier.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            WaitStatus::Exited(_) => "normal",
+            WaitStatus::Signalled { .. } => "signalled",
+            WaitStatus::Stopped(_) => "stopped",
+            WaitStatus::Continued => "continued",
+        }
+    }
+
+    /// Signal that terminated or stopped the process.
+    pub fn signal(&self) -> Option<libc::c_int> {
+        match self {
+            WaitStatus::Signalled { signal, .. } | WaitStatus::Stopped(signal) => Some(*signal),
+            _ => None,
+        }
+    }
+
+    /// Returns `true` if the process produced a core dump.
+    pub fn core_dumped(&self) -> bool {
+        matches!(
+            self,
+            WaitStatus::Signalled {
+                core_dumped: true,
+                ..
+            }
+        )
+    }
+
+    /// Status as reported by bash in the `$?` variable.
+    pub fn shell_status(&self) -> Option<libc::c_int> {
+        match self {
+            WaitStatus::Exited(code) => Some(*code),
+            WaitStatus::Signalled { signal, .. } | WaitStatus::Stopped(signal) => {
+                Some(128 + *signal)
+            }
+            WaitStatus::Continued => None,
+        }
+    }
+}
+
+/// Display the name of a signal, like `SIGKILL`.
+pub struct SignalName(pub libc::c_int);
+
+impl fmt::Display for SignalName {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self.0 {
+            libc::SIGHUP => "SIGHUP",
+            libc::SIGINT => "SIGINT",
+            libc::SIGQUIT => "SIGQUIT",
+            libc::SIGILL => "SIGILL",
+            libc::SIGTRAP => "SIGTRAP",
+            libc::SIGABRT => "SIGABRT",
+            libc::SIGBUS => "SIGBUS",
+            libc::SIGFPE => "SIGFPE",
+            libc::SIGKILL => "SIGKILL",
+            libc::SIGUSR1 => "SIGUSR1",
+            libc::SIGSEGV => "SIGSEGV",
+            libc::SIGUSR2 => "SIGUSR2",
+            libc::SIGPIPE => "SIGPIPE",
+            libc::SIGALRM => "SIGALRM",
+            libc::SIGTERM => "SIGTERM",
+            libc::SIGSTKFLT => "SIGSTKFLT",
+            libc::SIGCHLD => "SIGCHLD",
+            libc::SIGCONT => "SIGCONT",
+            libc::SIGSTOP => "SIGSTOP",
+            libc::SIGTSTP => "SIGTSTP",
+            libc::SIGTTIN => "SIGTTIN",
+            libc::SIGTTOU => "SIGTTOU",
+            libc::SIGURG => "SIGURG",
+            libc::SIGXCPU => "SIGXCPU",
+            libc::SIGXFSZ => "SIGXFSZ",
+            libc::SIGVTALRM => "SIGVTALRM",
+            libc::SIGPROF => "SIGPROF",
+            libc::SIGWINCH => "SIGWINCH",
+            libc::SIGIO => "SIGIO",
+            libc::SIGPWR => "SIGPWR",
+            libc::SIGSYS => "SIGSYS",
+
+            n => {
+                let (min, max) = (libc::SIGRTMIN(), libc::SIGRTMAX());
+                return if (min..=max).contains(&n) {
+                    write!(fmt, "SIGRTMIN+{}", n - min)
+                } else {
+                    write!(fmt, "SIG{}", n)
+                };
+            }
+        };
+
+        fmt.write_str(name)
+    }
+}
+
+#[test]
+fn decode_status() {
+    assert_eq!(WaitStatus::new(0), WaitStatus::Exited(0));
+    assert_eq!(WaitStatus::new(3 << 8), WaitStatus::Exited(3));
+    assert_eq!(WaitStatus::new(0xffff), WaitStatus::Continued);
+
+    let killed = WaitStatus::new(libc::SIGKILL);
+    assert_eq!(killed.kind(), "signalled");
+    assert_eq!(killed.shell_status(), Some(137));
+    assert!(!killed.core_dumped());
+
+    let segv = WaitStatus::new(libc::SIGSEGV | 0x80);
+    assert!(segv.core_dumped());
+    assert_eq!(segv.signal(), Some(libc::SIGSEGV));
+
+    let stopped = WaitStatus::new((libc::SIGTSTP << 8) | 0x7f);
+    assert_eq!(stopped, WaitStatus::Stopped(libc::SIGTSTP));
+    assert_eq!(stopped.shell_status(), Some(148));
+}
+
+#[test]
+fn signal_names() {
+    assert_eq!(SignalName(libc::SIGKILL).to_string(), "SIGKILL");
+    assert_eq!(SignalName(libc::SIGTSTP).to_string(), "SIGTSTP");
+    assert_eq!(SignalName(libc::SIGRTMIN() + 2).to_string(), "SIGRTMIN+2");
+    assert_eq!(SignalName(200).to_string(), "SIG200");
+}